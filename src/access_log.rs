@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::body::{boxed, Body};
+use axum::extract::{ConnectInfo, MatchedPath};
+use axum::http::{header, HeaderMap, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::Rng;
+use time::macros::format_description;
+use time::OffsetDateTime;
+use tracing::info;
+
+use crate::conf::{AccessLogFormat, Conf};
+use crate::metrics::{count_request_body, CountedBody};
+use crate::reload::SharedLiveConf;
+use crate::request_id::RequestId;
+
+/// Route label for requests that didn't match any route.
+pub const UNMATCHED_ROUTE: &str = "unmatched";
+
+const MAX_SUBJECT_LEN: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Sampling {
+    sample_rate: f64,
+    slow_only: Option<Duration>,
+}
+
+/// Access log settings resolved from config, keyed by route template.
+#[derive(Debug, Clone)]
+pub struct AccessLogPolicy {
+    pub enabled: bool,
+    pub format: AccessLogFormat,
+    default: Sampling,
+    routes: HashMap<String, Sampling>,
+}
+
+impl AccessLogPolicy {
+    pub fn from_conf(conf: &Conf) -> Self {
+        let default = Sampling {
+            sample_rate: conf.access_log.sample_rate,
+            slow_only: conf.access_log.slow_only_ms.map(Duration::from_millis),
+        };
+
+        AccessLogPolicy {
+            enabled: conf.access_log.enabled,
+            format: conf.access_log.format,
+            default,
+            routes: conf
+                .routes
+                .iter()
+                .map(|(route, route_conf)| {
+                    let overrides = &route_conf.access_log;
+                    let sampling = Sampling {
+                        sample_rate: overrides.sample_rate.unwrap_or(default.sample_rate),
+                        slow_only: overrides
+                            .slow_only_ms
+                            .map(Duration::from_millis)
+                            .or(default.slow_only),
+                    };
+
+                    (route.clone(), sampling)
+                })
+                .filter(|(_, sampling)| *sampling != default)
+                .collect(),
+        }
+    }
+
+    pub fn should_log<R: Rng>(&self, route: &str, latency: Duration, rng: &mut R) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        let sampling = self.routes.get(route).unwrap_or(&self.default);
+
+        if sampling
+            .slow_only
+            .is_some_and(|threshold| latency < threshold)
+        {
+            return false;
+        }
+
+        sampling.sample_rate >= 1.0 || rng.gen_bool(sampling.sample_rate.max(0.0))
+    }
+}
+
+/// One access log record.
+#[derive(Debug)]
+struct Entry {
+    method: String,
+    route: String,
+    path: String,
+    version: String,
+    status: u16,
+    latency: Duration,
+    bytes_in: Option<u64>,
+    bytes_out: Option<u64>,
+    client_ip: Option<String>,
+    user_agent: Option<String>,
+    referer: Option<String>,
+    request_id: Option<String>,
+    user: Option<String>,
+}
+
+impl Entry {
+    fn emit(&self, format: AccessLogFormat) {
+        match format {
+            AccessLogFormat::Json => info!(
+                target: "access_log",
+                method = %self.method,
+                route = %self.route,
+                path = %self.path,
+                status = self.status,
+                latency_ms = self.latency.as_secs_f64() * 1000.0,
+                bytes_in = self.bytes_in,
+                bytes_out = self.bytes_out,
+                client_ip = self.client_ip.as_deref(),
+                user_agent = self.user_agent.as_deref(),
+                request_id = self.request_id.as_deref(),
+                user = self.user.as_deref(),
+                "request completed"
+            ),
+            AccessLogFormat::Combined => info!(
+                target: "access_log",
+                request_id = self.request_id.as_deref(),
+                "{}",
+                self.combined(OffsetDateTime::now_utc())
+            ),
+        }
+    }
+
+    /// `%h %l %u [%t] "%r" %>s %b "%{Referer}i" "%{User-agent}i"`
+    fn combined(&self, now: OffsetDateTime) -> String {
+        let time = now
+            .format(format_description!(
+                "[day]/[month repr:short]/[year]:[hour]:[minute]:[second] +0000"
+            ))
+            .unwrap_or_default();
+
+        format!(
+            "{} - {} [{}] \"{} {} {}\" {} {} \"{}\" \"{}\"",
+            self.client_ip.as_deref().unwrap_or("-"),
+            escape(self.user.as_deref().unwrap_or("-")),
+            time,
+            self.method,
+            self.path,
+            self.version,
+            self.status,
+            self.bytes_out
+                .map_or_else(|| "-".to_string(), |bytes| bytes.to_string()),
+            escape(self.referer.as_deref().unwrap_or("-")),
+            escape(self.user_agent.as_deref().unwrap_or("-")),
+        )
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+/// The `sub` claim of a JWT bearer token, as claimed by the token; the signature is
+/// checked by the backend, not here.
+fn bearer_subject(headers: &HeaderMap) -> Option<String> {
+    let token = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?;
+
+    let payload = URL_SAFE_NO_PAD.decode(token.split('.').nth(1)?).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&payload).ok()?;
+
+    claims
+        .get("sub")?
+        .as_str()
+        .filter(|sub| sub.len() <= MAX_SUBJECT_LEN && sub.chars().all(|c| c.is_ascii_graphic()))
+        .map(str::to_string)
+}
+
+/// Logs every request that passes the sampling policy, including fallbacks and
+/// responses produced by other layers. The entry is written once the response body is
+/// sent or dropped, with the bytes actually read and sent.
+pub async fn access_log(req: Request<Body>, next: Next<Body>, live: SharedLiveConf) -> Response {
+    let start = Instant::now();
+    let bytes_in = Arc::new(AtomicU64::new(0));
+
+    let headers = req.headers();
+    let mut entry = Entry {
+        method: req.method().to_string(),
+        route: req
+            .extensions()
+            .get::<MatchedPath>()
+            .map_or(UNMATCHED_ROUTE, |path| path.as_str())
+            .to_string(),
+        path: req
+            .uri()
+            .path_and_query()
+            .map_or_else(|| req.uri().path().to_string(), |pq| pq.to_string()),
+        version: format!("{:?}", req.version()),
+        status: 0,
+        latency: Duration::ZERO,
+        bytes_in: None,
+        bytes_out: None,
+        client_ip: req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0.ip().to_string()),
+        user_agent: header_str(headers, header::USER_AGENT),
+        referer: header_str(headers, header::REFERER),
+        request_id: req.extensions().get::<RequestId>().map(|id| id.to_string()),
+        user: bearer_subject(headers),
+    };
+
+    let req = req.map(|body| {
+        let bytes_in = bytes_in.clone();
+        count_request_body(body, move |bytes| bytes_in.store(bytes, Ordering::Relaxed))
+    });
+    let resp = next.run(req).await;
+
+    entry.latency = start.elapsed();
+
+    let live = live.snapshot();
+
+    if !live
+        .access_log
+        .should_log(&entry.route, entry.latency, &mut rand::thread_rng())
+    {
+        return resp;
+    }
+
+    entry.path = live
+        .redaction
+        .for_route(&entry.route)
+        .uri(&entry.route, &entry.path);
+    entry.status = resp.status().as_u16();
+
+    let format = live.access_log.format;
+
+    resp.map(|body| {
+        boxed(CountedBody::new(body, move |bytes| {
+            entry.bytes_in = Some(bytes_in.load(Ordering::Relaxed));
+            entry.bytes_out = Some(bytes);
+            entry.emit(format);
+        }))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::{AccessLogRouteConf, RouteConf};
+    use axum::http::HeaderValue;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use time::macros::datetime;
+
+    fn policy(configure: impl FnOnce(&mut Conf)) -> AccessLogPolicy {
+        let mut conf = Conf::default();
+        configure(&mut conf);
+        AccessLogPolicy::from_conf(&conf)
+    }
+
+    fn logged(policy: &AccessLogPolicy, route: &str, latency_ms: u64) -> usize {
+        let mut rng = StdRng::seed_from_u64(7);
+
+        (0..1000)
+            .filter(|_| policy.should_log(route, Duration::from_millis(latency_ms), &mut rng))
+            .count()
+    }
+
+    #[test]
+    fn test_logs_everything_by_default() {
+        let policy = policy(|_| {});
+
+        assert_eq!(logged(&policy, "/api/v1/statuses", 1), 1000);
+        assert_eq!(logged(&policy, UNMATCHED_ROUTE, 1), 1000);
+    }
+
+    #[test]
+    fn test_disabled_logs_nothing() {
+        let policy = policy(|conf| conf.access_log.enabled = false);
+
+        assert_eq!(logged(&policy, "/api/v1/statuses", 1), 0);
+    }
+
+    #[test]
+    fn test_route_sample_rate_overrides_default() {
+        let policy = policy(|conf| {
+            conf.routes.insert(
+                "/api/v1/statuses".to_string(),
+                RouteConf {
+                    access_log: AccessLogRouteConf {
+                        sample_rate: Some(0.1),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            );
+        });
+
+        let sampled = logged(&policy, "/api/v1/statuses", 1);
+
+        assert!((50..150).contains(&sampled), "{sampled}");
+        assert_eq!(logged(&policy, "/api/v1/portfolios", 1), 1000);
+    }
+
+    #[test]
+    fn test_slow_only_skips_fast_requests() {
+        let policy = policy(|conf| conf.access_log.slow_only_ms = Some(500));
+
+        assert_eq!(logged(&policy, "/api/v1/statuses", 499), 0);
+        assert_eq!(logged(&policy, "/api/v1/statuses", 500), 1000);
+    }
+
+    #[test]
+    fn test_route_inherits_slow_only_when_overriding_rate() {
+        let policy = policy(|conf| {
+            conf.access_log.slow_only_ms = Some(500);
+            conf.routes.insert(
+                "/api/v1/plans".to_string(),
+                RouteConf {
+                    access_log: AccessLogRouteConf {
+                        sample_rate: Some(0.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            );
+        });
+
+        assert_eq!(logged(&policy, "/api/v1/plans", 1000), 0);
+        assert_eq!(logged(&policy, "/api/v1/portfolios", 100), 0);
+        assert_eq!(logged(&policy, "/api/v1/portfolios", 1000), 1000);
+    }
+
+    #[test]
+    fn test_combined_format() {
+        let entry = Entry {
+            method: "GET".to_string(),
+            route: "/api/v1/portfolios/:pid".to_string(),
+            path: "/api/v1/portfolios/1?include=x".to_string(),
+            version: "HTTP/1.1".to_string(),
+            status: 200,
+            latency: Duration::from_millis(12),
+            bytes_in: None,
+            bytes_out: Some(512),
+            client_ip: Some("10.0.0.1".to_string()),
+            user_agent: Some("curl/8.0 \"quoted\"".to_string()),
+            referer: None,
+            request_id: Some("req-1".to_string()),
+            user: Some("user-\"1".to_string()),
+        };
+
+        assert_eq!(
+            entry.combined(datetime!(2024-03-05 07:08:09 UTC)),
+            r#"10.0.0.1 - user-\"1 [05/Mar/2024:07:08:09 +0000] "GET /api/v1/portfolios/1?include=x HTTP/1.1" 200 512 "-" "curl/8.0 \"quoted\"""#
+        );
+    }
+
+    #[test]
+    fn test_bearer_subject() {
+        let payload = URL_SAFE_NO_PAD.encode(r#"{"sub":"user-1","exp":1}"#);
+        let mut headers = HeaderMap::new();
+
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer e30.{payload}.sig")).unwrap(),
+        );
+        assert_eq!(bearer_subject(&headers), Some("user-1".to_string()));
+
+        let payload = URL_SAFE_NO_PAD.encode(r#"{"sub":"user 1\n10.0.0.9 - admin"}"#);
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer e30.{payload}.sig")).unwrap(),
+        );
+        assert_eq!(bearer_subject(&headers), None);
+
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer opaque"),
+        );
+        assert_eq!(bearer_subject(&headers), None);
+
+        headers.remove(header::AUTHORIZATION);
+        assert_eq!(bearer_subject(&headers), None);
+    }
+}
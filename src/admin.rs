@@ -0,0 +1,362 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use axum::extract::ConnectInfo;
+use axum::headers::authorization::{Authorization, Bearer};
+use axum::headers::HeaderMapExt;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderValue, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json, TypedHeader};
+use ipnet::IpNet;
+use serde::Deserialize;
+use tracing::error;
+
+use crate::conf::{AdminListenerConf, Conf, ConfError};
+use crate::handlers::{create_error_response, JSON_API_TYPE};
+use crate::log_control::{log_control, LogFilterState};
+use crate::responses::log_filters::{LogFilterAttributes, LogFilters, LogFiltersData};
+
+/// Resolved bearer token for the `/admin` endpoints, `None` disables them.
+#[derive(Clone, Default)]
+pub struct AdminToken(Option<Arc<str>>);
+
+impl AdminToken {
+    pub fn new(token: &str) -> Self {
+        AdminToken(Some(Arc::from(token)))
+    }
+
+    pub fn from_conf(conf: &Conf) -> Self {
+        match conf.admin.token.as_ref().map(|secret| secret.resolve()) {
+            Some(Ok(token)) if !token.is_empty() => AdminToken::new(&token),
+            Some(Ok(_)) => {
+                error!("admin token is empty, admin endpoints are disabled");
+                AdminToken::default()
+            }
+            Some(Err(e)) => {
+                error!(error = %e, "can't resolve admin token, admin endpoints are disabled");
+                AdminToken::default()
+            }
+            None => AdminToken::default(),
+        }
+    }
+
+    /// Returns the error response for requests that may not use the admin endpoints.
+    fn reject(&self, authorization: Option<&Authorization<Bearer>>) -> Option<Response> {
+        let expected = match &self.0 {
+            Some(expected) => expected,
+            None => return Some(not_found_response()),
+        };
+
+        match authorization {
+            Some(auth) if constant_time_eq(auth.token().as_bytes(), expected.as_bytes()) => None,
+            _ => Some(
+                create_error_response(
+                    StatusCode::UNAUTHORIZED,
+                    "401",
+                    "Unauthorized",
+                    "A valid admin bearer token is required.",
+                )
+                .into_response(),
+            ),
+        }
+    }
+}
+
+/// Who may use the admin listener, checked on every path it serves.
+#[derive(Clone, Default)]
+pub struct AdminAccess {
+    token: Option<Arc<str>>,
+    allowed_networks: Arc<[IpNet]>,
+}
+
+impl AdminAccess {
+    pub fn from_conf(conf: &AdminListenerConf) -> Result<Self, ConfError> {
+        let token = match &conf.token {
+            Some(secret) => match secret.resolve()? {
+                token if token.is_empty() => {
+                    return Err(ConfError {
+                        message: "admin.listener.token is empty".to_string(),
+                    })
+                }
+                token => Some(Arc::from(token)),
+            },
+            None => None,
+        };
+
+        Ok(AdminAccess {
+            token,
+            allowed_networks: conf.allowed_networks()?.into(),
+        })
+    }
+
+    /// Returns the error response for clients that may not use the admin listener,
+    /// a client without a known address is rejected when an allowlist is set.
+    fn reject(
+        &self,
+        client: Option<SocketAddr>,
+        authorization: Option<&Authorization<Bearer>>,
+    ) -> Option<Response> {
+        if !self.allowed_networks.is_empty() {
+            let allowed = client.is_some_and(|client| {
+                self.allowed_networks
+                    .iter()
+                    .any(|network| network.contains(&client.ip()))
+            });
+
+            if !allowed {
+                return Some(
+                    create_error_response(
+                        StatusCode::FORBIDDEN,
+                        "403",
+                        "Forbidden",
+                        "The client address is not allowed on the admin listener.",
+                    )
+                    .into_response(),
+                );
+            }
+        }
+
+        let expected = self.token.as_ref()?;
+
+        match authorization {
+            Some(auth) if constant_time_eq(auth.token().as_bytes(), expected.as_bytes()) => None,
+            _ => Some(
+                create_error_response(
+                    StatusCode::UNAUTHORIZED,
+                    "401",
+                    "Unauthorized",
+                    "A valid admin listener bearer token is required.",
+                )
+                .into_response(),
+            ),
+        }
+    }
+}
+
+pub async fn check_admin_access<B>(
+    req: Request<B>,
+    next: Next<B>,
+    access: AdminAccess,
+) -> Response {
+    let client = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0);
+    let authorization = req.headers().typed_get::<Authorization<Bearer>>();
+
+    match access.reject(client, authorization.as_ref()) {
+        Some(resp) => resp,
+        None => next.run(req).await,
+    }
+}
+
+#[derive(Deserialize)]
+struct LogFilterUpdate {
+    data: LogFilterUpdateData,
+}
+
+#[derive(Deserialize)]
+struct LogFilterUpdateData {
+    attributes: LogFilterUpdateAttributes,
+}
+
+#[derive(Deserialize)]
+struct LogFilterUpdateAttributes {
+    /// New `EnvFilter` directives, `null` resets to the base filter.
+    filter: Option<String>,
+    revert_after_minutes: Option<u64>,
+}
+
+pub async fn get_log_filter(
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    Extension(token): Extension<AdminToken>,
+) -> Response {
+    if let Some(resp) = token.reject(authorization.as_ref().map(|h| &h.0)) {
+        return resp;
+    }
+
+    match log_control() {
+        Some(control) => log_filters_response(control.state()),
+        None => log_control_unavailable(),
+    }
+}
+
+pub async fn update_log_filter(
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    Extension(token): Extension<AdminToken>,
+    body: Bytes,
+) -> Response {
+    if let Some(resp) = token.reject(authorization.as_ref().map(|h| &h.0)) {
+        return resp;
+    }
+
+    let update: LogFilterUpdate = match serde_json::from_slice(&body) {
+        Ok(update) => update,
+        Err(e) => {
+            return create_error_response(
+                StatusCode::BAD_REQUEST,
+                "400",
+                "Bad request",
+                &format!("Invalid log filter document, {e}"),
+            )
+            .into_response()
+        }
+    };
+
+    let control = match log_control() {
+        Some(control) => control,
+        None => return log_control_unavailable(),
+    };
+
+    let attributes = update.data.attributes;
+
+    match attributes.filter {
+        Some(filter) => {
+            if let Err(e) = control.set_filter(&filter, attributes.revert_after_minutes) {
+                return create_error_response(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "422",
+                    "Invalid log filter",
+                    &e,
+                )
+                .into_response();
+            }
+        }
+        None => control.reset(),
+    }
+
+    log_filters_response(control.state())
+}
+
+fn log_filters_response(state: LogFilterState) -> Response {
+    let log_filters = LogFilters {
+        data: LogFiltersData {
+            id: "current".to_string(),
+            r#type: "log-filters".to_string(),
+            attributes: LogFilterAttributes {
+                filter: state.current,
+                base_filter: state.base,
+            },
+        },
+    };
+
+    let mut resp = (StatusCode::OK, Json(log_filters)).into_response();
+    resp.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(JSON_API_TYPE));
+    resp
+}
+
+fn log_control_unavailable() -> Response {
+    create_error_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "503",
+        "Service unavailable",
+        "Log control is not initialized.",
+    )
+    .into_response()
+}
+
+fn not_found_response() -> Response {
+    create_error_response(
+        StatusCode::NOT_FOUND,
+        "404",
+        "Not found",
+        "The requested resource could not be found.",
+    )
+    .into_response()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::Secret;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret1"));
+    }
+
+    #[test]
+    fn test_check_disabled_without_token() {
+        let resp = AdminToken::default()
+            .reject(None)
+            .expect("should be disabled");
+
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_check_requires_matching_token() {
+        let token = AdminToken::new("s3cr3t");
+
+        let resp = token.reject(None).expect("should require a token");
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let wrong = Authorization::bearer("nope").expect("valid bearer");
+        assert!(token.reject(Some(&wrong)).is_some());
+
+        let right = Authorization::bearer("s3cr3t").expect("valid bearer");
+        assert!(token.reject(Some(&right)).is_none());
+    }
+
+    fn access(token: Option<&str>, allowed_ips: &[&str]) -> AdminAccess {
+        AdminAccess::from_conf(&AdminListenerConf {
+            bind_address: "127.0.0.1".parse().unwrap(),
+            port: 9090,
+            token: token.map(|token| Secret::Inline(token.to_string())),
+            allowed_ips: allowed_ips.iter().map(|ip| ip.to_string()).collect(),
+        })
+        .expect("valid admin listener conf")
+    }
+
+    fn client(ip: &str) -> Option<SocketAddr> {
+        Some(SocketAddr::new(ip.parse().unwrap(), 40000))
+    }
+
+    #[test]
+    fn test_admin_access_open_without_token_or_allowlist() {
+        assert!(access(None, &[]).reject(None, None).is_none());
+    }
+
+    #[test]
+    fn test_admin_access_allowlist() {
+        let access = access(None, &["10.0.0.0/8", "192.168.1.7"]);
+
+        assert!(access.reject(client("10.1.2.3"), None).is_none());
+        assert!(access.reject(client("192.168.1.7"), None).is_none());
+
+        let resp = access
+            .reject(client("192.168.1.8"), None)
+            .expect("should reject unlisted client");
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+
+        assert!(access.reject(None, None).is_some());
+    }
+
+    #[test]
+    fn test_admin_access_token() {
+        let access = access(Some("s3cr3t"), &[]);
+
+        let resp = access.reject(None, None).expect("should require a token");
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+
+        let wrong = Authorization::bearer("nope").expect("valid bearer");
+        assert!(access.reject(None, Some(&wrong)).is_some());
+
+        let right = Authorization::bearer("s3cr3t").expect("valid bearer");
+        assert!(access.reject(None, Some(&right)).is_none());
+    }
+}
@@ -1,9 +1,19 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::{env, fmt};
 
-use serde::Deserialize;
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::Value;
+
+/// Environment variables with this prefix override config keys, `__` separates nested keys:
+/// `HTTP2_LISTEN_PORT=9000`, `HTTP2_NATS__HOST=nats:4222`.
+pub const ENV_PREFIX: &str = "HTTP2_";
+const ENV_SEPARATOR: &str = "__";
+const DEFAULT_PATH: &str = "./config.json";
 
 #[derive(Debug, Clone)]
 pub struct ConfError {
@@ -16,26 +26,730 @@ impl fmt::Display for ConfError {
     }
 }
 
-#[derive(Debug, Deserialize)]
+impl std::error::Error for ConfError {}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Conf {
     pub listen_port: u16,
     pub enable_cors: bool,
     pub nats: NatsConf,
     pub allowed_origins: Vec<String>,
     pub is_debug: bool,
+    /// Per-route settings keyed by route template, e.g. `/api/v1/portfolios/:pid`.
+    #[serde(default)]
+    pub routes: HashMap<String, RouteConf>,
+    #[serde(default)]
+    pub shutdown: ShutdownConf,
+    #[serde(default)]
+    pub log: LogConf,
+    #[serde(default)]
+    pub admin: AdminConf,
+    #[serde(default)]
+    pub access_log: AccessLogConf,
+    #[serde(default)]
+    pub redaction: RedactionConf,
+    #[serde(default)]
+    pub metrics: MetricsConf,
+    #[serde(default)]
+    pub cors: CorsConf,
+    #[serde(default)]
+    pub security_headers: SecurityHeadersConf,
+    #[serde(default)]
+    pub json_api: JsonApiConf,
+    #[serde(default)]
+    pub request_body: RequestBodyConf,
+    #[serde(default)]
+    pub imports: ImportsConf,
+    #[serde(default)]
+    pub jobs: JobsConf,
+}
+
+impl Default for Conf {
+    fn default() -> Self {
+        Conf {
+            listen_port: 8000,
+            enable_cors: false,
+            nats: NatsConf::default(),
+            allowed_origins: vec![],
+            is_debug: false,
+            routes: HashMap::new(),
+            shutdown: ShutdownConf::default(),
+            log: LogConf::default(),
+            admin: AdminConf::default(),
+            access_log: AccessLogConf::default(),
+            redaction: RedactionConf::default(),
+            metrics: MetricsConf::default(),
+            cors: CorsConf::default(),
+            security_headers: SecurityHeadersConf::default(),
+            json_api: JsonApiConf::default(),
+            request_body: RequestBodyConf::default(),
+            imports: ImportsConf::default(),
+            jobs: JobsConf::default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConf {
+    /// `EnvFilter` directives such as `info,http2::handlers=debug`. `RUST_LOG` takes
+    /// precedence, without either the level follows `is_debug`.
+    pub filter: Option<String>,
+    /// Filters changed at runtime return to the base filter after this many minutes,
+    /// 0 keeps them until changed again.
+    pub revert_after_minutes: u64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConf {
+    /// Bearer token for the `/admin` endpoints, they are disabled when unset.
+    pub token: Option<Secret>,
+    /// Serves `/metrics`, `/readyz` and `/admin` on a separate listener instead of
+    /// the public port.
+    pub listener: Option<AdminListenerConf>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AdminListenerConf {
+    #[serde(default = "default_admin_bind_address")]
+    pub bind_address: IpAddr,
+    pub port: u16,
+    /// Bearer token required on every path of the admin listener.
+    #[serde(default)]
+    pub token: Option<Secret>,
+    /// Client IPs or CIDR ranges allowed on the admin listener, any client when empty.
+    #[serde(default)]
+    pub allowed_ips: Vec<String>,
+}
+
+fn default_admin_bind_address() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+}
+
+impl AdminListenerConf {
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.bind_address, self.port)
+    }
+
+    pub fn allowed_networks(&self) -> Result<Vec<IpNet>, ConfError> {
+        self.allowed_ips
+            .iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                entry
+                    .parse::<IpNet>()
+                    .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from))
+                    .map_err(|_| ConfError {
+                        message: format!(
+                            "admin.listener.allowed_ips[{i}] {entry:?} is not an IP address or CIDR range"
+                        ),
+                    })
+            })
+            .collect()
+    }
+}
+
+/// CORS response settings, used when `enable_cors` is set. Origins come from
+/// `allowed_origins`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConf {
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+    /// Response headers readable by browser scripts.
+    pub exposed_headers: Vec<String>,
+    /// Lets browsers send cookies and HTTP auth with cross-origin requests.
+    pub allow_credentials: bool,
+    /// How long browsers may cache a preflight response.
+    pub max_age_secs: Option<u64>,
+}
+
+impl Default for CorsConf {
+    fn default() -> Self {
+        CorsConf {
+            allowed_methods: ["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"]
+                .map(String::from)
+                .to_vec(),
+            allowed_headers: ["content-type", "authorization", "x-request-id"]
+                .map(String::from)
+                .to_vec(),
+            exposed_headers: ["x-request-id", "etag", "location"]
+                .map(String::from)
+                .to_vec(),
+            allow_credentials: false,
+            max_age_secs: None,
+        }
+    }
+}
+
+impl CorsConf {
+    fn validate(&self) -> Result<(), ConfError> {
+        for (i, method) in self.allowed_methods.iter().enumerate() {
+            if axum::http::Method::from_bytes(method.as_bytes()).is_err() {
+                return Err(ConfError {
+                    message: format!("cors.allowed_methods[{i}] {method:?} is not a valid method"),
+                });
+            }
+        }
+
+        for (key, names) in [
+            ("cors.allowed_headers", &self.allowed_headers),
+            ("cors.exposed_headers", &self.exposed_headers),
+        ] {
+            for (i, name) in names.iter().enumerate() {
+                if name == "*" || axum::http::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                    return Err(ConfError {
+                        message: format!("{key}[{i}] {name:?} is not a valid header name"),
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConf {
+    /// Serves the Prometheus text format on `/metrics`.
+    pub prometheus: bool,
+    /// Pushes the same metrics to an OpenTelemetry collector.
+    pub otlp: Option<OtlpMetricsConf>,
+}
+
+impl Default for MetricsConf {
+    fn default() -> Self {
+        MetricsConf {
+            prometheus: true,
+            otlp: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OtlpMetricsConf {
+    /// gRPC endpoint of the collector, e.g. `http://otel-collector:4317`.
+    pub endpoint: String,
+    #[serde(default = "default_otlp_interval_ms")]
+    pub interval_ms: u64,
+    #[serde(default = "default_otlp_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_otlp_interval_ms() -> u64 {
+    60000
 }
 
-#[derive(Debug, Deserialize, Clone)]
+fn default_otlp_timeout_ms() -> u64 {
+    10000
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogFormat {
+    /// Structured fields on the `access_log` target.
+    #[default]
+    Json,
+    /// Apache combined log format as the event message.
+    Combined,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessLogConf {
+    pub enabled: bool,
+    pub format: AccessLogFormat,
+    /// Fraction of requests logged, from 0.0 to 1.0.
+    pub sample_rate: f64,
+    /// Only requests taking at least this long are logged.
+    pub slow_only_ms: Option<u64>,
+}
+
+impl Default for AccessLogConf {
+    fn default() -> Self {
+        AccessLogConf {
+            enabled: true,
+            format: AccessLogFormat::Json,
+            sample_rate: 1.0,
+            slow_only_ms: None,
+        }
+    }
+}
+
+/// Per-route overrides of the `access_log` sampling settings.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccessLogRouteConf {
+    pub sample_rate: Option<f64>,
+    pub slow_only_ms: Option<u64>,
+}
+
+/// Headers set on every response, replacing any value set by handlers. An empty
+/// value leaves the header out.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityHeadersConf {
+    pub enabled: bool,
+    pub strict_transport_security: String,
+    pub content_type_options: String,
+    pub referrer_policy: String,
+    pub cross_origin_resource_policy: String,
+    pub content_security_policy: String,
+}
+
+impl Default for SecurityHeadersConf {
+    fn default() -> Self {
+        SecurityHeadersConf {
+            enabled: true,
+            strict_transport_security: "max-age=31536000; includeSubDomains".to_string(),
+            content_type_options: "nosniff".to_string(),
+            referrer_policy: "no-referrer".to_string(),
+            cross_origin_resource_policy: "same-origin".to_string(),
+            content_security_policy: "default-src 'none'; frame-ancestors 'none'".to_string(),
+        }
+    }
+}
+
+/// Per-route overrides of the `security_headers` values.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecurityHeadersRouteConf {
+    pub strict_transport_security: Option<String>,
+    pub content_type_options: Option<String>,
+    pub referrer_policy: Option<String>,
+    pub cross_origin_resource_policy: Option<String>,
+    pub content_security_policy: Option<String>,
+}
+
+/// JSON:API media type negotiation on `/api/` routes.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct JsonApiConf {
+    /// Rejects a `Content-Type` on POST and PATCH with 415 and an `Accept` with 406
+    /// when they break the JSON:API media type rules.
+    pub strict_media_type: bool,
+    /// Extension URIs clients may request with the `ext` media type parameter.
+    pub extensions: Vec<String>,
+}
+
+/// Request body limits and staging of large uploads.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RequestBodyConf {
+    /// Largest body accepted in bytes, `routes.<template>.body_limit_bytes` overrides it.
+    pub limit_bytes: usize,
+    /// JetStream object store bucket the bodies of `object_store` upload routes are
+    /// staged in. The bucket must exist, the backend deletes objects it has processed.
+    pub object_store_bucket: Option<String>,
+}
+
+impl Default for RequestBodyConf {
+    fn default() -> Self {
+        RequestBodyConf {
+            limit_bytes: 250 * 1024,
+            object_store_bucket: None,
+        }
+    }
+}
+
+/// Portfolio file imports, the files are staged in `request_body.object_store_bucket`.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImportsConf {
+    /// Subject import jobs are published on, a JetStream stream must capture it.
+    pub subject: String,
+    /// Jobs carry the user's bearer token, a warning is logged when the stream keeps
+    /// them longer than this.
+    pub stream_max_age_secs: u64,
+}
+
+impl Default for ImportsConf {
+    fn default() -> Self {
+        ImportsConf {
+            subject: "portfolio.imports".to_string(),
+            stream_max_age_secs: 3600,
+        }
+    }
+}
+
+/// Jobs of `async` routes, set up at startup.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct JobsConf {
+    /// Subject the requests of `async` routes are published on, a JetStream stream
+    /// must capture it.
+    pub subject: String,
+    /// JetStream key-value bucket job states are kept in. The bucket must exist, its
+    /// max age is how long a finished job can be fetched.
+    pub bucket: String,
+    /// Stream capturing `results_subject`, read through the durable consumer `http2-jobs`.
+    pub results_stream: String,
+    /// Subject backends publish job results on.
+    pub results_subject: String,
+    /// Jobs carry the user's bearer token, a warning is logged when the stream
+    /// capturing `subject` keeps them longer than this.
+    pub stream_max_age_secs: u64,
+}
+
+impl Default for JobsConf {
+    fn default() -> Self {
+        JobsConf {
+            subject: "http.jobs".to_string(),
+            bucket: "http-jobs".to_string(),
+            results_stream: "HTTP_JOB_RESULTS".to_string(),
+            results_subject: "http.jobs.results".to_string(),
+            stream_max_age_secs: 3600,
+        }
+    }
+}
+
+/// How a route's requests are answered.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteMode {
+    /// The response waits for the backend's reply.
+    #[default]
+    Sync,
+    /// POST, PUT, PATCH and DELETE are queued as jobs and answered with a 202, the
+    /// client polls the job for the result.
+    Async,
+}
+
+/// How a route's request body reaches the backend.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BodyUpload {
+    /// In the NATS request, limited by the server's `max_payload`.
+    #[default]
+    Inline,
+    /// Streamed into `request_body.object_store_bucket`, the request names the object.
+    ObjectStore,
+}
+
+/// Request data masked before it reaches logs and spans, on every route.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedactionConf {
+    /// Header names, case-insensitive.
+    pub headers: Vec<String>,
+    pub query_params: Vec<String>,
+    /// JSON pointers into request bodies, e.g. `/data/attributes/password`.
+    pub json_pointers: Vec<String>,
+}
+
+impl Default for RedactionConf {
+    fn default() -> Self {
+        RedactionConf {
+            headers: [
+                "authorization",
+                "cookie",
+                "proxy-authorization",
+                "x-api-key",
+            ]
+            .map(String::from)
+            .to_vec(),
+            query_params: ["access_token", "refresh_token", "token", "password", "code"]
+                .map(String::from)
+                .to_vec(),
+            json_pointers: [
+                "/data/attributes/password",
+                "/data/attributes/access_token",
+                "/data/attributes/refresh_token",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+/// Per-route additions to `redaction`.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct RedactRouteConf {
+    pub headers: Vec<String>,
+    pub query_params: Vec<String>,
+    pub json_pointers: Vec<String>,
+    /// Names of path params in the route template, e.g. `id` for `/confirmation-codes/:id`.
+    pub path_params: Vec<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShutdownConf {
+    /// Time between reporting not ready and closing the listener, lets load balancers
+    /// notice the pod is going away before connections are refused.
+    pub pre_stop_delay_ms: u64,
+    /// Upper bound for in-flight requests to finish once the listener is closed.
+    pub drain_timeout_ms: u64,
+}
+
+impl Default for ShutdownConf {
+    fn default() -> Self {
+        ShutdownConf {
+            pre_stop_delay_ms: 0,
+            drain_timeout_ms: 30_000,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RouteConf {
+    /// Disabled routes answer 404, can be toggled by a SIGHUP reload.
+    pub enabled: bool,
+    /// Overrides `nats.request_timeout_ms` for this route.
+    pub timeout_ms: Option<u64>,
+    pub access_log: AccessLogRouteConf,
+    pub redact: RedactRouteConf,
+    pub security_headers: SecurityHeadersRouteConf,
+    /// JSON Schema file POST, PUT and PATCH bodies are validated against, loaded at startup.
+    pub request_schema: Option<String>,
+    /// Overrides `request_body.limit_bytes` for this route.
+    pub body_limit_bytes: Option<usize>,
+    pub upload: BodyUpload,
+    pub mode: RouteMode,
+}
+
+impl Default for RouteConf {
+    fn default() -> Self {
+        RouteConf {
+            enabled: true,
+            timeout_ms: None,
+            access_log: AccessLogRouteConf::default(),
+            redact: RedactRouteConf::default(),
+            security_headers: SecurityHeadersRouteConf::default(),
+            request_schema: None,
+            body_limit_bytes: None,
+            upload: BodyUpload::Inline,
+            mode: RouteMode::Sync,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct NatsConf {
+    #[serde(default)]
     pub host: String,
+    /// Additional cluster members used for failover, tried together with `host`.
+    #[serde(default)]
+    pub servers: Vec<String>,
+    #[serde(default)]
+    pub auth: NatsAuthConf,
+    #[serde(default)]
+    pub tls: Option<NatsTlsConf>,
+    /// Start serving HTTP before the first NATS connection is established.
+    /// Until it is, readiness reports failure and proxied routes return 503. The
+    /// process exits once `connect_retry.max_attempts` is used up.
+    #[serde(default)]
+    pub lazy_connect: bool,
+    #[serde(default)]
+    pub connect_retry: ConnectRetryConf,
+    /// Default deadline for backend requests, see `RouteConf::timeout_ms`.
+    #[serde(default = "default_request_timeout_ms")]
+    pub request_timeout_ms: u64,
+    #[serde(default = "default_ping_interval_ms")]
+    pub ping_interval_ms: u64,
+}
+
+fn default_request_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_ping_interval_ms() -> u64 {
+    10_000
+}
+
+impl Default for NatsConf {
+    fn default() -> Self {
+        NatsConf {
+            host: "localhost:4222".to_string(),
+            servers: vec![],
+            auth: NatsAuthConf::default(),
+            tls: None,
+            lazy_connect: false,
+            connect_retry: ConnectRetryConf::default(),
+            request_timeout_ms: default_request_timeout_ms(),
+            ping_interval_ms: default_ping_interval_ms(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectRetryConf {
+    /// Number of connection attempts before giving up, 0 means retry forever.
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub multiplier: f64,
+    /// Fraction of the delay that is randomized, from 0.0 to 1.0.
+    pub jitter: f64,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct NatsAuthConf {
+    pub user: Option<Secret>,
+    pub password: Option<Secret>,
+    pub token: Option<Secret>,
+    pub nkey_seed: Option<Secret>,
+    /// Path to a `.creds` file holding the user JWT and NKey seed.
+    pub credentials_file: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct NatsTlsConf {
+    /// Reject servers that do not offer TLS.
+    pub required: bool,
+    pub ca_file: Option<String>,
+    /// Client certificate and key for mTLS, both must be set together.
+    pub cert_file: Option<String>,
+    pub key_file: Option<String>,
+}
+
+/// A secret value given inline, by environment variable name or by file path:
+/// `"value"`, `{"env": "NATS_PASSWORD"}` or `{"file": "/run/secrets/nats"}`.
+#[derive(Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Secret {
+    Env { env: String },
+    File { file: String },
+    Inline(String),
+}
+
+impl Secret {
+    pub fn resolve(&self) -> Result<String, ConfError> {
+        match self {
+            Secret::Inline(value) => Ok(value.clone()),
+            Secret::Env { env } => env::var(env).map_err(|e| ConfError {
+                message: format!("can't read secret from env var {env}, {e}"),
+            }),
+            Secret::File { file } => std::fs::read_to_string(file)
+                .map(|value| value.trim_end_matches(['\r', '\n']).to_string())
+                .map_err(|e| ConfError {
+                    message: format!("can't read secret from file {file}, {e}"),
+                }),
+        }
+    }
+}
+
+/// Inline secrets are masked so the effective config can be printed safely.
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serde_json::Map::new();
+
+        match self {
+            Secret::Inline(_) => return serializer.serialize_str("***"),
+            Secret::Env { env } => map.insert("env".to_string(), Value::from(env.as_str())),
+            Secret::File { file } => map.insert("file".to_string(), Value::from(file.as_str())),
+        };
+
+        map.serialize(serializer)
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Secret::Inline(_) => write!(f, "Inline(***)"),
+            Secret::Env { env } => write!(f, "Env({env})"),
+            Secret::File { file } => write!(f, "File({file})"),
+        }
+    }
+}
+
+impl NatsConf {
+    pub fn server_urls(&self) -> Vec<String> {
+        let mut urls = Vec::with_capacity(self.servers.len() + 1);
+
+        if !self.host.is_empty() {
+            urls.push(self.host.clone());
+        }
+
+        urls.extend(self.servers.iter().cloned());
+
+        urls
+    }
+
+    pub fn validate(&self) -> Result<(), ConfError> {
+        if self.server_urls().is_empty() {
+            return Err(ConfError {
+                message: "nats.host or nats.servers must be set".to_string(),
+            });
+        }
+
+        let auth = &self.auth;
+        let methods = [
+            auth.user.is_some() || auth.password.is_some(),
+            auth.token.is_some(),
+            auth.nkey_seed.is_some(),
+            auth.credentials_file.is_some(),
+        ];
+
+        if methods.iter().filter(|set| **set).count() > 1 {
+            return Err(ConfError {
+                message: "nats.auth accepts only one of user/password, token, nkey_seed or credentials_file".to_string(),
+            });
+        }
+
+        if auth.user.is_some() != auth.password.is_some() {
+            return Err(ConfError {
+                message: "nats.auth.user and nats.auth.password must be set together".to_string(),
+            });
+        }
+
+        if let Some(tls) = &self.tls {
+            if tls.cert_file.is_some() != tls.key_file.is_some() {
+                return Err(ConfError {
+                    message: "nats.tls.cert_file and nats.tls.key_file must be set together"
+                        .to_string(),
+                });
+            }
+        }
+
+        if self.request_timeout_ms == 0 {
+            return Err(ConfError {
+                message: "nats.request_timeout_ms must be greater than 0".to_string(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ConnectRetryConf {
+    fn default() -> Self {
+        ConnectRetryConf {
+            max_attempts: 10,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
 }
 
 impl Conf {
-    pub fn new() -> Result<Conf, ConfError> {
-        let path = env::var("CFG_PATH").unwrap_or_else(|_| "./config.json".to_string());
+    /// Builds the effective config from the JSON file, then `HTTP2_*` env vars, then CLI flags.
+    pub fn load<I>(args: &CliArgs, vars: I) -> Result<Conf, ConfError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let path = args
+            .config_path
+            .clone()
+            .or_else(|| env::var("CFG_PATH").ok())
+            .unwrap_or_else(|| DEFAULT_PATH.to_string());
 
-        let file = File::open(path).map_err(|e| ConfError {
-            message: format!("can't open config.json file, {e}"),
+        let file = File::open(&path).map_err(|e| ConfError {
+            message: format!("can't open config file {path}, {e}"),
         })?;
 
         let mut buf_reader = BufReader::new(file);
@@ -45,15 +759,507 @@ impl Conf {
         buf_reader
             .read_to_string(&mut contents)
             .map_err(|e| ConfError {
-                message: format!("can't read config.json file, {e}"),
+                message: format!("can't read config file {path}, {e}"),
             })?;
 
-        let conf: Conf = serde_json::from_str(contents.as_str()).map_err(|e| ConfError {
-            message: format!("can't parse config.json file, {e}"),
+        let mut value: Value = serde_json::from_str(contents.as_str()).map_err(|e| ConfError {
+            message: format!("can't parse config file {path}, {e}"),
         })?;
 
+        let mut overrides = apply_env_overrides(&mut value, vars)?;
+
+        for (key, raw) in &args.overrides {
+            let keys: Vec<String> = key.split('.').map(str::to_string).collect();
+
+            set_path(&mut value, &keys, parse_override(raw)).map_err(|message| ConfError {
+                message: format!("invalid command-line override {key}, {message}"),
+            })?;
+
+            overrides.push((keys, raw.clone()));
+        }
+
+        Conf::from_value(value, &overrides)
+    }
+
+    /// `overrides` lists the key paths and raw values already set on `value`. When a field
+    /// rejects the JSON reading of an override, the raw text is retried as a string, so
+    /// `HTTP2_NATS__AUTH__PASSWORD=123456` stays a password instead of becoming a number.
+    pub fn from_value(
+        mut value: Value,
+        overrides: &[(Vec<String>, String)],
+    ) -> Result<Conf, ConfError> {
+        let conf: Conf = loop {
+            match serde_path_to_error::deserialize(value.clone()) {
+                Ok(conf) => break conf,
+                Err(e) => {
+                    let path = e.path().to_string();
+
+                    let retry = overrides.iter().rev().find(|(keys, _)| {
+                        keys.join(".") == path
+                            && get_path(&value, keys).is_some_and(|current| !current.is_string())
+                    });
+
+                    match retry {
+                        Some((keys, raw)) => {
+                            set_path(&mut value, keys, Value::from(raw.as_str())).map_err(
+                                |message| ConfError {
+                                    message: format!("invalid override {path}, {message}"),
+                                },
+                            )?;
+                        }
+                        None => {
+                            return Err(ConfError {
+                                message: format!("invalid config at {path}, {}", e.inner()),
+                            })
+                        }
+                    }
+                }
+            }
+        };
+
+        conf.validate()?;
+
         Ok(conf)
     }
+
+    pub fn validate(&self) -> Result<(), ConfError> {
+        if self.listen_port == 0 {
+            return Err(ConfError {
+                message: "listen_port must be between 1 and 65535".to_string(),
+            });
+        }
+
+        for (i, origin) in self.allowed_origins.iter().enumerate() {
+            validate_origin(origin).map_err(|reason| ConfError {
+                message: format!("allowed_origins[{i}] {origin:?} is not a valid origin, {reason}"),
+            })?;
+        }
+
+        self.cors.validate()?;
+
+        if let Some(filter) = &self.log.filter {
+            tracing_subscriber::EnvFilter::try_new(filter).map_err(|e| ConfError {
+                message: format!("log.filter {filter:?} is not a valid filter, {e}"),
+            })?;
+        }
+
+        if let Some(listener) = &self.admin.listener {
+            if listener.port == 0 || listener.port == self.listen_port {
+                return Err(ConfError {
+                    message: "admin.listener.port must be between 1 and 65535 and differ from listen_port"
+                        .to_string(),
+                });
+            }
+
+            listener.allowed_networks()?;
+        }
+
+        if let Some(otlp) = &self.metrics.otlp {
+            let uri: axum::http::Uri = otlp.endpoint.parse().map_err(|_| ConfError {
+                message: format!(
+                    "metrics.otlp.endpoint {:?} is not a valid URI",
+                    otlp.endpoint
+                ),
+            })?;
+
+            if !matches!(uri.scheme_str(), Some("http") | Some("https")) || uri.host().is_none() {
+                return Err(ConfError {
+                    message: format!(
+                        "metrics.otlp.endpoint {:?} must be an http or https URL",
+                        otlp.endpoint
+                    ),
+                });
+            }
+
+            if otlp.interval_ms == 0 || otlp.timeout_ms == 0 {
+                return Err(ConfError {
+                    message: "metrics.otlp.interval_ms and timeout_ms must be positive".to_string(),
+                });
+            }
+        }
+
+        validate_header_values(
+            "security_headers",
+            [
+                &self.security_headers.strict_transport_security,
+                &self.security_headers.content_type_options,
+                &self.security_headers.referrer_policy,
+                &self.security_headers.cross_origin_resource_policy,
+                &self.security_headers.content_security_policy,
+            ],
+        )?;
+
+        for (i, extension) in self.json_api.extensions.iter().enumerate() {
+            let uri: Option<axum::http::Uri> = extension.parse().ok();
+
+            if uri.is_none_or(|uri| uri.scheme().is_none()) {
+                return Err(ConfError {
+                    message: format!(
+                        "json_api.extensions[{i}] {extension:?} is not an absolute URI"
+                    ),
+                });
+            }
+        }
+
+        if self.request_body.limit_bytes == 0 {
+            return Err(ConfError {
+                message: "request_body.limit_bytes must be positive".to_string(),
+            });
+        }
+
+        let jobs = &self.jobs;
+        if [
+            &jobs.subject,
+            &jobs.bucket,
+            &jobs.results_stream,
+            &jobs.results_subject,
+        ]
+        .iter()
+        .any(|value| value.is_empty())
+            || jobs.subject == jobs.results_subject
+        {
+            return Err(ConfError {
+                message: "jobs settings must be set and jobs.results_subject must differ from jobs.subject"
+                    .to_string(),
+            });
+        }
+
+        validate_sample_rate("access_log.sample_rate", self.access_log.sample_rate)?;
+        validate_json_pointers("redaction.json_pointers", &self.redaction.json_pointers)?;
+
+        if self.imports.stream_max_age_secs == 0 {
+            return Err(ConfError {
+                message: "imports.stream_max_age_secs must be greater than 0".to_string(),
+            });
+        }
+
+        if self.jobs.stream_max_age_secs == 0 {
+            return Err(ConfError {
+                message: "jobs.stream_max_age_secs must be greater than 0".to_string(),
+            });
+        }
+
+        let templates = crate::routes::route_templates();
+
+        for (route, route_conf) in &self.routes {
+            if !templates.contains(route) {
+                return Err(ConfError {
+                    message: format!("routes key {route:?} is not a route of the API"),
+                });
+            }
+
+            validate_json_pointers(
+                &format!("routes.{route}.redact.json_pointers"),
+                &route_conf.redact.json_pointers,
+            )?;
+
+            if route_conf.timeout_ms == Some(0) {
+                return Err(ConfError {
+                    message: format!("routes.{route}.timeout_ms must be greater than 0"),
+                });
+            }
+
+            if route_conf.body_limit_bytes == Some(0) {
+                return Err(ConfError {
+                    message: format!("routes.{route}.body_limit_bytes must be positive"),
+                });
+            }
+
+            if route_conf.upload == BodyUpload::ObjectStore {
+                if self.request_body.object_store_bucket.is_none() {
+                    return Err(ConfError {
+                        message: format!(
+                            "routes.{route}.upload is object_store but request_body.object_store_bucket is not set"
+                        ),
+                    });
+                }
+
+                if route_conf.request_schema.is_some() {
+                    return Err(ConfError {
+                        message: format!(
+                            "routes.{route}.request_schema can't check an object_store upload"
+                        ),
+                    });
+                }
+            }
+
+            if let Some(sample_rate) = route_conf.access_log.sample_rate {
+                validate_sample_rate(
+                    &format!("routes.{route}.access_log.sample_rate"),
+                    sample_rate,
+                )?;
+            }
+
+            let overrides = &route_conf.security_headers;
+            validate_header_values(
+                &format!("routes.{route}.security_headers"),
+                [
+                    &overrides.strict_transport_security,
+                    &overrides.content_type_options,
+                    &overrides.referrer_policy,
+                    &overrides.cross_origin_resource_policy,
+                    &overrides.content_security_policy,
+                ]
+                .into_iter()
+                .flatten(),
+            )?;
+        }
+
+        self.nats.validate()
+    }
+
+    /// Effective config as pretty JSON with inline secrets masked.
+    pub fn to_redacted_json(&self) -> Result<String, ConfError> {
+        serde_json::to_string_pretty(self).map_err(|e| ConfError {
+            message: format!("can't serialize config, {e}"),
+        })
+    }
+}
+
+/// Command-line flags, applied on top of the config file and env vars.
+#[derive(Debug, Clone, Default)]
+pub struct CliArgs {
+    pub config_path: Option<String>,
+    /// Dotted key paths and raw values, e.g. `("nats.host", "localhost:4222")`.
+    pub overrides: Vec<(String, String)>,
+    pub print_config: bool,
+}
+
+impl CliArgs {
+    pub fn parse<I>(args: I) -> Result<CliArgs, ConfError>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut cli = CliArgs::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => {
+                    (flag.to_string(), Some(value.to_string()))
+                }
+                _ => (arg.clone(), None),
+            };
+
+            let mut value = |name: &str| {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| ConfError {
+                        message: format!("{name} requires a value"),
+                    })
+            };
+
+            match flag.as_str() {
+                "--config" => cli.config_path = Some(value("--config")?),
+                "--listen-port" => cli
+                    .overrides
+                    .push(("listen_port".to_string(), value("--listen-port")?)),
+                "--nats-host" => cli
+                    .overrides
+                    .push(("nats.host".to_string(), value("--nats-host")?)),
+                "--debug" => cli
+                    .overrides
+                    .push(("is_debug".to_string(), "true".to_string())),
+                "--set" => {
+                    let assignment = value("--set")?;
+                    let (key, raw) = assignment.split_once('=').ok_or_else(|| ConfError {
+                        message: format!("--set expects key=value, got {assignment:?}"),
+                    })?;
+
+                    cli.overrides.push((key.to_string(), raw.to_string()));
+                }
+                "--print-config" => cli.print_config = true,
+                _ => {
+                    return Err(ConfError {
+                        message: format!("unknown command-line argument {arg:?}"),
+                    })
+                }
+            }
+        }
+
+        Ok(cli)
+    }
+}
+
+/// `HTTP2_*` vars whose first key is not a top-level config key are skipped, orchestrators
+/// inject unrelated ones such as Kubernetes service links. Unknown keys under a known
+/// section are likely typos and fail. Returns the key paths and raw values that were applied.
+fn apply_env_overrides<I>(
+    value: &mut Value,
+    vars: I,
+) -> Result<Vec<(Vec<String>, String)>, ConfError>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let known = top_level_keys()?;
+
+    let mut vars: Vec<(String, String)> = vars
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+        .collect();
+    vars.sort();
+
+    let mut overrides = Vec::new();
+
+    for (name, raw) in vars {
+        let keys = env_keys(&name);
+
+        if known.get(keys[0].as_str()).is_none() {
+            continue;
+        }
+
+        set_path(value, &keys, parse_override(&raw)).map_err(|message| ConfError {
+            message: format!("invalid env override {name}, {message}"),
+        })?;
+
+        overrides.push((keys, raw));
+    }
+
+    Ok(overrides)
+}
+
+/// Names of the `HTTP2_*` vars `Conf::load` skips, so they can be reported once
+/// logging is set up.
+pub fn ignored_env_vars<I>(vars: I) -> Vec<String>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let Ok(known) = top_level_keys() else {
+        return vec![];
+    };
+
+    let mut names: Vec<String> = vars
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|name| name.starts_with(ENV_PREFIX))
+        .filter(|name| known.get(env_keys(name)[0].as_str()).is_none())
+        .collect();
+    names.sort();
+
+    names
+}
+
+fn top_level_keys() -> Result<Value, ConfError> {
+    serde_json::to_value(Conf::default()).map_err(|e| ConfError {
+        message: format!("can't serialize default config, {e}"),
+    })
+}
+
+fn env_keys(name: &str) -> Vec<String> {
+    name[ENV_PREFIX.len()..]
+        .split(ENV_SEPARATOR)
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// Values are read as JSON when possible so numbers, booleans and arrays work,
+/// anything else is taken as a plain string. String fields get the raw text back
+/// in `Conf::from_value`.
+fn parse_override(raw: &str) -> Value {
+    serde_json::from_str(raw).unwrap_or_else(|_| Value::from(raw))
+}
+
+fn get_path<'a>(value: &'a Value, keys: &[String]) -> Option<&'a Value> {
+    keys.iter()
+        .try_fold(value, |current, key| current.get(key.as_str()))
+}
+
+fn set_path(value: &mut Value, keys: &[String], new_value: Value) -> Result<(), String> {
+    let mut current = value;
+
+    for (i, key) in keys.iter().enumerate() {
+        if key.is_empty() {
+            return Err("empty key segment".to_string());
+        }
+
+        if current.is_null() {
+            *current = Value::Object(serde_json::Map::new());
+        }
+
+        let object = current
+            .as_object_mut()
+            .ok_or_else(|| format!("{} is not an object", keys[..i].join(".")))?;
+
+        if i == keys.len() - 1 {
+            object.insert(key.clone(), new_value);
+
+            return Ok(());
+        }
+
+        current = object.entry(key.clone()).or_insert(Value::Null);
+    }
+
+    Err("empty key".to_string())
+}
+
+fn validate_sample_rate(key: &str, sample_rate: f64) -> Result<(), ConfError> {
+    if (0.0..=1.0).contains(&sample_rate) {
+        Ok(())
+    } else {
+        Err(ConfError {
+            message: format!("{key} must be between 0.0 and 1.0, got {sample_rate}"),
+        })
+    }
+}
+
+fn validate_json_pointers(key: &str, pointers: &[String]) -> Result<(), ConfError> {
+    match pointers.iter().find(|pointer| !pointer.starts_with('/')) {
+        Some(pointer) => Err(ConfError {
+            message: format!("{key} entry {pointer:?} must be a JSON pointer starting with /"),
+        }),
+        None => Ok(()),
+    }
+}
+
+fn validate_header_values<'a>(
+    key: &str,
+    values: impl IntoIterator<Item = &'a String>,
+) -> Result<(), ConfError> {
+    match values
+        .into_iter()
+        .find(|value| axum::http::HeaderValue::from_str(value).is_err())
+    {
+        Some(value) => Err(ConfError {
+            message: format!("{key} value {value:?} is not a valid header value"),
+        }),
+        None => Ok(()),
+    }
+}
+
+fn validate_origin(origin: &str) -> Result<(), &'static str> {
+    // `*.` may only start the host, it is validated as a plain label.
+    let (origin, wildcard) = match origin.split_once("://*.") {
+        Some((scheme, domain)) => (format!("{scheme}://wildcard.{domain}"), true),
+        None => (origin.to_string(), false),
+    };
+
+    if origin.contains('*') {
+        return Err("only a leading *. subdomain wildcard is supported");
+    }
+
+    let uri: axum::http::Uri = origin.parse().map_err(|_| "can't parse as URI")?;
+
+    match uri.scheme_str() {
+        Some("http") | Some("https") => {}
+        _ => return Err("scheme must be http or https"),
+    }
+
+    if uri.host().is_none_or(str::is_empty) {
+        return Err("host is missing");
+    }
+
+    let domain = uri.host().and_then(|host| host.strip_prefix("wildcard."));
+
+    if wildcard && domain.is_none_or(|domain| domain.is_empty() || domain.ends_with('.')) {
+        return Err("wildcard must be followed by a domain");
+    }
+
+    if uri.query().is_some() || !matches!(uri.path(), "" | "/") || origin.ends_with('/') {
+        return Err("origin must not contain a path, query or trailing slash");
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -68,9 +1274,23 @@ mod tests {
             enable_cors: true,
             nats: NatsConf {
                 host: "localhost:4222".to_string(),
+                ..Default::default()
             },
             allowed_origins: vec!["http://localhost:3000".to_string()],
             is_debug: true,
+            routes: HashMap::new(),
+            shutdown: ShutdownConf::default(),
+            log: LogConf::default(),
+            admin: AdminConf::default(),
+            access_log: AccessLogConf::default(),
+            redaction: RedactionConf::default(),
+            metrics: MetricsConf::default(),
+            cors: CorsConf::default(),
+            security_headers: SecurityHeadersConf::default(),
+            json_api: JsonApiConf::default(),
+            request_body: RequestBodyConf::default(),
+            imports: ImportsConf::default(),
+            jobs: JobsConf::default(),
         };
 
         assert_eq!(test_conf.listen_port, 8080);
@@ -97,6 +1317,111 @@ mod tests {
         let conf = conf.unwrap();
         assert_eq!(conf.listen_port, 8080);
         assert_eq!(conf.nats.host, "localhost:4222");
+        assert!(!conf.nats.lazy_connect);
+        assert_eq!(conf.nats.connect_retry.max_attempts, 10);
+        assert_eq!(conf.nats.request_timeout_ms, 10_000);
+        assert!(conf.routes.is_empty());
+        assert_eq!(conf.shutdown.drain_timeout_ms, 30_000);
+    }
+
+    #[test]
+    fn test_route_timeout_deserialization() {
+        let config_json = r#"{
+            "listen_port": 8080,
+            "enable_cors": false,
+            "nats": {"host": "localhost:4222", "request_timeout_ms": 2000},
+            "allowed_origins": [],
+            "is_debug": false,
+            "routes": {
+                "/api/v1/securities/:sid/annual-income-statements": {"timeout_ms": 30000}
+            }
+        }"#;
+
+        let conf: Conf = serde_json::from_str(config_json).expect("should parse conf");
+        assert_eq!(conf.nats.request_timeout_ms, 2000);
+        assert_eq!(
+            conf.routes["/api/v1/securities/:sid/annual-income-statements"].timeout_ms,
+            Some(30000)
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_zero_route_timeout() {
+        let mut conf = Conf::default();
+        conf.routes.insert(
+            "/api/v1/plans".to_string(),
+            RouteConf {
+                timeout_ms: Some(0),
+                ..Default::default()
+            },
+        );
+
+        let err = conf.validate().expect_err("zero timeout should fail");
+        assert!(
+            err.message.contains("routes./api/v1/plans.timeout_ms"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_unlimited_import_retention() {
+        let mut conf = Conf::default();
+        conf.imports.stream_max_age_secs = 0;
+
+        let err = conf.validate().expect_err("zero max age should fail");
+        assert!(
+            err.message.contains("imports.stream_max_age_secs"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_unlimited_job_retention() {
+        let mut conf = Conf::default();
+        conf.jobs.stream_max_age_secs = 0;
+
+        let err = conf.validate().expect_err("zero max age should fail");
+        assert!(
+            err.message.contains("jobs.stream_max_age_secs"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_unknown_route_key() {
+        let mut conf = Conf::default();
+        conf.routes
+            .insert("/api/v1/plan".to_string(), RouteConf::default());
+
+        let err = conf.validate().expect_err("unknown route should fail");
+        assert!(err.message.contains("/api/v1/plan"), "{}", err.message);
+
+        let mut conf = Conf::default();
+        conf.routes
+            .insert("/api/v1/users/:uid".to_string(), RouteConf::default());
+
+        conf.validate().expect("known route should pass");
+    }
+
+    #[test]
+    fn test_connect_retry_deserialization() {
+        let config_json = r#"{
+            "host": "localhost:4222",
+            "lazy_connect": true,
+            "connect_retry": {
+                "max_attempts": 0,
+                "initial_backoff_ms": 100
+            }
+        }"#;
+
+        let nats: NatsConf = serde_json::from_str(config_json).expect("should parse nats conf");
+        assert!(nats.lazy_connect);
+        assert_eq!(nats.connect_retry.max_attempts, 0);
+        assert_eq!(nats.connect_retry.initial_backoff_ms, 100);
+        assert_eq!(nats.connect_retry.max_backoff_ms, 30_000);
     }
 
     #[test]
@@ -139,9 +1464,627 @@ mod tests {
     fn test_nats_conf_clone() {
         let nats_conf = NatsConf {
             host: "test.host:4222".to_string(),
+            lazy_connect: true,
+            ..Default::default()
         };
         let cloned = nats_conf.clone();
 
         assert_eq!(nats_conf.host, cloned.host);
+        assert_eq!(nats_conf.lazy_connect, cloned.lazy_connect);
+    }
+
+    fn nats_conf(config_json: &str) -> NatsConf {
+        serde_json::from_str(config_json).expect("should parse nats conf")
+    }
+
+    #[test]
+    fn test_secret_sources() {
+        let auth: NatsAuthConf = serde_json::from_str(
+            r#"{
+                "user": "gateway",
+                "password": {"env": "HTTP2_TEST_NATS_PASSWORD"},
+                "token": {"file": "/run/secrets/nats-token"}
+            }"#,
+        )
+        .expect("should parse auth conf");
+
+        assert!(matches!(auth.user, Some(Secret::Inline(ref v)) if v == "gateway"));
+        assert!(
+            matches!(auth.password, Some(Secret::Env { ref env }) if env == "HTTP2_TEST_NATS_PASSWORD")
+        );
+        assert!(
+            matches!(auth.token, Some(Secret::File { ref file }) if file == "/run/secrets/nats-token")
+        );
+    }
+
+    #[test]
+    fn test_secret_resolve_from_file() {
+        let mut file = tempfile::NamedTempFile::new().expect("should create temp file");
+        writeln!(file, "s3cr3t").expect("should write secret");
+
+        let secret = Secret::File {
+            file: file.path().to_string_lossy().to_string(),
+        };
+
+        assert_eq!(secret.resolve().expect("should resolve secret"), "s3cr3t");
+    }
+
+    #[test]
+    fn test_secret_resolve_missing_env() {
+        let secret = Secret::Env {
+            env: "HTTP2_TEST_SURELY_MISSING_VAR".to_string(),
+        };
+
+        let err = secret.resolve().expect_err("missing env var should fail");
+        assert!(err.message.contains("HTTP2_TEST_SURELY_MISSING_VAR"));
+    }
+
+    #[test]
+    fn test_secret_debug_is_redacted() {
+        let secret = Secret::Inline("hunter2".to_string());
+
+        assert!(!format!("{:?}", secret).contains("hunter2"));
+    }
+
+    #[test]
+    fn test_server_urls() {
+        let nats = nats_conf(
+            r#"{"host": "nats://a:4222", "servers": ["nats://b:4222", "nats://c:4222"]}"#,
+        );
+
+        assert_eq!(
+            nats.server_urls(),
+            vec!["nats://a:4222", "nats://b:4222", "nats://c:4222"]
+        );
+        assert!(nats.validate().is_ok());
+
+        let nats = nats_conf(r#"{"servers": ["nats://b:4222"]}"#);
+        assert_eq!(nats.server_urls(), vec!["nats://b:4222"]);
+    }
+
+    #[test]
+    fn test_validate_requires_server() {
+        let err = nats_conf("{}")
+            .validate()
+            .expect_err("should require a server");
+
+        assert!(err.message.contains("nats.host"));
+    }
+
+    #[test]
+    fn test_validate_rejects_multiple_auth_methods() {
+        let nats = nats_conf(r#"{"host": "a", "auth": {"token": "t", "nkey_seed": "s"}}"#);
+
+        assert!(nats.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_requires_user_with_password() {
+        let nats = nats_conf(r#"{"host": "a", "auth": {"user": "u"}}"#);
+
+        assert!(nats.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_requires_cert_with_key() {
+        let nats = nats_conf(r#"{"host": "a", "tls": {"cert_file": "/tls/client.crt"}}"#);
+        assert!(nats.validate().is_err());
+
+        let nats = nats_conf(
+            r#"{"host": "a", "tls": {"ca_file": "/tls/ca.crt", "cert_file": "/tls/client.crt", "key_file": "/tls/client.key"}}"#,
+        );
+        assert!(nats.validate().is_ok());
+    }
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn config_file(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().expect("should create temp file");
+        write!(file, "{}", contents).expect("should write config");
+        file
+    }
+
+    fn cli(args: &[&str]) -> CliArgs {
+        CliArgs::parse(args.iter().map(|a| a.to_string())).expect("should parse args")
+    }
+
+    const BASE_CONFIG: &str = r#"{
+        "listen_port": 8000,
+        "enable_cors": true,
+        "nats": {"host": "file-host:4222"},
+        "allowed_origins": ["http://localhost"],
+        "is_debug": false
+    }"#;
+
+    #[test]
+    fn test_load_layers_env_then_cli() {
+        let file = config_file(BASE_CONFIG);
+        let path = file.path().to_string_lossy().to_string();
+
+        let args = cli(&["--config", &path, "--listen-port=9100"]);
+        let conf = Conf::load(
+            &args,
+            env(&[
+                ("HTTP2_LISTEN_PORT", "9000"),
+                ("HTTP2_NATS__HOST", "env-host:4222"),
+                ("HTTP2_NATS__CONNECT_RETRY__MAX_ATTEMPTS", "3"),
+                ("HTTP2_ALLOWED_ORIGINS", r#"["https://stockwayup.com"]"#),
+            ]),
+        )
+        .expect("should load conf");
+
+        assert_eq!(conf.listen_port, 9100);
+        assert_eq!(conf.nats.host, "env-host:4222");
+        assert_eq!(conf.nats.connect_retry.max_attempts, 3);
+        assert_eq!(conf.allowed_origins, vec!["https://stockwayup.com"]);
+    }
+
+    #[test]
+    fn test_load_cli_set_nested_value() {
+        let file = config_file(BASE_CONFIG);
+        let path = file.path().to_string_lossy().to_string();
+
+        let args = cli(&[
+            "--config",
+            &path,
+            "--set",
+            "nats.tls.ca_file=/tls/ca.crt",
+            "--debug",
+        ]);
+        let conf = Conf::load(&args, env(&[])).expect("should load conf");
+
+        assert_eq!(
+            conf.nats.tls.and_then(|tls| tls.ca_file),
+            Some("/tls/ca.crt".to_string())
+        );
+        assert!(conf.is_debug);
+    }
+
+    #[test]
+    fn test_load_ignores_env_vars_without_prefix() {
+        let file = config_file(BASE_CONFIG);
+        let path = file.path().to_string_lossy().to_string();
+
+        let conf = Conf::load(&cli(&["--config", &path]), env(&[("PATH", "/usr/bin")]))
+            .expect("env vars without the prefix should be ignored");
+
+        assert_eq!(conf.listen_port, 8000);
+    }
+
+    #[test]
+    fn test_load_skips_unrelated_env_vars() {
+        let file = config_file(BASE_CONFIG);
+        let path = file.path().to_string_lossy().to_string();
+        let vars = [
+            ("HTTP2_SERVICE_HOST", "10.0.0.1"),
+            ("HTTP2_PORT", "tcp://10.0.0.1:8000"),
+            ("HTTP2_LISTEN_PORT", "9000"),
+        ];
+
+        let conf = Conf::load(&cli(&["--config", &path]), env(&vars))
+            .expect("service links should be skipped");
+
+        assert_eq!(conf.listen_port, 9000);
+        assert_eq!(
+            ignored_env_vars(env(&vars)),
+            ["HTTP2_PORT", "HTTP2_SERVICE_HOST"]
+        );
+    }
+
+    #[test]
+    fn test_load_keeps_numeric_secrets_as_strings() {
+        let file = config_file(BASE_CONFIG);
+        let path = file.path().to_string_lossy().to_string();
+
+        let conf = Conf::load(
+            &cli(&["--config", &path, "--set", "admin.token=1234"]),
+            env(&[
+                ("HTTP2_NATS__AUTH__USER", "42"),
+                ("HTTP2_NATS__AUTH__PASSWORD", "123456"),
+                ("HTTP2_NATS__HOST", "4222"),
+            ]),
+        )
+        .expect("numeric secrets should load");
+
+        let auth = conf.nats.auth;
+        assert!(matches!(auth.user, Some(Secret::Inline(ref v)) if v == "42"));
+        assert!(matches!(auth.password, Some(Secret::Inline(ref v)) if v == "123456"));
+        assert!(matches!(conf.admin.token, Some(Secret::Inline(ref v)) if v == "1234"));
+        assert_eq!(conf.nats.host, "4222");
+    }
+
+    #[test]
+    fn test_load_still_rejects_mistyped_numeric_override() {
+        let file = config_file(BASE_CONFIG);
+        let path = file.path().to_string_lossy().to_string();
+
+        let err = Conf::load(
+            &cli(&["--config", &path]),
+            env(&[("HTTP2_NATS__CONNECT_RETRY__MAX_ATTEMPTS", "many")]),
+        )
+        .expect_err("non-numeric value should fail");
+
+        assert!(
+            err.message.contains("nats.connect_retry.max_attempts"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_load_rejects_unknown_nested_env_key() {
+        let file = config_file(BASE_CONFIG);
+        let path = file.path().to_string_lossy().to_string();
+
+        let err = Conf::load(
+            &cli(&["--config", &path]),
+            env(&[("HTTP2_NATS__HOTS", "x")]),
+        )
+        .expect_err("unknown key should fail");
+
+        assert!(err.message.contains("nats"), "{}", err.message);
+        assert!(err.message.contains("hots"), "{}", err.message);
+    }
+
+    #[test]
+    fn test_from_value_reports_path_of_invalid_value() {
+        let mut value: Value = serde_json::from_str(BASE_CONFIG).expect("valid json");
+        value["nats"]["connect_retry"] = serde_json::json!({"max_attempts": "many"});
+
+        let err = Conf::from_value(value, &[]).expect_err("invalid value should fail");
+
+        assert!(
+            err.message.contains("nats.connect_retry.max_attempts"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_from_value_rejects_unknown_top_level_key() {
+        let mut value: Value = serde_json::from_str(BASE_CONFIG).expect("valid json");
+        value["listen_prot"] = Value::from(8000);
+
+        let err = Conf::from_value(value, &[]).expect_err("unknown key should fail");
+
+        assert!(err.message.contains("listen_prot"), "{}", err.message);
+    }
+
+    #[test]
+    fn test_validate_rejects_port_zero() {
+        let conf = Conf {
+            listen_port: 0,
+            ..Default::default()
+        };
+
+        let err = conf.validate().expect_err("port 0 should fail");
+        assert!(err.message.contains("listen_port"));
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_origins() {
+        for origin in [
+            "localhost:3000",
+            "ftp://example.com",
+            "https://example.com/",
+            "https://example.com/app",
+            "https://",
+            "https://*",
+            "https://*.",
+            "https://app.*.stockwayup.com",
+            "https://*stockwayup.com",
+            "*",
+        ] {
+            let conf = Conf {
+                allowed_origins: vec!["http://localhost".to_string(), origin.to_string()],
+                ..Default::default()
+            };
+
+            let err = conf.validate().expect_err(origin);
+            assert!(
+                err.message.contains("allowed_origins[1]"),
+                "{}",
+                err.message
+            );
+        }
+    }
+
+    #[test]
+    fn test_validate_accepts_origins_with_port() {
+        let conf = Conf {
+            allowed_origins: vec![
+                "http://127.0.0.1:8080".to_string(),
+                "https://dev.stockwayup.com".to_string(),
+                "https://*.stockwayup.com".to_string(),
+                "http://*.localhost:3000".to_string(),
+            ],
+            ..Default::default()
+        };
+
+        assert!(conf.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_cors_settings() {
+        let mut conf = Conf::default();
+        conf.cors.allowed_methods.push("NOT A METHOD".to_string());
+        let err = conf.validate().expect_err("bad method should fail");
+        assert!(
+            err.message.contains("cors.allowed_methods[7]"),
+            "{}",
+            err.message
+        );
+
+        let mut conf = Conf::default();
+        conf.cors.exposed_headers = vec!["*".to_string()];
+        let err = conf.validate().expect_err("wildcard header should fail");
+        assert!(
+            err.message.contains("cors.exposed_headers[0]"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_security_header_value() {
+        let mut conf = Conf::default();
+        conf.routes.insert(
+            "/api/v1/plans".to_string(),
+            RouteConf {
+                security_headers: SecurityHeadersRouteConf {
+                    content_security_policy: Some("default-src 'none'\n".to_string()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+
+        let err = conf.validate().expect_err("newline should fail");
+        assert!(
+            err.message
+                .contains("routes./api/v1/plans.security_headers"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_relative_json_api_extension() {
+        let mut conf = Conf::default();
+        conf.json_api.extensions = vec!["https://jsonapi.org/ext/atomic".to_string()];
+        assert!(conf.validate().is_ok());
+
+        conf.json_api.extensions.push("atomic".to_string());
+        let err = conf.validate().expect_err("relative URI should fail");
+        assert!(
+            err.message.contains("json_api.extensions[1]"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_async_route_and_jobs() {
+        let conf: Conf = serde_json::from_str(
+            r#"{
+                "listen_port": 8000,
+                "enable_cors": false,
+                "nats": {"host": "localhost:4222"},
+                "allowed_origins": [],
+                "is_debug": false,
+                "routes": {"/api/v1/portfolios/:pid": {"mode": "async"}},
+                "jobs": {"bucket": "portfolio-jobs"}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            conf.routes["/api/v1/portfolios/:pid"].mode,
+            RouteMode::Async
+        );
+        assert_eq!(conf.jobs.bucket, "portfolio-jobs");
+        assert_eq!(conf.jobs.subject, "http.jobs");
+        assert!(conf.validate().is_ok());
+
+        let mut conf = conf;
+        conf.jobs.results_subject = conf.jobs.subject.clone();
+        let err = conf.validate().expect_err("shared subject should fail");
+        assert!(
+            err.message.contains("jobs.results_subject"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_validate_object_store_upload_needs_bucket() {
+        let mut conf = Conf::default();
+        conf.routes.insert(
+            "/api/v1/portfolios/:pid/imports".to_string(),
+            RouteConf {
+                upload: BodyUpload::ObjectStore,
+                body_limit_bytes: Some(50 * 1024 * 1024),
+                ..Default::default()
+            },
+        );
+
+        let err = conf.validate().expect_err("missing bucket should fail");
+        assert!(
+            err.message.contains("request_body.object_store_bucket"),
+            "{}",
+            err.message
+        );
+
+        conf.request_body.object_store_bucket = Some("uploads".to_string());
+        assert!(conf.validate().is_ok());
+
+        conf.request_body.limit_bytes = 0;
+        assert!(conf.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_log_filter() {
+        let mut conf = Conf::default();
+        conf.log.filter = Some("http2=loud".to_string());
+
+        let err = conf.validate().expect_err("bad filter should fail");
+        assert!(err.message.contains("log.filter"), "{}", err.message);
+
+        conf.log.filter = Some("info,http2::handlers=debug".to_string());
+        assert!(conf.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_sample_rate() {
+        let mut conf = Conf::default();
+        conf.access_log.sample_rate = 1.5;
+
+        let err = conf
+            .validate()
+            .expect_err("sample rate above 1 should fail");
+        assert!(
+            err.message.contains("access_log.sample_rate"),
+            "{}",
+            err.message
+        );
+
+        conf.access_log.sample_rate = 0.1;
+        conf.routes.insert(
+            "/api/v1/statuses".to_string(),
+            RouteConf {
+                access_log: AccessLogRouteConf {
+                    sample_rate: Some(-0.5),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+
+        let err = conf
+            .validate()
+            .expect_err("negative route sample rate should fail");
+        assert!(
+            err.message.contains("routes./api/v1/statuses.access_log"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_json_pointer() {
+        let mut conf = Conf::default();
+        conf.redaction.json_pointers = vec!["data/attributes/password".to_string()];
+
+        let err = conf.validate().expect_err("relative pointer should fail");
+        assert!(
+            err.message.contains("redaction.json_pointers"),
+            "{}",
+            err.message
+        );
+    }
+
+    #[test]
+    fn test_admin_listener_from_value() {
+        let conf = Conf::from_value(
+            serde_json::json!({
+                "listen_port": 8000,
+                "enable_cors": false,
+                "nats": {"host": "localhost:4222"},
+                "allowed_origins": [],
+                "is_debug": false,
+                "admin": {"listener": {"port": 9090, "allowed_ips": ["10.0.0.0/8", "127.0.0.1"]}}
+            }),
+            &[],
+        )
+        .expect("should parse admin listener");
+
+        let listener = conf.admin.listener.expect("listener should be set");
+        assert_eq!(listener.addr().to_string(), "0.0.0.0:9090");
+        assert_eq!(listener.allowed_networks().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_admin_listener() {
+        let listener = AdminListenerConf {
+            bind_address: default_admin_bind_address(),
+            port: 8000,
+            token: None,
+            allowed_ips: vec![],
+        };
+
+        let mut conf = Conf::default();
+        conf.admin.listener = Some(listener.clone());
+        assert!(conf.validate().is_err(), "same port as listen_port");
+
+        conf.admin.listener = Some(AdminListenerConf {
+            port: 9090,
+            allowed_ips: vec!["10.0.0.0/33".to_string()],
+            ..listener
+        });
+        let err = conf.validate().expect_err("bad CIDR should fail");
+        assert!(err.message.contains("allowed_ips[0]"), "{}", err.message);
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_otlp_metrics() {
+        let mut conf = Conf::default();
+        conf.metrics.otlp = Some(OtlpMetricsConf {
+            endpoint: "otel-collector:4317".to_string(),
+            interval_ms: default_otlp_interval_ms(),
+            timeout_ms: default_otlp_timeout_ms(),
+        });
+
+        let err = conf
+            .validate()
+            .expect_err("endpoint without scheme should fail");
+        assert!(
+            err.message.contains("metrics.otlp.endpoint"),
+            "{}",
+            err.message
+        );
+
+        conf.metrics.otlp = Some(OtlpMetricsConf {
+            endpoint: "http://otel-collector:4317".to_string(),
+            interval_ms: 0,
+            timeout_ms: default_otlp_timeout_ms(),
+        });
+        assert!(conf.validate().is_err(), "zero interval should fail");
+    }
+
+    #[test]
+    fn test_cli_args_errors() {
+        assert!(CliArgs::parse(vec!["--unknown".to_string()]).is_err());
+        assert!(CliArgs::parse(vec!["--config".to_string()]).is_err());
+        assert!(CliArgs::parse(vec!["--set".to_string(), "nokey".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_cli_args_print_config() {
+        let args = cli(&["--print-config", "--nats-host", "h:4222"]);
+
+        assert!(args.print_config);
+        assert_eq!(
+            args.overrides,
+            vec![("nats.host".to_string(), "h:4222".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_redacted_json_masks_inline_secrets() {
+        let mut conf = Conf::default();
+        conf.nats.auth.token = Some(Secret::Inline("hunter2".to_string()));
+        conf.nats.auth.nkey_seed = Some(Secret::Env {
+            env: "NATS_SEED".to_string(),
+        });
+
+        let json = conf.to_redacted_json().expect("should serialize");
+
+        assert!(!json.contains("hunter2"));
+        assert!(json.contains("***"));
+        assert!(json.contains("NATS_SEED"));
     }
 }
@@ -0,0 +1,134 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use axum::http::{HeaderName, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::conf::Conf;
+use crate::reload::SharedLiveConf;
+
+/// Origins from `allowed_origins`, exact ones and `scheme://*.domain[:port]` patterns
+/// that match any subdomain of `domain`, but not `domain` itself.
+#[derive(Debug, Clone, Default)]
+pub struct AllowedOrigins {
+    exact: HashSet<String>,
+    wildcards: Vec<(String, String)>,
+}
+
+impl AllowedOrigins {
+    pub fn new(origins: &[String]) -> Self {
+        let mut allowed = AllowedOrigins::default();
+
+        for origin in origins {
+            let origin = origin.to_ascii_lowercase();
+
+            match origin.split_once("://*.") {
+                Some((scheme, domain)) => allowed
+                    .wildcards
+                    .push((format!("{scheme}://"), format!(".{domain}"))),
+                None => {
+                    allowed.exact.insert(origin);
+                }
+            }
+        }
+
+        allowed
+    }
+
+    pub fn is_allowed(&self, origin: &str) -> bool {
+        let origin = origin.to_ascii_lowercase();
+
+        self.exact.contains(&origin)
+            || self.wildcards.iter().any(|(prefix, suffix)| {
+                origin
+                    .strip_prefix(prefix.as_str())
+                    .and_then(|rest| rest.strip_suffix(suffix.as_str()))
+                    .is_some_and(is_subdomain)
+            })
+    }
+}
+
+/// One or more DNS labels, so a pattern can't match across a port or path.
+fn is_subdomain(labels: &str) -> bool {
+    labels.split('.').all(|label| {
+        !label.is_empty()
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    })
+}
+
+/// Builds the CORS layer from `cors`, origins are looked up on every request so a
+/// reload can change them.
+pub fn cors_layer(conf: &Conf, live: SharedLiveConf) -> CorsLayer {
+    let allow_origin = AllowOrigin::predicate(move |origin, _| {
+        live.snapshot().is_origin_allowed(origin.as_bytes())
+    });
+
+    // Invalid entries are rejected by `Conf::validate`.
+    let methods: Vec<Method> = conf
+        .cors
+        .allowed_methods
+        .iter()
+        .filter_map(|method| method.parse().ok())
+        .collect();
+
+    let header_names = |names: &[String]| -> Vec<HeaderName> {
+        names.iter().filter_map(|name| name.parse().ok()).collect()
+    };
+
+    let layer = CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(methods)
+        .allow_headers(header_names(&conf.cors.allowed_headers))
+        .expose_headers(header_names(&conf.cors.exposed_headers))
+        .allow_credentials(conf.cors.allow_credentials);
+
+    match conf.cors.max_age_secs {
+        Some(secs) => layer.max_age(Duration::from_secs(secs)),
+        None => layer,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origins(patterns: &[&str]) -> AllowedOrigins {
+        AllowedOrigins::new(&patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_exact_origins() {
+        let allowed = origins(&["https://stockwayup.com", "http://localhost:3000"]);
+
+        assert!(allowed.is_allowed("https://stockwayup.com"));
+        assert!(allowed.is_allowed("https://StockWayUp.com"));
+        assert!(allowed.is_allowed("http://localhost:3000"));
+        assert!(!allowed.is_allowed("http://localhost:3001"));
+        assert!(!allowed.is_allowed("http://stockwayup.com"));
+    }
+
+    #[test]
+    fn test_wildcard_subdomains() {
+        let allowed = origins(&["https://*.stockwayup.com"]);
+
+        assert!(allowed.is_allowed("https://app.stockwayup.com"));
+        assert!(allowed.is_allowed("https://eu.app.stockwayup.com"));
+        assert!(!allowed.is_allowed("https://stockwayup.com"));
+        assert!(!allowed.is_allowed("https://.stockwayup.com"));
+        assert!(!allowed.is_allowed("http://app.stockwayup.com"));
+        assert!(!allowed.is_allowed("https://evil.com/.stockwayup.com"));
+        assert!(!allowed.is_allowed("https://app.stockwayup.com.evil.com"));
+        assert!(!allowed.is_allowed("https://app.stockwayup.com:8443"));
+    }
+
+    #[test]
+    fn test_wildcard_with_port() {
+        let allowed = origins(&["http://*.localhost:3000"]);
+
+        assert!(allowed.is_allowed("http://web.localhost:3000"));
+        assert!(!allowed.is_allowed("http://web.localhost:3001"));
+        assert!(!allowed.is_allowed("http://a:1.localhost:3000"));
+    }
+}
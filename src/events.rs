@@ -16,6 +16,20 @@ pub struct HttpReq<'a> {
     pub body: &'a [u8],
 }
 
+/// Published for every accepted import, the file waits in the object store.
+#[derive(Serialize)]
+pub struct ImportJob<'a> {
+    pub id: &'a str,
+    pub portfolio_id: &'a str,
+    pub access_token: &'a str,
+    pub bucket: &'a str,
+    pub object: &'a str,
+    pub size: usize,
+    pub file_name: Option<&'a str>,
+    pub content_type: Option<&'a str>,
+    pub format: Option<&'a str>,
+}
+
 #[derive(Serialize)]
 pub struct Uri<'a> {
     #[serde(with = "serde_bytes")]
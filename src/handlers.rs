@@ -1,7 +1,18 @@
-use crate::metrics::AppMetrics;
-use crate::responses::errors::{Error, Errors};
-use async_nats::{Client, HeaderMap};
-use axum::extract::{MatchedPath, OriginalUri, Path, Query};
+use crate::jobs::{accepted, Jobs};
+use crate::metrics::{AppMetrics, GaugeGuard};
+use crate::nats::{insert_deadline_headers, is_connected, SharedClient};
+use crate::path_params::PathParams;
+use crate::reload::SharedLiveConf;
+use crate::request_body::{
+    discard_staged, read_body, stage_body, too_large, BodyError, BUCKET_HEADER, OBJECT_HEADER,
+};
+use crate::request_id::RequestId;
+use crate::responses::errors::{Error, ErrorMeta, Errors};
+use crate::schemas::RequestSchemas;
+use crate::shutdown::SharedDrain;
+use async_nats::client::{Request, RequestErrorKind};
+use async_nats::HeaderMap;
+use axum::extract::{BodyStream, MatchedPath, OriginalUri, Path, Query};
 use axum::headers::{
     authorization::{Authorization, Bearer},
     HeaderValue,
@@ -15,15 +26,13 @@ use serde::Serialize;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::Instant;
-use tokio::sync::RwLock;
-use tracing::{error, info, instrument, Span};
-use uuid::Uuid;
+use tracing::{debug, error, info, instrument, warn, Span};
 
 use super::events::HttpReq;
 use super::responses::statuses::{Attributes, Statuses, StatusesData};
 
 const SUBJECT: &str = "http";
-const JSON_API_TYPE: &str = "application/vnd.api+json";
+pub(crate) const JSON_API_TYPE: &str = "application/vnd.api+json";
 
 // Simplified header management for future OpenTelemetry integration
 
@@ -44,6 +53,33 @@ pub async fn health_check() -> impl IntoResponse {
     resp
 }
 
+pub async fn readiness_check(
+    Extension(nats): Extension<SharedClient>,
+    Extension(drain): Extension<SharedDrain>,
+) -> impl IntoResponse {
+    if drain.is_draining() {
+        return create_error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "503",
+            "Service unavailable",
+            "The service is shutting down.",
+        )
+        .into_response();
+    }
+
+    if is_connected(&nats).await {
+        return health_check().await.into_response();
+    }
+
+    create_error_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "503",
+        "Service unavailable",
+        "NATS connection is not established.",
+    )
+    .into_response()
+}
+
 pub async fn not_found() -> impl IntoResponse {
     create_error_response(
         StatusCode::NOT_FOUND,
@@ -56,28 +92,21 @@ pub async fn not_found() -> impl IntoResponse {
 pub async fn metrics_handler(
     Extension(metrics): Extension<Option<Arc<AppMetrics>>>,
 ) -> impl IntoResponse {
-    match metrics {
-        Some(metrics) => match metrics.render().await {
-            Ok(body) => match Response::builder()
-                .status(StatusCode::OK)
-                .header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
-                .body(body)
-            {
-                Ok(response) => response.into_response(),
-                Err(e) => {
-                    error!(error = %e, "failed to build metrics response");
-                    Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body("Internal server error".to_string())
-                        .unwrap_or_else(|_| Response::new("Fatal error".to_string()))
-                        .into_response()
-                }
-            },
-            Err(_) => Response::builder()
-                .status(StatusCode::INTERNAL_SERVER_ERROR)
-                .body("Error rendering metrics".to_string())
-                .unwrap_or_else(|_| Response::new("Error rendering metrics".to_string()))
-                .into_response(),
+    match metrics.and_then(|metrics| metrics.render()) {
+        Some(output) => match Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+            .body(output)
+        {
+            Ok(response) => response.into_response(),
+            Err(e) => {
+                error!(error = %e, "failed to build metrics response");
+                Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body("Internal server error".to_string())
+                    .unwrap_or_else(|_| Response::new("Fatal error".to_string()))
+                    .into_response()
+            }
         },
         None => Response::builder()
             .status(StatusCode::SERVICE_UNAVAILABLE)
@@ -88,36 +117,127 @@ pub async fn metrics_handler(
 }
 
 #[allow(clippy::too_many_arguments)]
-#[instrument(skip(body, nats), fields(
+// Request data only reaches the span through `Redaction`, never through `Debug`.
+#[instrument(skip_all, fields(
     http.method = %method,
     http.route = %matched_path.as_str(),
-    http.request.body.size = body.len(),
+    http.target = tracing::field::Empty,
+    http.request.body.size = tracing::field::Empty,
+    user.authenticated = tracing::field::Empty,
+    http.response.status_code = tracing::field::Empty,
+    nats.response.size = tracing::field::Empty,
+    duration_ms = tracing::field::Empty,
+    error = tracing::field::Empty,
 ))]
 pub async fn proxy(
     OriginalUri(uri): OriginalUri,
     matched_path: MatchedPath,
     method: Method,
-    body: Bytes,
-    Path(user_values): Path<HashMap<String, String>>,
+    body: BodyStream,
+    Path(mut user_values): Path<HashMap<String, String>>,
     Query(query_args): Query<HashMap<String, String>>,
     authorization: Option<TypedHeader<Authorization<Bearer>>>,
-    Extension(nats): Extension<Arc<RwLock<Client>>>,
+    request_headers: axum::http::HeaderMap,
+    Extension(nats): Extension<SharedClient>,
+    Extension(live): Extension<SharedLiveConf>,
+    Extension(drain): Extension<SharedDrain>,
     Extension(metrics): Extension<Option<Arc<AppMetrics>>>,
+    Extension(schemas): Extension<RequestSchemas>,
+    Extension(path_params): Extension<PathParams>,
+    Extension(jobs): Extension<Jobs>,
+    Extension(id): Extension<RequestId>,
 ) -> impl IntoResponse {
     let start_time = Instant::now();
-    let id = Uuid::new_v4();
+    let _in_flight = drain.track();
+    let live = live.snapshot();
+
+    if !live.is_route_enabled(matched_path.as_str()) {
+        return not_found().await.into_response();
+    }
+
+    if let Some(resp) = path_params.reject(matched_path.as_str(), &mut user_values) {
+        return resp.into_response();
+    }
+
+    // A job is read back with the token it was submitted with, so it needs one.
+    let job_owner = match (
+        jobs.is_async(matched_path.as_str(), &method),
+        &authorization,
+    ) {
+        (false, _) => None,
+        (true, Some(TypedHeader(bearer))) if !bearer.token().is_empty() => {
+            Some(bearer.token().to_string())
+        }
+        (true, _) => {
+            return create_error_response(
+                StatusCode::UNAUTHORIZED,
+                "401",
+                "Unauthorized",
+                "A request queued as a job needs a bearer token.",
+            )
+            .into_response()
+        }
+    };
+
+    let body_limit = live.request_body.limit(matched_path.as_str());
+
+    // Staged bodies are streamed into the object store once NATS is known to be up.
+    let (body, upload) = match live.request_body.staging_bucket(matched_path.as_str()) {
+        Some(bucket) => (Bytes::new(), Some((bucket.to_string(), body))),
+        None => match read_body(body).await {
+            Ok(body) => {
+                Span::current().record("http.request.body.size", body.len());
+                (body, None)
+            }
+            Err(e) => return body_error_response(e, body_limit),
+        },
+    };
+
+    let timeout = live.deadlines.for_route(matched_path.as_str());
+
+    let redaction = live.redaction.for_route(matched_path.as_str());
+    let target = uri.path_and_query().map_or(uri.path(), |pq| pq.as_str());
 
     // Add span attributes
     let span = Span::current();
-    span.record("request.id", id.to_string().as_str());
+    span.record(
+        "http.target",
+        redaction.uri(matched_path.as_str(), target).as_str(),
+    );
     span.record("user.authenticated", authorization.is_some());
 
+    debug!(
+        headers = %redaction.headers(&request_headers),
+        path_params = %redaction.path_params(&user_values),
+        payload = %redaction.body(&body),
+        "request payload"
+    );
+
+    // Invalid bodies are answered here instead of failing in the backend.
+    if let Some(result) = schemas.check(matched_path.as_str(), &method, &body) {
+        if let Some(metrics) = &metrics {
+            metrics.record_body_validation(&method, matched_path.as_str(), result.is_ok());
+        }
+
+        if let Err(resp) = result {
+            return resp.into_response();
+        }
+    }
+
+    // HEAD is served by the GET route, the router drops the response body.
+    let backend_method = if method == Method::HEAD {
+        Method::GET
+    } else {
+        method.clone()
+    };
+
+    let access_token = authorization.map(|val| val.token().to_string());
 
     let req = HttpReq::new(
         uri,
         matched_path.clone(),
-        method.to_string(),
-        authorization.map_or_else(|| "".to_string(), |val| val.token().to_string()),
+        backend_method.to_string(),
+        access_token.clone().unwrap_or_default(),
         user_values,
         query_args,
         &body,
@@ -130,12 +250,26 @@ pub async fn proxy(
 
     let client = nats.read().await;
 
+    let client = match client.as_ref() {
+        Some(client) => client,
+        None => {
+            warn!("NATS is not connected yet, rejecting request");
+            return create_error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "503",
+                "Service unavailable",
+                "The service is starting up, please retry later.",
+            )
+            .into_response();
+        }
+    };
+
     let mut buf = Vec::new();
 
     let mut se = Serializer::new(&mut buf).with_struct_map();
 
     if let Err(e) = req.serialize(&mut se) {
-        error!(error = %e, request_id = %id, "failed to serialize request");
+        error!(error = %e, "failed to serialize request");
         return create_error_response(
             StatusCode::INTERNAL_SERVER_ERROR,
             "500",
@@ -145,17 +279,98 @@ pub async fn proxy(
         .into_response();
     }
 
+    let max_payload = client.server_info().max_payload;
+
+    if max_payload > 0 && buf.len() > max_payload {
+        warn!(
+            size = buf.len(),
+            max_payload, "request is larger than the NATS max_payload"
+        );
+        return too_large(max_payload).into_response();
+    }
+
+    let staged = match upload {
+        Some((bucket, body)) => match stage_body(client, &bucket, body).await {
+            Ok(object) => {
+                span.record("http.request.body.size", object.size);
+                headers.insert(BUCKET_HEADER, bucket.as_str());
+                headers.insert(OBJECT_HEADER, object.name.as_str());
+                Some((bucket, object.name))
+            }
+            Err(e) => return body_error_response(e, body_limit),
+        },
+        None => None,
+    };
+
+    // Answered before the backend is done, the client polls the job instead.
+    if let Some(owner) = job_owner {
+        let submitted = jobs
+            .submit(
+                client,
+                matched_path.as_str(),
+                &backend_method,
+                &owner,
+                headers,
+                Bytes::from(buf),
+            )
+            .await;
+
+        return match submitted {
+            Ok((job_id, record)) => {
+                span.record("http.response.status_code", 202_i64);
+                info!(job = job_id, "request queued as a job");
+
+                accepted(&job_id, &record)
+            }
+            Err(e) => {
+                span.record("error", true);
+                error!(error = %e, "failed to queue job");
+
+                if let Some((bucket, name)) = &staged {
+                    discard_staged(client, bucket, name).await;
+                }
+
+                create_error_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "503",
+                    "Service unavailable",
+                    "The request could not be queued, please retry later.",
+                )
+                .into_response()
+            }
+        };
+    }
+
     let status_code: String;
 
-    let resp = match client
-        .request_with_headers(SUBJECT, headers, Bytes::from(buf))
-        .await
-    {
+    let remaining = timeout.saturating_sub(start_time.elapsed());
+    insert_deadline_headers(&mut headers, remaining);
+
+    let request = Request::new()
+        .headers(headers)
+        .payload(Bytes::from(buf))
+        .timeout(Some(remaining));
+
+    let pending = metrics
+        .as_ref()
+        .map(|metrics| GaugeGuard::new(metrics.nats_pending_requests.clone()));
+    let resp = client.send_request(SUBJECT, request).await;
+
+    drop(pending);
+
+    // Without a responder nobody took ownership of the staged object.
+    if let (Err(e), Some((bucket, name))) = (&resp, &staged) {
+        if e.kind() == RequestErrorKind::NoResponders {
+            discard_staged(client, bucket, name).await;
+        }
+    }
+
+    let resp = match resp {
         Ok(response) => {
             let headers = match response.headers {
                 Some(headers) => headers,
                 None => {
-                    error!(request_id = %id, "NATS response missing headers");
+                    error!("NATS response missing headers");
                     return create_error_response(
                         StatusCode::INTERNAL_SERVER_ERROR,
                         "500",
@@ -173,7 +388,7 @@ pub async fn proxy(
             let code = match StatusCode::from_bytes(status_value.to_string().as_bytes()) {
                 Ok(code) => code,
                 Err(e) => {
-                    error!(error = %e, request_id = %id, "invalid status code from NATS");
+                    error!(error = %e, "invalid status code from NATS");
                     StatusCode::INTERNAL_SERVER_ERROR
                 }
             };
@@ -203,55 +418,84 @@ pub async fn proxy(
     // Record final span attributes
     span.record("duration_ms", elapsed_time.as_millis() as i64);
 
-    // Record metrics if available
+    // HTTP metrics are recorded by `record_metrics` for every route
     if let Some(metrics) = metrics {
-        let route_template = matched_path.as_str();
         let status_num: u16 = status_code.parse().unwrap_or(500);
 
-        metrics.record_http_request(method.as_str(), route_template, status_num, elapsed_time);
-
         metrics.record_nats_request(SUBJECT, status_num < 400, elapsed_time);
     }
 
-    info!(
-        request_id = %id,
-        method = %method,
-        route = %matched_path.as_str(),
-        status = status_code.as_str(),
-        elapsed_time_ms = elapsed_time.as_millis(),
-        "request completed"
-    );
-
     resp
 }
 
-fn create_error_response(
+pub(crate) fn body_error_response(e: BodyError, limit: usize) -> axum::response::Response {
+    match e {
+        BodyError::TooLarge => too_large(limit).into_response(),
+        BodyError::Read(e) => {
+            warn!(error = %e, "failed to read request body");
+            create_error_response(
+                StatusCode::BAD_REQUEST,
+                "400",
+                "Bad request",
+                "The request body could not be read.",
+            )
+            .into_response()
+        }
+        BodyError::Store(e) => {
+            error!(error = %e, "failed to stage request body");
+            create_error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "503",
+                "Service unavailable",
+                "The upload could not be stored, please retry later.",
+            )
+            .into_response()
+        }
+    }
+}
+
+pub(crate) fn create_error_response(
     status: StatusCode,
     code: &str,
     title: &str,
     detail: &str,
 ) -> Response<Full<axum::body::Bytes>> {
-    let errors = Errors {
-        errors: vec![Error {
+    create_errors_response(
+        status,
+        vec![Error {
             code: code.to_string(),
             title: title.to_string(),
             detail: detail.to_string(),
+            source: None,
+            meta: None,
         }],
-    };
+    )
+}
 
-    let mut buf = Vec::new();
-    let mut se = Serializer::new(&mut buf);
+/// A JSON:API error document, each error gets the current request id as meta.
+pub(crate) fn create_errors_response(
+    status: StatusCode,
+    mut errors: Vec<Error>,
+) -> Response<Full<axum::body::Bytes>> {
+    let request_id = RequestId::current();
 
-    // Handle serialization failure gracefully
-    if let Err(e) = errors.serialize(&mut se) {
+    for error in &mut errors {
+        if error.meta.is_none() {
+            error.meta = request_id.as_ref().map(|id| ErrorMeta {
+                request_id: id.to_string(),
+            });
+        }
+    }
+
+    let buf = serde_json::to_vec(&Errors { errors }).unwrap_or_else(|e| {
         error!(error = %e, "failed to serialize error response");
         // Return a minimal fallback response as JSON string
-        let fallback_body = format!(
-            r#"{{"errors":[{{"code":"{}","title":"{}","detail":"Serialization failed"}}]}}"#,
-            code, title
-        );
-        buf = fallback_body.into_bytes();
-    }
+        format!(
+            r#"{{"errors":[{{"code":"{}","title":"Error","detail":"Serialization failed"}}]}}"#,
+            status.as_u16()
+        )
+        .into_bytes()
+    });
 
     match Response::builder()
         .status(status)
@@ -0,0 +1,287 @@
+use std::collections::HashMap;
+
+use async_nats::{Client, HeaderMap};
+use axum::body::Bytes;
+use axum::extract::{BodyStream, MatchedPath, Path};
+use axum::headers::authorization::Bearer;
+use axum::headers::Authorization;
+use axum::http::header::{CONTENT_TYPE, LOCATION};
+use axum::http::{HeaderMap as HttpHeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{Extension, Json, TypedHeader};
+use rmp_serde::Serializer;
+use serde::Serialize;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use crate::conf::ImportsConf;
+use crate::events::ImportJob;
+use crate::handlers::{body_error_response, create_error_response, not_found, JSON_API_TYPE};
+use crate::multipart::{boundary, Multipart, MultipartError, PartHeaders};
+use crate::nats::SharedClient;
+use crate::path_params::PathParams;
+use crate::reload::SharedLiveConf;
+use crate::request_body::{discard_staged, stage, BodyError, StageError};
+use crate::request_id::RequestId;
+use crate::responses::imports::{Import, ImportAttributes, ImportData};
+use crate::responses::links::Links;
+use crate::shutdown::SharedDrain;
+
+/// Takes `multipart/form-data` instead of a JSON:API document.
+pub const IMPORTS_ROUTE: &str = "/api/v1/portfolios/:pid/imports";
+/// Proxied, the backend reports the progress of an import.
+pub const IMPORT_ROUTE: &str = "/api/v1/portfolios/:pid/imports/:iid";
+
+const FILE_PART: &str = "file";
+const FORMAT_PART: &str = "format";
+const MAX_FORMAT_BYTES: usize = 64;
+
+/// The parts of an import form read so far.
+#[derive(Default)]
+struct ImportForm {
+    file: Option<(PartHeaders, async_nats::jetstream::object_store::ObjectInfo)>,
+    format: Option<String>,
+}
+
+impl ImportForm {
+    async fn read(
+        &mut self,
+        form: &mut Multipart<BodyStream>,
+        client: &Client,
+        bucket: &str,
+    ) -> Result<(), MultipartError> {
+        while let Some(part) = form.next_part().await? {
+            match part.name.as_str() {
+                FILE_PART if self.file.is_some() => {
+                    return Err(MultipartError::Malformed("An import takes a single file."));
+                }
+                FILE_PART => {
+                    let chunks = Box::pin(futures::stream::unfold(&mut *form, |form| async {
+                        form.next_chunk()
+                            .await
+                            .transpose()
+                            .map(|chunk| (chunk, form))
+                    }));
+
+                    let object = stage(client, bucket, chunks).await.map_err(|e| match e {
+                        StageError::Body(e) => e,
+                        StageError::Store(e) => MultipartError::Body(BodyError::Store(e)),
+                    })?;
+
+                    self.file = Some((part, object));
+                }
+                FORMAT_PART => {
+                    let mut format = vec![];
+
+                    while let Some(chunk) = form.next_chunk().await? {
+                        format.extend_from_slice(&chunk);
+
+                        if format.len() > MAX_FORMAT_BYTES {
+                            break;
+                        }
+                    }
+
+                    self.format = Some(
+                        String::from_utf8(format)
+                            .ok()
+                            .filter(|format| format.len() <= MAX_FORMAT_BYTES)
+                            .ok_or(MultipartError::Malformed(
+                                "The format must be text of up to 64 bytes.",
+                            ))?,
+                    );
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Stages the uploaded file in the object store and publishes an import job, the
+/// client polls the returned import through the proxied `IMPORT_ROUTE`.
+#[allow(clippy::too_many_arguments)]
+pub async fn create_import(
+    matched_path: MatchedPath,
+    Path(mut user_values): Path<HashMap<String, String>>,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    headers: HttpHeaderMap,
+    body: BodyStream,
+    Extension(nats): Extension<SharedClient>,
+    Extension(live): Extension<SharedLiveConf>,
+    Extension(imports): Extension<ImportsConf>,
+    Extension(drain): Extension<SharedDrain>,
+    Extension(path_params): Extension<PathParams>,
+    Extension(id): Extension<RequestId>,
+) -> Response {
+    let _in_flight = drain.track();
+    let live = live.snapshot();
+
+    // Without a bucket there is nowhere to put the files.
+    let Some(bucket) = live
+        .request_body
+        .bucket()
+        .filter(|_| live.is_route_enabled(matched_path.as_str()))
+    else {
+        return not_found().await.into_response();
+    };
+
+    // Checked before anything is written to the bucket.
+    let Some(TypedHeader(Authorization(bearer))) = authorization else {
+        return create_error_response(
+            StatusCode::UNAUTHORIZED,
+            "401",
+            "Unauthorized",
+            "An import needs a bearer token.",
+        )
+        .into_response();
+    };
+
+    if let Some(resp) = path_params.reject(matched_path.as_str(), &mut user_values) {
+        return resp.into_response();
+    }
+
+    let Some(boundary) = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(boundary)
+    else {
+        return create_error_response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "415",
+            "Unsupported media type",
+            "An import must be sent as multipart/form-data.",
+        )
+        .into_response();
+    };
+
+    let client = nats.read().await;
+
+    let Some(client) = client.as_ref() else {
+        return create_error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "503",
+            "Service unavailable",
+            "The service is starting up, please retry later.",
+        )
+        .into_response();
+    };
+
+    let limit = live.request_body.limit(matched_path.as_str());
+    let mut import = ImportForm::default();
+
+    let file = import
+        .read(&mut Multipart::new(body, &boundary), client, bucket)
+        .await
+        .and_then(|()| {
+            import
+                .file
+                .take()
+                .ok_or(MultipartError::Malformed("An import needs a file part."))
+        });
+
+    let (part, object) = match file {
+        Ok(file) => file,
+        Err(e) => {
+            if let Some((_, object)) = &import.file {
+                discard_staged(client, bucket, &object.name).await;
+            }
+
+            return match e {
+                MultipartError::Body(e) => body_error_response(e, limit),
+                MultipartError::Malformed(detail) => {
+                    create_error_response(StatusCode::BAD_REQUEST, "400", "Bad request", detail)
+                        .into_response()
+                }
+            };
+        }
+    };
+
+    let import_id = Uuid::new_v4().to_string();
+    let portfolio_id = user_values.get("pid").map_or("", String::as_str);
+
+    let job = ImportJob {
+        id: &import_id,
+        portfolio_id,
+        access_token: bearer.token(),
+        bucket,
+        object: &object.name,
+        size: object.size,
+        file_name: part.file_name.as_deref(),
+        content_type: part.content_type.as_deref(),
+        format: import.format.as_deref(),
+    };
+
+    if let Err(e) = publish_job(client, &imports.subject, &job, &id).await {
+        error!(error = %e, subject = imports.subject, "failed to publish import job");
+        discard_staged(client, bucket, &object.name).await;
+
+        return create_error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "503",
+            "Service unavailable",
+            "The import could not be queued, please retry later.",
+        )
+        .into_response();
+    }
+
+    info!(
+        import = import_id,
+        portfolio = portfolio_id,
+        size = object.size,
+        "import queued"
+    );
+
+    let self_link = IMPORT_ROUTE
+        .replace(":pid", portfolio_id)
+        .replace(":iid", &import_id);
+
+    let mut resp = (
+        StatusCode::ACCEPTED,
+        Json(Import {
+            data: ImportData {
+                id: import_id,
+                r#type: "imports".to_string(),
+                attributes: ImportAttributes {
+                    status: "pending".to_string(),
+                    file_name: part.file_name,
+                    size: object.size,
+                },
+                links: Links {
+                    self_link: self_link.clone(),
+                },
+            },
+        }),
+    )
+        .into_response();
+
+    resp.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(JSON_API_TYPE));
+
+    if let Ok(location) = HeaderValue::from_str(&self_link) {
+        resp.headers_mut().insert(LOCATION, location);
+    }
+
+    resp
+}
+
+/// Publishes `job` and waits for the stream to store it.
+async fn publish_job(
+    client: &Client,
+    subject: &str,
+    job: &ImportJob<'_>,
+    id: &RequestId,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut buf = Vec::new();
+    job.serialize(&mut Serializer::new(&mut buf).with_struct_map())?;
+
+    let mut headers = HeaderMap::new();
+    headers.insert("id", id.to_string());
+
+    async_nats::jetstream::new(client.clone())
+        .publish_with_headers(subject.to_string(), headers, Bytes::from(buf))
+        .await?
+        .await?;
+
+    Ok(())
+}
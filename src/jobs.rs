@@ -0,0 +1,486 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_nats::jetstream::consumer::{pull, AckPolicy};
+use async_nats::jetstream::kv::Store;
+use async_nats::jetstream::Message;
+use async_nats::{Client, HeaderMap};
+use axum::body::Bytes;
+use axum::extract::{MatchedPath, Path};
+use axum::headers::authorization::Bearer;
+use axum::headers::Authorization;
+use axum::http::header::{CONTENT_TYPE, LOCATION};
+use axum::http::{HeaderValue, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{BoxError, Extension, Json, TypedHeader};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::conf::{Conf, JobsConf, RouteMode};
+use crate::handlers::{create_error_response, not_found, JSON_API_TYPE};
+use crate::nats::SharedClient;
+use crate::path_params::PathParams;
+use crate::responses::jobs::{Job, JobAttributes, JobData};
+use crate::responses::links::Links;
+use crate::shutdown::SharedDrain;
+
+/// Status of a job queued by an `async` route.
+pub const JOB_ROUTE: &str = "/api/v1/jobs/:jid";
+/// Id of the job a request or result belongs to.
+pub const JOB_HEADER: &str = "job";
+
+/// Durable consumer shared by all gateway instances, each result is recorded once.
+const CONSUMER: &str = "http2-jobs";
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    Done,
+    Failed,
+}
+
+/// A job as kept in the key-value bucket.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JobRecord {
+    pub status: JobStatus,
+    pub route: String,
+    pub method: String,
+    pub code: Option<u16>,
+    #[serde(with = "serde_bytes")]
+    pub result: Vec<u8>,
+    /// SHA-256 of the bearer token the job was submitted with, empty in records
+    /// stored before jobs had owners.
+    #[serde(default, with = "serde_bytes")]
+    pub owner: Vec<u8>,
+}
+
+impl JobRecord {
+    fn pending(route: &str, method: &Method, token: &str) -> Self {
+        JobRecord {
+            status: JobStatus::Pending,
+            route: route.to_string(),
+            method: method.to_string(),
+            code: None,
+            result: vec![],
+            owner: owner(token),
+        }
+    }
+
+    /// Only the token the job was submitted with can read it, a record without an
+    /// owner can't be read at all.
+    fn is_owned_by(&self, token: &str) -> bool {
+        !self.owner.is_empty() && self.owner == owner(token)
+    }
+
+    /// Stores the backend's reply, an error status fails the job.
+    fn finish(&mut self, code: u16, result: &[u8]) {
+        self.status = if code < 400 {
+            JobStatus::Done
+        } else {
+            JobStatus::Failed
+        };
+        self.code = Some(code);
+        self.result = result.to_vec();
+    }
+}
+
+fn owner(token: &str) -> Vec<u8> {
+    Sha256::digest(token.as_bytes()).to_vec()
+}
+
+/// Routes answered with a job instead of the backend's reply, and where jobs go.
+#[derive(Debug, Clone)]
+pub struct Jobs {
+    conf: Arc<JobsConf>,
+    routes: Arc<HashSet<String>>,
+}
+
+impl Jobs {
+    pub fn from_conf(conf: &Conf) -> Self {
+        Jobs {
+            conf: Arc::new(conf.jobs.clone()),
+            routes: Arc::new(
+                conf.routes
+                    .iter()
+                    .filter(|(_, route_conf)| route_conf.mode == RouteMode::Async)
+                    .map(|(route, _)| route.clone())
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        !self.routes.is_empty()
+    }
+
+    /// Reads stay synchronous, so a route can serve the resource its writes change.
+    pub fn is_async(&self, route: &str, method: &Method) -> bool {
+        matches!(
+            *method,
+            Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+        ) && self.routes.contains(route)
+    }
+
+    async fn store(&self, client: &Client) -> Result<Store, BoxError> {
+        Ok(async_nats::jetstream::new(client.clone())
+            .get_key_value(self.conf.bucket.as_str())
+            .await?)
+    }
+
+    /// Records a pending job owned by `token`, then publishes the request with
+    /// JetStream and waits for the stream to store it. Returns the job id and record.
+    pub async fn submit(
+        &self,
+        client: &Client,
+        route: &str,
+        method: &Method,
+        token: &str,
+        mut headers: HeaderMap,
+        payload: Bytes,
+    ) -> Result<(String, JobRecord), BoxError> {
+        let store = self.store(client).await?;
+        let job_id = Uuid::new_v4().to_string();
+
+        let record = JobRecord::pending(route, method, token);
+        store
+            .create(&job_id, Bytes::from(rmp_serde::to_vec_named(&record)?))
+            .await?;
+
+        headers.insert(JOB_HEADER, job_id.as_str());
+
+        let published = async {
+            async_nats::jetstream::new(client.clone())
+                .publish_with_headers(self.conf.subject.clone(), headers, payload)
+                .await?
+                .await?;
+
+            Ok::<_, BoxError>(())
+        }
+        .await;
+
+        if let Err(e) = published {
+            if let Err(e) = store.delete(&job_id).await {
+                warn!(error = %e, job = job_id, "can't remove job that was never queued");
+            }
+
+            return Err(e);
+        }
+
+        Ok((job_id, record))
+    }
+
+    async fn get(&self, client: &Client, job_id: &str) -> Result<Option<JobRecord>, BoxError> {
+        match self.store(client).await?.get(job_id).await? {
+            Some(value) => Ok(Some(rmp_serde::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Records the results backends publish until the process exits, reconnecting to
+    /// the consumer after failures.
+    pub fn spawn_results(self, nats: SharedClient) {
+        tokio::spawn(async move {
+            loop {
+                let client = nats.read().await.clone();
+
+                if let Some(client) = client {
+                    if let Err(e) = self.consume_results(&client).await {
+                        error!(
+                            error = %e,
+                            stream = self.conf.results_stream,
+                            "job results consumer failed, retrying"
+                        );
+                    }
+                }
+
+                tokio::time::sleep(RETRY_DELAY).await;
+            }
+        });
+    }
+
+    async fn consume_results(&self, client: &Client) -> Result<(), BoxError> {
+        let store = self.store(client).await?;
+
+        let consumer = async_nats::jetstream::new(client.clone())
+            .get_stream(self.conf.results_stream.as_str())
+            .await?
+            .get_or_create_consumer(
+                CONSUMER,
+                pull::Config {
+                    durable_name: Some(CONSUMER.to_string()),
+                    filter_subject: self.conf.results_subject.clone(),
+                    ack_policy: AckPolicy::Explicit,
+                    ..Default::default()
+                },
+            )
+            .await?;
+
+        let mut messages = consumer.messages().await?;
+
+        info!(stream = self.conf.results_stream, "consuming job results");
+
+        while let Some(message) = messages.next().await {
+            let message = message?;
+
+            // Unacknowledged results are redelivered.
+            match record_result(&store, &message).await {
+                Ok(()) => {
+                    if let Err(e) = message.ack().await {
+                        warn!(error = %e, "can't acknowledge job result");
+                    }
+                }
+                Err(e) => warn!(error = %e, "can't record job result"),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Moves the job named in the result's `job` header out of pending. The result
+/// carries the backend's reply as it would have been sent to a `request`.
+async fn record_result(store: &Store, message: &Message) -> Result<(), BoxError> {
+    let header = |name: &str| {
+        message
+            .headers
+            .as_ref()
+            .and_then(|headers| headers.get(name))
+            .map(|value| value.to_string())
+    };
+
+    let Some(job_id) = header(JOB_HEADER) else {
+        warn!(subject = %message.subject, "job result without a job header, dropping it");
+        return Ok(());
+    };
+
+    let code = header("code")
+        .and_then(|code| code.parse().ok())
+        .filter(|code| StatusCode::from_u16(*code).is_ok())
+        .unwrap_or(500);
+
+    let Some(value) = store.get(&job_id).await? else {
+        warn!(
+            job = job_id,
+            "result for an unknown or expired job, dropping it"
+        );
+        return Ok(());
+    };
+
+    let mut record: JobRecord = rmp_serde::from_slice(&value)?;
+    record.finish(code, &message.payload);
+
+    store
+        .put(&job_id, Bytes::from(rmp_serde::to_vec_named(&record)?))
+        .await?;
+
+    info!(job = job_id, code, route = record.route, "job finished");
+
+    Ok(())
+}
+
+/// The job as a JSON:API `jobs` resource with a link to itself.
+pub fn job_response(status: StatusCode, job_id: &str, record: &JobRecord) -> Response {
+    let self_link = JOB_ROUTE.replace(":jid", job_id);
+
+    let status_name = match record.status {
+        JobStatus::Pending => "pending",
+        JobStatus::Done => "done",
+        JobStatus::Failed => "failed",
+    };
+
+    let mut resp = (
+        status,
+        Json(Job {
+            data: JobData {
+                id: job_id.to_string(),
+                r#type: "jobs".to_string(),
+                attributes: JobAttributes {
+                    status: status_name.to_string(),
+                    code: record.code,
+                    result: serde_json::from_slice(&record.result).ok(),
+                },
+                links: Links {
+                    self_link: self_link.clone(),
+                },
+            },
+        }),
+    )
+        .into_response();
+
+    resp.headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(JSON_API_TYPE));
+
+    if status == StatusCode::ACCEPTED {
+        if let Ok(location) = HeaderValue::from_str(&self_link) {
+            resp.headers_mut().insert(LOCATION, location);
+        }
+    }
+
+    resp
+}
+
+/// 202 for a request queued as a job.
+pub fn accepted(job_id: &str, record: &JobRecord) -> Response {
+    job_response(StatusCode::ACCEPTED, job_id, record)
+}
+
+/// Pending, done or failed with the backend's reply. 404 once the job has expired
+/// and for any bearer token other than the one the job was submitted with.
+pub async fn job_status(
+    matched_path: MatchedPath,
+    Path(mut user_values): Path<HashMap<String, String>>,
+    authorization: Option<TypedHeader<Authorization<Bearer>>>,
+    Extension(nats): Extension<SharedClient>,
+    Extension(jobs): Extension<Jobs>,
+    Extension(drain): Extension<SharedDrain>,
+    Extension(path_params): Extension<PathParams>,
+) -> Response {
+    let _in_flight = drain.track();
+
+    if !jobs.enabled() {
+        return not_found().await.into_response();
+    }
+
+    if let Some(resp) = path_params.reject(matched_path.as_str(), &mut user_values) {
+        return resp.into_response();
+    }
+
+    let Some(TypedHeader(Authorization(bearer))) = authorization else {
+        return not_found().await.into_response();
+    };
+
+    let client = nats.read().await;
+
+    let Some(client) = client.as_ref() else {
+        return create_error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "503",
+            "Service unavailable",
+            "The service is starting up, please retry later.",
+        )
+        .into_response();
+    };
+
+    let job_id = user_values.get("jid").map_or("", String::as_str);
+
+    match jobs.get(client, job_id).await {
+        Ok(Some(record)) if record.is_owned_by(bearer.token()) => {
+            job_response(StatusCode::OK, job_id, &record)
+        }
+        Ok(_) => not_found().await.into_response(),
+        Err(e) => {
+            error!(error = %e, job = job_id, "can't read job");
+
+            create_error_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "503",
+                "Service unavailable",
+                "The job could not be read, please retry later.",
+            )
+            .into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::RouteConf;
+
+    const PORTFOLIO: &str = "/api/v1/portfolios/:pid";
+
+    async fn body(resp: Response) -> serde_json::Value {
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[test]
+    fn test_only_writes_of_async_routes_are_jobs() {
+        let mut conf = Conf::default();
+        conf.routes.insert(
+            PORTFOLIO.to_string(),
+            RouteConf {
+                mode: RouteMode::Async,
+                ..Default::default()
+            },
+        );
+
+        let jobs = Jobs::from_conf(&conf);
+
+        assert!(jobs.enabled());
+        assert!(jobs.is_async(PORTFOLIO, &Method::DELETE));
+        assert!(jobs.is_async(PORTFOLIO, &Method::PATCH));
+        assert!(!jobs.is_async(PORTFOLIO, &Method::GET));
+        assert!(!jobs.is_async("/api/v1/portfolios", &Method::POST));
+        assert!(!Jobs::from_conf(&Conf::default()).enabled());
+    }
+
+    #[test]
+    fn test_finish() {
+        let mut record = JobRecord::pending(PORTFOLIO, &Method::DELETE, "alice-token");
+        record.finish(204, b"");
+        assert_eq!(record.status, JobStatus::Done);
+        assert_eq!(record.code, Some(204));
+
+        let mut record = JobRecord::pending(PORTFOLIO, &Method::DELETE, "alice-token");
+        record.finish(409, br#"{"errors":[{"code":"409"}]}"#);
+        assert_eq!(record.status, JobStatus::Failed);
+    }
+
+    #[test]
+    fn test_only_the_submitter_can_read_a_job() {
+        let record = JobRecord::pending(PORTFOLIO, &Method::DELETE, "alice-token");
+
+        assert!(record.is_owned_by("alice-token"));
+        assert!(!record.is_owned_by("bob-token"));
+        assert!(!record.is_owned_by(""));
+        assert_ne!(record.owner, b"alice-token");
+    }
+
+    #[test]
+    fn test_records_without_owner_are_not_readable() {
+        let record = JobRecord::pending(PORTFOLIO, &Method::DELETE, "alice-token");
+        let mut stored = serde_json::to_value(&record).unwrap();
+        stored.as_object_mut().unwrap().remove("owner");
+
+        let record: JobRecord = serde_json::from_value(stored).unwrap();
+        assert!(!record.is_owned_by("alice-token"));
+    }
+
+    #[tokio::test]
+    async fn test_accepted_links_the_job() {
+        let record = JobRecord::pending(PORTFOLIO, &Method::DELETE, "alice-token");
+        let resp = accepted("7c1d", &record);
+
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+        assert_eq!(resp.headers()[LOCATION], "/api/v1/jobs/7c1d");
+        assert_eq!(resp.headers()[CONTENT_TYPE], JSON_API_TYPE);
+
+        let body = body(resp).await;
+        assert_eq!(body["data"]["type"], "jobs");
+        assert_eq!(body["data"]["attributes"]["status"], "pending");
+        assert_eq!(body["data"]["links"]["self"], "/api/v1/jobs/7c1d");
+    }
+
+    #[tokio::test]
+    async fn test_finished_job_carries_the_result() {
+        let mut record = JobRecord::pending(PORTFOLIO, &Method::PATCH, "alice-token");
+        record.finish(200, br#"{"data":{"id":"42","type":"portfolios"}}"#);
+
+        let resp = job_response(StatusCode::OK, "7c1d", &record);
+
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert!(resp.headers().get(LOCATION).is_none());
+
+        let body = body(resp).await;
+        assert_eq!(body["data"]["attributes"]["status"], "done");
+        assert_eq!(body["data"]["attributes"]["code"], 200);
+        assert_eq!(body["data"]["attributes"]["result"]["data"]["id"], "42");
+    }
+}
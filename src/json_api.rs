@@ -0,0 +1,502 @@
+use std::collections::HashSet;
+
+use axum::extract::MatchedPath;
+use axum::http::header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, TRANSFER_ENCODING};
+use axum::http::{HeaderMap, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::conf::Conf;
+use crate::handlers::{create_errors_response, JSON_API_TYPE};
+use crate::imports::IMPORTS_ROUTE;
+use crate::reload::SharedLiveConf;
+use crate::responses::errors::{Error, ErrorSource};
+
+/// Routes the media type rules apply to, probes and metrics keep their own types.
+const API_PREFIX: &str = "/api/";
+
+/// A media type with a lowercased essence and parameter names, values are unquoted.
+#[derive(Debug, PartialEq)]
+struct MediaType {
+    essence: String,
+    params: Vec<(String, String)>,
+}
+
+impl MediaType {
+    fn parse(value: &str) -> Option<Self> {
+        let mut parts = split_unquoted(value, ';').into_iter();
+
+        let essence = parts.next()?.trim().to_ascii_lowercase();
+        let (type_, subtype) = essence.split_once('/')?;
+
+        if type_.is_empty() || subtype.is_empty() {
+            return None;
+        }
+
+        let mut params = vec![];
+
+        for part in parts.map(str::trim).filter(|part| !part.is_empty()) {
+            let (name, value) = part.split_once('=')?;
+            params.push((name.trim().to_ascii_lowercase(), unquote(value.trim())));
+        }
+
+        Some(MediaType { essence, params })
+    }
+
+    fn is_json_api(&self) -> bool {
+        self.essence == JSON_API_TYPE
+    }
+
+    /// Splits an `Accept` entry into the media type and its weight. Parameters from
+    /// `q` on are accept-params, not media type parameters.
+    fn split_weight(mut self) -> (Self, f32) {
+        match self.params.iter().position(|(name, _)| name == "q") {
+            Some(i) => {
+                let weight = self.params[i].1.parse().unwrap_or(1.0);
+                self.params.truncate(i);
+                (self, weight)
+            }
+            None => (self, 1.0),
+        }
+    }
+}
+
+/// Splits on `separator` outside of quoted strings.
+pub(crate) fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut parts = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                parts.push(&value[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+
+    parts.push(&value[start..]);
+    parts
+}
+
+pub(crate) fn unquote(value: &str) -> String {
+    match value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        Some(inner) => {
+            let mut unquoted = String::with_capacity(inner.len());
+            let mut chars = inner.chars();
+
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => unquoted.extend(chars.next()),
+                    c => unquoted.push(c),
+                }
+            }
+
+            unquoted
+        }
+        None => value.to_string(),
+    }
+}
+
+/// JSON:API media type negotiation resolved from `json_api`.
+#[derive(Debug, Clone, Default)]
+pub struct MediaTypePolicy {
+    strict: bool,
+    extensions: HashSet<String>,
+}
+
+impl MediaTypePolicy {
+    pub fn from_conf(conf: &Conf) -> Self {
+        MediaTypePolicy {
+            strict: conf.json_api.strict_media_type,
+            extensions: conf.json_api.extensions.iter().cloned().collect(),
+        }
+    }
+
+    /// Why a JSON:API media type can't be served, only `ext` and `profile` are
+    /// allowed and every `ext` URI must be supported. Unknown profiles are ignored.
+    fn unsupported(&self, media_type: &MediaType) -> Option<String> {
+        for (name, value) in &media_type.params {
+            match name.as_str() {
+                "profile" => {}
+                "ext" => {
+                    if let Some(uri) = value
+                        .split_ascii_whitespace()
+                        .find(|uri| !self.extensions.contains(*uri))
+                    {
+                        return Some(format!("The extension {uri:?} is not supported."));
+                    }
+                }
+                _ => {
+                    return Some(format!(
+                    "The media type parameter {name:?} is not allowed, only ext and profile are."
+                ))
+                }
+            }
+        }
+
+        None
+    }
+
+    fn check_content_type(&self, headers: &HeaderMap, multipart: bool) -> Result<(), String> {
+        let Some(value) = headers.get(CONTENT_TYPE) else {
+            let empty = !headers.contains_key(TRANSFER_ENCODING)
+                && headers
+                    .get(CONTENT_LENGTH)
+                    .is_none_or(|length| length == "0");
+
+            return if empty {
+                Ok(())
+            } else {
+                Err(format!("A request body must be sent as {JSON_API_TYPE}."))
+            };
+        };
+
+        let media_type = value.to_str().ok().and_then(MediaType::parse);
+
+        if multipart
+            && media_type
+                .as_ref()
+                .is_some_and(|media_type| media_type.essence == "multipart/form-data")
+        {
+            return Ok(());
+        }
+
+        let media_type = media_type
+            .filter(MediaType::is_json_api)
+            .ok_or_else(|| format!("A request body must be sent as {JSON_API_TYPE}."))?;
+
+        match self.unsupported(&media_type) {
+            Some(reason) => Err(reason),
+            None => Ok(()),
+        }
+    }
+
+    fn check_accept(&self, headers: &HeaderMap) -> Result<(), String> {
+        let accepted: Vec<(MediaType, f32)> = headers
+            .get_all(ACCEPT)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| split_unquoted(value, ','))
+            .filter_map(MediaType::parse)
+            .map(MediaType::split_weight)
+            .collect();
+
+        if accepted.is_empty() {
+            return Ok(());
+        }
+
+        let mut json_api = accepted
+            .iter()
+            .filter(|(media_type, _)| media_type.is_json_api())
+            .peekable();
+
+        // Instances with other parameters are ignored, it's an error only if none is left.
+        if json_api.peek().is_some() {
+            let mut reason = None;
+
+            for (media_type, weight) in json_api {
+                match self.unsupported(media_type) {
+                    None if *weight > 0.0 => return Ok(()),
+                    None => {}
+                    Some(unsupported) => {
+                        reason.get_or_insert(unsupported);
+                    }
+                }
+            }
+
+            return Err(reason.unwrap_or_else(|| format!("{JSON_API_TYPE} is not accepted.")));
+        }
+
+        let wildcard = accepted.iter().any(|(media_type, weight)| {
+            *weight > 0.0 && matches!(media_type.essence.as_str(), "*/*" | "application/*")
+        });
+
+        if wildcard {
+            Ok(())
+        } else {
+            Err(format!("Responses are only available as {JSON_API_TYPE}."))
+        }
+    }
+
+    /// Returns the error response for requests breaking the media type rules,
+    /// `Content-Type` is checked on POST and PATCH, `Accept` on every method.
+    /// `multipart` allows a `multipart/form-data` body for upload routes.
+    fn reject(&self, method: &Method, headers: &HeaderMap, multipart: bool) -> Option<Response> {
+        if !self.strict {
+            return None;
+        }
+
+        if matches!(*method, Method::POST | Method::PATCH) {
+            if let Err(detail) = self.check_content_type(headers, multipart) {
+                return Some(media_type_error(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    "Unsupported media type",
+                    "Content-Type",
+                    detail,
+                ));
+            }
+        }
+
+        if let Err(detail) = self.check_accept(headers) {
+            return Some(media_type_error(
+                StatusCode::NOT_ACCEPTABLE,
+                "Not acceptable",
+                "Accept",
+                detail,
+            ));
+        }
+
+        None
+    }
+}
+
+fn media_type_error(status: StatusCode, title: &str, header: &str, detail: String) -> Response {
+    create_errors_response(
+        status,
+        vec![Error {
+            code: status.as_u16().to_string(),
+            title: title.to_string(),
+            detail,
+            source: Some(ErrorSource {
+                header: Some(header.to_string()),
+                ..Default::default()
+            }),
+            meta: None,
+        }],
+    )
+    .into_response()
+}
+
+/// Enforces the JSON:API media type rules on `/api/` routes when `strict_media_type`
+/// is enabled.
+pub async fn check_media_types<B>(
+    req: Request<B>,
+    next: Next<B>,
+    live: SharedLiveConf,
+) -> Response {
+    if req.uri().path().starts_with(API_PREFIX) {
+        let multipart = req
+            .extensions()
+            .get::<MatchedPath>()
+            .is_some_and(|path| path.as_str() == IMPORTS_ROUTE);
+
+        if let Some(resp) = live
+            .snapshot()
+            .json_api
+            .reject(req.method(), req.headers(), multipart)
+        {
+            return resp;
+        }
+    }
+
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    const ATOMIC: &str = "https://jsonapi.org/ext/atomic";
+
+    fn policy() -> MediaTypePolicy {
+        let mut conf = Conf::default();
+        conf.json_api.strict_media_type = true;
+        conf.json_api.extensions = vec![ATOMIC.to_string()];
+        MediaTypePolicy::from_conf(&conf)
+    }
+
+    fn headers(entries: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in entries {
+            headers.append(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn status(method: Method, entries: &[(&'static str, &str)]) -> Option<StatusCode> {
+        policy()
+            .reject(&method, &headers(entries), false)
+            .map(|resp| resp.status())
+    }
+
+    #[test]
+    fn test_parse_media_type() {
+        let media_type =
+            MediaType::parse(r#"Application/VND.API+JSON; EXT="https://a.example/x https://b.example/\"y\""; profile=p"#)
+                .unwrap();
+
+        assert!(media_type.is_json_api());
+        assert_eq!(
+            media_type.params,
+            [
+                (
+                    "ext".to_string(),
+                    r#"https://a.example/x https://b.example/"y""#.to_string()
+                ),
+                ("profile".to_string(), "p".to_string()),
+            ]
+        );
+
+        assert_eq!(MediaType::parse("json"), None);
+        assert_eq!(MediaType::parse("application/"), None);
+        assert_eq!(MediaType::parse("application/json; charset"), None);
+    }
+
+    #[test]
+    fn test_split_unquoted_keeps_quoted_separators() {
+        assert_eq!(
+            split_unquoted(r#"a/b; ext="x,y", */*"#, ','),
+            [r#"a/b; ext="x,y""#, " */*"]
+        );
+    }
+
+    #[test]
+    fn test_content_type_rules() {
+        let json_api = ("content-type", JSON_API_TYPE);
+
+        assert_eq!(status(Method::POST, &[json_api]), None);
+        assert_eq!(
+            status(
+                Method::PATCH,
+                &[(
+                    "content-type",
+                    &format!(
+                        "{JSON_API_TYPE}; ext=\"{ATOMIC}\"; profile=\"https://example.com/p\""
+                    )
+                )]
+            ),
+            None
+        );
+        assert_eq!(status(Method::POST, &[]), None);
+        assert_eq!(
+            status(Method::POST, &[("content-length", "2")]),
+            Some(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        );
+        assert_eq!(
+            status(Method::POST, &[("content-type", "application/json")]),
+            Some(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        );
+        assert_eq!(
+            status(
+                Method::POST,
+                &[("content-type", &format!("{JSON_API_TYPE}; charset=utf-8"))]
+            ),
+            Some(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        );
+        assert_eq!(
+            status(
+                Method::POST,
+                &[(
+                    "content-type",
+                    &format!("{JSON_API_TYPE}; ext=https://example.com/x")
+                )]
+            ),
+            Some(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        );
+
+        // Only request bodies of POST and PATCH are checked.
+        assert_eq!(status(Method::GET, &[("content-type", "text/plain")]), None);
+    }
+
+    #[test]
+    fn test_accept_rules() {
+        assert_eq!(status(Method::GET, &[]), None);
+        assert_eq!(status(Method::GET, &[("accept", "*/*")]), None);
+        assert_eq!(status(Method::GET, &[("accept", "application/*")]), None);
+        assert_eq!(status(Method::GET, &[("accept", JSON_API_TYPE)]), None);
+        assert_eq!(
+            status(
+                Method::GET,
+                &[(
+                    "accept",
+                    &format!("{JSON_API_TYPE}; charset=utf-8, {JSON_API_TYPE}; ext=\"{ATOMIC}\"")
+                )]
+            ),
+            None
+        );
+        assert_eq!(
+            status(
+                Method::GET,
+                &[("accept", "text/html"), ("accept", "*/*;q=0.1")]
+            ),
+            None
+        );
+
+        assert_eq!(
+            status(Method::GET, &[("accept", "text/html, application/json")]),
+            Some(StatusCode::NOT_ACCEPTABLE)
+        );
+        assert_eq!(
+            status(
+                Method::GET,
+                &[("accept", &format!("{JSON_API_TYPE}; charset=utf-8, */*"))]
+            ),
+            Some(StatusCode::NOT_ACCEPTABLE)
+        );
+        assert_eq!(
+            status(
+                Method::GET,
+                &[(
+                    "accept",
+                    &format!("{JSON_API_TYPE}; ext=\"https://example.com/x\"")
+                )]
+            ),
+            Some(StatusCode::NOT_ACCEPTABLE)
+        );
+        assert_eq!(
+            status(
+                Method::GET,
+                &[("accept", &format!("{JSON_API_TYPE};q=0, */*"))]
+            ),
+            Some(StatusCode::NOT_ACCEPTABLE)
+        );
+        assert_eq!(
+            status(Method::GET, &[("accept", "*/*;q=0")]),
+            Some(StatusCode::NOT_ACCEPTABLE)
+        );
+    }
+
+    #[test]
+    fn test_accept_weight_is_not_a_media_type_param() {
+        assert_eq!(
+            status(
+                Method::GET,
+                &[("accept", &format!("{JSON_API_TYPE};q=0.5;level=1"))]
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_lenient_by_default() {
+        let headers = headers(&[("content-type", "text/plain"), ("accept", "text/html")]);
+
+        assert!(MediaTypePolicy::from_conf(&Conf::default())
+            .reject(&Method::POST, &headers, false)
+            .is_none());
+    }
+
+    #[test]
+    fn test_multipart_only_on_upload_routes() {
+        let headers = headers(&[("content-type", "multipart/form-data; boundary=x")]);
+
+        assert_eq!(
+            policy()
+                .reject(&Method::POST, &headers, false)
+                .map(|resp| resp.status()),
+            Some(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        );
+        assert!(policy().reject(&Method::POST, &headers, true).is_none());
+    }
+}
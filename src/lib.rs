@@ -1,11 +1,32 @@
 #![deny(warnings)]
 #![forbid(unsafe_code)]
 
+pub mod access_log;
+pub mod admin;
 pub mod conf;
+pub mod cors;
 pub mod events;
 pub mod handlers;
+pub mod imports;
+pub mod jobs;
+pub mod json_api;
+pub mod log_control;
+pub mod log_format;
+pub mod methods;
 pub mod metrics;
+pub mod multipart;
+pub mod nats;
 pub mod observability;
+pub mod otlp_metrics;
+pub mod panics;
+pub mod path_params;
+pub mod redact;
+pub mod reload;
+pub mod request_body;
+pub mod request_id;
 pub mod responses;
 pub mod routes;
+pub mod schemas;
+pub mod security_headers;
+pub mod shutdown;
 pub mod signals;
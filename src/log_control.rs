@@ -0,0 +1,292 @@
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::Duration;
+
+use tracing::{info, warn};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::conf::Conf;
+
+pub type FilterHandle = reload::Handle<EnvFilter, Registry>;
+
+static LOG_CONTROL: OnceLock<Arc<LogControl>> = OnceLock::new();
+
+/// Filter used when nothing was changed at runtime: `RUST_LOG`, then `log.filter`,
+/// then `debug` or `info` depending on `is_debug`.
+pub fn base_filter(conf: &Conf) -> String {
+    match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(rust_log) if !rust_log.trim().is_empty() => rust_log,
+        _ => conf.log.filter.clone().unwrap_or_else(|| {
+            if conf.is_debug {
+                "debug".to_string()
+            } else {
+                "info".to_string()
+            }
+        }),
+    }
+}
+
+pub fn install(control: Arc<LogControl>) {
+    if LOG_CONTROL.set(control).is_err() {
+        warn!("log control is already installed");
+    }
+}
+
+pub fn log_control() -> Option<Arc<LogControl>> {
+    LOG_CONTROL.get().cloned()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogFilterState {
+    pub base: String,
+    pub current: String,
+}
+
+#[derive(Debug)]
+struct State {
+    base: String,
+    current: String,
+    revert_after_minutes: u64,
+    /// Bumped on every change so a scheduled revert doesn't undo a newer change.
+    generation: u64,
+}
+
+/// Runtime control over the global `EnvFilter`, directives can target modules
+/// (`http2::handlers=debug`) or routes through the request span
+/// (`[http_request{route=/api/v1/plans}]=debug`).
+#[derive(Debug)]
+pub struct LogControl {
+    handle: FilterHandle,
+    state: Mutex<State>,
+}
+
+impl LogControl {
+    pub fn new(handle: FilterHandle, base: String, revert_after_minutes: u64) -> Self {
+        LogControl {
+            handle,
+            state: Mutex::new(State {
+                current: base.clone(),
+                base,
+                revert_after_minutes,
+                generation: 0,
+            }),
+        }
+    }
+
+    pub fn state(&self) -> LogFilterState {
+        let state = self.lock();
+
+        LogFilterState {
+            base: state.base.clone(),
+            current: state.current.clone(),
+        }
+    }
+
+    /// Applies `directives` until reset. `revert_after_minutes` overrides the configured
+    /// revert delay, 0 keeps the filter until it is changed again.
+    pub fn set_filter(
+        self: &Arc<Self>,
+        directives: &str,
+        revert_after_minutes: Option<u64>,
+    ) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+
+        let mut state = self.lock();
+
+        self.handle.reload(filter).map_err(|e| e.to_string())?;
+
+        state.current = directives.to_string();
+        state.generation += 1;
+
+        let revert_after = revert_after_minutes.unwrap_or(state.revert_after_minutes);
+
+        info!(
+            filter = directives,
+            revert_after_minutes = revert_after,
+            "log filter changed"
+        );
+
+        if revert_after > 0 {
+            self.schedule_revert(
+                state.generation,
+                Duration::from_secs(revert_after.saturating_mul(60)),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns to the base filter.
+    pub fn reset(&self) {
+        let mut state = self.lock();
+
+        self.apply_base(&mut state);
+    }
+
+    /// Replaces the base filter, e.g. after a config reload. A filter changed at
+    /// runtime stays active until it is reset or reverted.
+    pub fn set_base(&self, base: String, revert_after_minutes: u64) {
+        let mut state = self.lock();
+        let overridden = state.current != state.base;
+
+        state.base = base;
+        state.revert_after_minutes = revert_after_minutes;
+
+        if !overridden {
+            self.apply_base(&mut state);
+        }
+    }
+
+    fn apply_base(&self, state: &mut State) {
+        match EnvFilter::try_new(&state.base) {
+            Ok(filter) => match self.handle.reload(filter) {
+                Ok(()) => {
+                    state.current = state.base.clone();
+                    state.generation += 1;
+
+                    info!(filter = %state.base, "log filter reset");
+                }
+                Err(e) => warn!(error = %e, "failed to reset log filter"),
+            },
+            Err(e) => warn!(error = %e, filter = %state.base, "invalid base log filter"),
+        }
+    }
+
+    fn schedule_revert(self: &Arc<Self>, generation: u64, after: Duration) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            warn!("no runtime to schedule the log filter revert");
+            return;
+        };
+
+        let control = self.clone();
+
+        runtime.spawn(async move {
+            tokio::time::sleep(after).await;
+
+            let mut state = control.lock();
+
+            if state.generation == generation {
+                control.apply_base(&mut state);
+            }
+        });
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn control(revert_after_minutes: u64) -> (reload::Layer<EnvFilter, Registry>, Arc<LogControl>) {
+        let (layer, handle) = reload::Layer::new(EnvFilter::new("info"));
+
+        (
+            layer,
+            Arc::new(LogControl::new(
+                handle,
+                "info".to_string(),
+                revert_after_minutes,
+            )),
+        )
+    }
+
+    #[test]
+    fn test_set_filter_and_reset() {
+        let (_layer, control) = control(0);
+
+        control
+            .set_filter("info,http2::handlers=debug", None)
+            .expect("should set filter");
+        assert_eq!(control.state().current, "info,http2::handlers=debug");
+        assert_eq!(control.state().base, "info");
+
+        control.reset();
+        assert_eq!(control.state().current, "info");
+    }
+
+    #[test]
+    fn test_set_filter_accepts_route_span_directive() {
+        let (_layer, control) = control(0);
+
+        assert!(control
+            .set_filter("info,[http_request{route=/api/v1/plans}]=debug", None)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_set_filter_rejects_invalid_directive() {
+        let (_layer, control) = control(0);
+
+        assert!(control.set_filter("http2=loud", None).is_err());
+        assert_eq!(control.state().current, "info");
+    }
+
+    #[test]
+    fn test_set_base_keeps_runtime_override() {
+        let (_layer, control) = control(0);
+
+        control.set_base("warn".to_string(), 0);
+        assert_eq!(control.state().current, "warn");
+
+        control
+            .set_filter("debug", None)
+            .expect("should set filter");
+        control.set_base("error".to_string(), 0);
+
+        assert_eq!(
+            control.state(),
+            LogFilterState {
+                base: "error".to_string(),
+                current: "debug".to_string(),
+            }
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_filter_reverts_after_timeout() {
+        let (_layer, control) = control(5);
+
+        control
+            .set_filter("debug", None)
+            .expect("should set filter");
+
+        tokio::time::sleep(Duration::from_secs(4 * 60)).await;
+        assert_eq!(control.state().current, "debug");
+
+        tokio::time::sleep(Duration::from_secs(2 * 60)).await;
+        assert_eq!(control.state().current, "info");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_newer_change_cancels_pending_revert() {
+        let (_layer, control) = control(1);
+
+        control
+            .set_filter("debug", None)
+            .expect("should set filter");
+        control
+            .set_filter("trace", Some(0))
+            .expect("should set filter");
+
+        tokio::time::sleep(Duration::from_secs(2 * 60)).await;
+        assert_eq!(control.state().current, "trace");
+    }
+
+    #[test]
+    fn test_base_filter_follows_conf() {
+        if std::env::var(EnvFilter::DEFAULT_ENV).is_ok() {
+            return;
+        }
+
+        let mut conf = Conf::default();
+        assert_eq!(base_filter(&conf), "info");
+
+        conf.is_debug = true;
+        assert_eq!(base_filter(&conf), "debug");
+
+        conf.log.filter = Some("warn,http2=info".to_string());
+        assert_eq!(base_filter(&conf), "warn,http2=info");
+    }
+}
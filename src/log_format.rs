@@ -0,0 +1,304 @@
+use opentelemetry::trace::{SpanId, TraceContextExt, TraceId, TracerProvider as _};
+use opentelemetry_sdk::trace::{Tracer, TracerProvider};
+use serde_json::{Map, Value};
+use std::fmt;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_opentelemetry::{OpenTelemetryLayer, OtelData};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+pub const REQUEST_ID: &str = "request_id";
+pub const ROUTE: &str = "route";
+
+/// Span layer that assigns trace and span ids, no spans are exported.
+pub fn otel_layer<S>() -> OpenTelemetryLayer<S, Tracer>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let tracer = TracerProvider::builder().build().tracer("http2");
+
+    tracing_opentelemetry::layer().with_tracer(tracer)
+}
+
+/// The correlation fields a span carries, inherited by every event inside it.
+#[derive(Default)]
+struct Correlation {
+    request_id: Option<String>,
+    route: Option<String>,
+}
+
+impl Correlation {
+    fn slot(&mut self, field: &Field) -> Option<&mut Option<String>> {
+        match field.name() {
+            REQUEST_ID => Some(&mut self.request_id),
+            ROUTE => Some(&mut self.route),
+            _ => None,
+        }
+    }
+}
+
+impl Visit for Correlation {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if let Some(slot) = self.slot(field) {
+            *slot = Some(value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if let Some(slot) = self.slot(field) {
+            *slot = Some(format!("{:?}", value));
+        }
+    }
+}
+
+/// Keeps the `request_id` and `route` span fields, including ones recorded after the span was created.
+pub struct CorrelationLayer;
+
+impl<S> Layer<S> for CorrelationLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            let mut correlation = Correlation::default();
+
+            attrs.record(&mut correlation);
+
+            span.extensions_mut().insert(correlation);
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        if let Some(span) = ctx.span(id) {
+            if let Some(correlation) = span.extensions_mut().get_mut::<Correlation>() {
+                values.record(correlation);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct JsonFields(Map<String, Value>);
+
+impl JsonFields {
+    fn insert(&mut self, field: &Field, value: Value) {
+        // Metadata of bridged `log` records, already reported as the event's own metadata.
+        if !field.name().starts_with("log.") {
+            self.0.insert(field.name().to_string(), value);
+        }
+    }
+}
+
+impl Visit for JsonFields {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, Value::from(value));
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.insert(field, Value::from(value.to_string()));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, Value::from(format!("{:?}", value)));
+    }
+}
+
+/// One JSON object per line with `request_id`, `route`, `trace_id` and `span_id` at the top level.
+pub struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let normalized = event.normalized_metadata();
+        let meta = normalized.as_ref().unwrap_or_else(|| event.metadata());
+
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+
+        let mut fields = JsonFields::default();
+        event.record(&mut fields);
+
+        let mut request_id = fields.0.remove(REQUEST_ID);
+        let mut route = fields.0.remove(ROUTE);
+
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope {
+                if let Some(correlation) = span.extensions().get::<Correlation>() {
+                    if request_id.is_none() {
+                        request_id = correlation.request_id.clone().map(Value::from);
+                    }
+                    if route.is_none() {
+                        route = correlation.route.clone().map(Value::from);
+                    }
+                }
+            }
+        }
+
+        let mut line = Map::new();
+
+        line.insert("timestamp".to_string(), Value::from(timestamp));
+        line.insert("level".to_string(), Value::from(meta.level().as_str()));
+        line.insert("target".to_string(), Value::from(meta.target()));
+
+        if let Some(request_id) = request_id {
+            line.insert(REQUEST_ID.to_string(), request_id);
+        }
+        if let Some(route) = route {
+            line.insert(ROUTE.to_string(), route);
+        }
+
+        if let Some(span) = ctx.parent_span() {
+            if let Some(otel) = span.extensions().get::<OtelData>() {
+                let trace_id = otel
+                    .builder
+                    .trace_id
+                    .unwrap_or_else(|| otel.parent_cx.span().span_context().trace_id());
+
+                if trace_id != TraceId::INVALID {
+                    line.insert("trace_id".to_string(), Value::from(trace_id.to_string()));
+                }
+                if let Some(span_id) = otel.builder.span_id.filter(|id| *id != SpanId::INVALID) {
+                    line.insert("span_id".to_string(), Value::from(span_id.to_string()));
+                }
+            }
+        }
+
+        line.insert("fields".to_string(), Value::Object(fields.0));
+
+        writeln!(writer, "{}", Value::Object(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::Empty;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn capture(f: impl FnOnce()) -> Vec<Value> {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+
+        let subscriber = tracing_subscriber::registry()
+            .with(otel_layer())
+            .with(CorrelationLayer)
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .event_format(JsonFormat)
+                    .with_writer(move || writer.clone()),
+            );
+
+        tracing::subscriber::with_default(subscriber, f);
+
+        let output = buffer.0.lock().unwrap().clone();
+
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_event_inherits_correlation_fields_from_spans() {
+        let lines = capture(|| {
+            let root =
+                tracing::info_span!("http_request", route = "/api/v1/users", request_id = Empty);
+            let _root = root.enter();
+
+            root.record(REQUEST_ID, "req-1");
+
+            let child = tracing::info_span!("proxy");
+            let _child = child.enter();
+
+            tracing::info!(status = 200, "NATS response received");
+        });
+
+        let line = &lines[0];
+
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line[REQUEST_ID], "req-1");
+        assert_eq!(line[ROUTE], "/api/v1/users");
+        assert_eq!(line["trace_id"].as_str().unwrap().len(), 32);
+        assert_eq!(line["span_id"].as_str().unwrap().len(), 16);
+        assert_eq!(line["fields"]["message"], "NATS response received");
+        assert_eq!(line["fields"]["status"], 200);
+    }
+
+    #[test]
+    fn test_child_spans_share_the_trace_id() {
+        let lines = capture(|| {
+            let root = tracing::info_span!("http_request");
+            let _root = root.enter();
+
+            tracing::info!("in root");
+
+            let child = tracing::info_span!("proxy");
+            let _child = child.enter();
+
+            tracing::info!("in child");
+        });
+
+        assert_eq!(lines[0]["trace_id"], lines[1]["trace_id"]);
+        assert_ne!(lines[0]["span_id"], lines[1]["span_id"]);
+    }
+
+    #[test]
+    fn test_event_fields_take_precedence_outside_spans() {
+        let lines = capture(|| {
+            tracing::warn!(request_id = "req-2", "NATS is not connected yet");
+        });
+
+        let line = &lines[0];
+
+        assert_eq!(line[REQUEST_ID], "req-2");
+        assert!(line.get("trace_id").is_none());
+        assert!(line["fields"].get(REQUEST_ID).is_none());
+    }
+}
@@ -2,90 +2,276 @@
 #![forbid(unsafe_code)]
 
 use futures::SinkExt;
-use log::warn;
+use libc::{SIGHUP, SIGUSR1, SIGUSR2};
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
+use tokio::sync::{Notify, RwLock};
+use tracing::{error, info, warn};
 
-use crate::conf::Conf;
+use crate::admin::AdminAccess;
+use crate::conf::{ignored_env_vars, CliArgs, Conf};
+use crate::jobs::Jobs;
+use crate::log_control::log_control;
+use crate::metrics::CountConnections;
+use crate::nats::{connect_with_retry, spawn_connect, spawn_retention_check, SharedClient};
 use crate::observability::{init_observability, shutdown_observability};
-use crate::routes::build_routes;
-use crate::signals::listen_signals;
+use crate::reload::{Reloader, SharedLiveConf};
+use crate::routes::{build_admin_routes, build_routes};
+use crate::schemas::RequestSchemas;
+use crate::shutdown::SharedDrain;
+use crate::signals::{listen_signal, listen_signals};
 
+mod access_log;
+mod admin;
 mod conf;
+mod cors;
 mod events;
 mod handlers;
+mod imports;
+mod jobs;
+mod json_api;
+mod log_control;
+mod log_format;
+mod methods;
 mod metrics;
+mod multipart;
+mod nats;
 mod observability;
+mod otlp_metrics;
+mod panics;
+mod path_params;
+mod redact;
+mod reload;
+mod request_body;
+mod request_id;
 mod responses;
 mod routes;
+mod schemas;
+mod security_headers;
+mod shutdown;
 mod signals;
 
+/// How long cut-off connections get to send their 503 and close.
+const CUT_OFF_GRACE: Duration = Duration::from_secs(1);
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Removed json_env_logger2::panic_hook() to avoid logger conflicts
-    // Panic handling is now done by tracing_subscriber
-
-    // Initialize observability (tracing and metrics) - this replaces json_env_logger2
-    let metrics = match init_observability() {
-        Ok(metrics) => Some(metrics),
-        Err(e) => {
-            // Use println! for early error logging since tracing may not be initialized
-            println!("ERROR: Failed to initialize observability: {}", e);
-            eprintln!("ERROR: Failed to initialize observability: {}", e);
-            None
-        }
+    let args = match CliArgs::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(err) => exit_before_configured(2, "invalid command line arguments", &err),
     };
 
-    let conf = match Conf::new() {
+    let conf = match Conf::load(&args, std::env::vars()) {
         Ok(conf) => conf,
-        Err(err) => {
-            warn!("failed to load configuration, {}", err);
+        Err(err) => exit_before_configured(1, "failed to load configuration", &err),
+    };
+
+    if args.print_config {
+        match conf.to_redacted_json() {
+            Ok(json) => {
+                println!("{}", json);
 
-            std::process::exit(1);
+                return Ok(());
+            }
+            Err(err) => exit_before_configured(1, "failed to render configuration", &err),
         }
+    }
+
+    let schemas = match RequestSchemas::load(&conf) {
+        Ok(schemas) => schemas,
+        Err(err) => exit_before_configured(1, "failed to load request schemas", &err),
     };
 
-    // Log level is now controlled by tracing_subscriber in init_observability()
-    if conf.is_debug {
-        println!("DEBUG: Debug mode enabled");
+    let metrics = init_observability(&conf).ok();
+
+    info!(
+        version = env!("CARGO_PKG_VERSION"),
+        listen_port = conf.listen_port,
+        "server starting"
+    );
+
+    for name in ignored_env_vars(std::env::vars()) {
+        warn!(var = name, "env var names no config key, ignoring it");
+    }
+
+    let nats_client: SharedClient = Arc::new(RwLock::new(None));
+
+    if conf.nats.lazy_connect {
+        spawn_connect(conf.nats.clone(), nats_client.clone());
+    } else {
+        *nats_client.write().await = Some(connect_with_retry(&conf.nats).await?);
+    }
+
+    let jobs = Jobs::from_conf(&conf);
+
+    if jobs.enabled() {
+        jobs.spawn_results(nats_client.clone());
+        spawn_retention_check(
+            conf.jobs.subject.clone(),
+            Duration::from_secs(conf.jobs.stream_max_age_secs),
+            nats_client.clone(),
+        );
+    }
+
+    if conf.request_body.object_store_bucket.is_some() {
+        spawn_retention_check(
+            conf.imports.subject.clone(),
+            Duration::from_secs(conf.imports.stream_max_age_secs),
+            nats_client.clone(),
+        );
     }
 
-    let nats_client = Arc::new(RwLock::new(
-        async_nats::ConnectOptions::new()
-            .ping_interval(std::time::Duration::from_secs(10))
-            .request_timeout(Some(std::time::Duration::from_secs(10)))
-            .connect(conf.nats.host)
-            .await?,
-    ));
+    let live = SharedLiveConf::new(&conf);
+
+    let drain = SharedDrain::default();
+
+    let routes = build_routes(
+        &conf,
+        live.clone(),
+        drain.clone(),
+        nats_client.clone(),
+        metrics.clone(),
+        schemas,
+    );
+
+    let admin_server = match &conf.admin.listener {
+        Some(listener) => {
+            let admin_routes = build_admin_routes(
+                &conf,
+                AdminAccess::from_conf(listener)?,
+                drain.clone(),
+                nats_client.clone(),
+                metrics.clone(),
+            );
+
+            info!(addr = %listener.addr(), "admin listener starting");
+
+            let server = axum::Server::try_bind(&listener.addr())?
+                .serve(admin_routes.into_make_service_with_connect_info::<SocketAddr>());
+
+            Some(tokio::task::spawn(server))
+        }
+        None => None,
+    };
+
+    let reloader = Reloader::new(args, conf.clone(), live);
+
+    listen_signal(SIGHUP, move || reloader.spawn());
 
-    let routes = build_routes(conf.allowed_origins, conf.enable_cors, nats_client.clone(), metrics.clone());
+    listen_signal(SIGUSR1, || {
+        if let Some(control) = log_control() {
+            if let Err(e) = control.set_filter("debug", None) {
+                error!(error = %e, "failed to enable debug logging");
+            }
+        }
+    });
+
+    listen_signal(SIGUSR2, || {
+        if let Some(control) = log_control() {
+            control.reset();
+        }
+    });
 
     let notify = listen_signals();
 
-    let server_shutdown_notify = notify.clone();
+    let shutdown_requested = notify.notified();
+    tokio::pin!(shutdown_requested);
+
+    let stop_accepting = Arc::new(Notify::new());
+
+    let server_stop_accepting = stop_accepting.clone();
 
     let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), conf.listen_port);
 
     let server = axum::Server::bind(&addr)
-        .serve(routes.into_make_service())
+        .serve(CountConnections::new(
+            routes.into_make_service_with_connect_info::<SocketAddr>(),
+            metrics.as_deref(),
+        ))
         .with_graceful_shutdown(async move {
-            server_shutdown_notify.notified().await;
+            server_stop_accepting.notified().await;
 
-            log::info!("server received shutdown signal")
+            info!("server stopped accepting connections")
         });
 
-    let result = tokio::try_join!(tokio::task::spawn(server),);
+    let mut server = tokio::task::spawn(server);
+
+    let mut cut_off = 0;
+
+    tokio::select! {
+        _ = &mut shutdown_requested => {
+            drain.start_draining();
+
+            info!(
+                pre_stop_delay_ms = conf.shutdown.pre_stop_delay_ms,
+                "server received shutdown signal, reporting not ready"
+            );
+
+            tokio::time::sleep(Duration::from_millis(conf.shutdown.pre_stop_delay_ms)).await;
+
+            stop_accepting.notify_one();
+
+            let drain_timeout = Duration::from_millis(conf.shutdown.drain_timeout_ms);
+
+            match tokio::time::timeout(drain_timeout, &mut server).await {
+                Ok(Ok(Ok(()))) => info!("all connections drained"),
+                Ok(Ok(Err(e))) => error!(error = %e, "server error"),
+                Ok(Err(e)) => error!(error = %e, "thread join error"),
+                Err(_) => {
+                    cut_off = drain.in_flight();
+
+                    warn!(
+                        drain_timeout_ms = conf.shutdown.drain_timeout_ms,
+                        in_flight = cut_off,
+                        "drain timeout reached, cutting off requests"
+                    );
 
-    match result {
-        Ok(_) => log::info!("shutdown completed"),
-        Err(e) => log::error!("thread join error {}", e),
+                    // Aborting the server task leaves hyper's connection tasks running,
+                    // so the handlers are ended and the connections close on their own.
+                    drain.cut_off();
+
+                    if tokio::time::timeout(CUT_OFF_GRACE, &mut server).await.is_err() {
+                        warn!("connections still open after the cut-off, aborting the server");
+
+                        server.abort();
+                    }
+                }
+            }
+        }
+        result = &mut server => match result {
+            Ok(Ok(())) => warn!("server exited before a shutdown signal"),
+            Ok(Err(e)) => error!(error = %e, "server error"),
+            Err(e) => error!(error = %e, "thread join error"),
+        },
     }
 
-    nats_client.write().await.close().await?;
+    // Kept until the public port is drained so probes keep reporting not ready
+    if let Some(admin_server) = admin_server {
+        admin_server.abort();
+    }
+
+    if let Some(mut client) = nats_client.write().await.take() {
+        if let Err(e) = client.flush().await {
+            error!(error = %e, "failed to flush NATS client");
+        }
+
+        client.close().await?;
+    }
+
+    info!(cut_off, "shutdown completed");
 
     // Shutdown observability
-    shutdown_observability();
+    shutdown_observability(metrics).await;
 
     Ok(())
 }
+
+/// Reports a failure that happens before the configuration is known through the default log pipeline.
+fn exit_before_configured(code: i32, message: &str, err: &dyn std::fmt::Display) -> ! {
+    let _ = init_observability(&Conf::default());
+
+    error!(error = %err, "{}", message);
+
+    std::process::exit(code)
+}
@@ -0,0 +1,116 @@
+use std::sync::Arc;
+
+use axum::body::{boxed, Empty};
+use axum::handler::Handler;
+use axum::http::header::ALLOW;
+use axum::http::{HeaderValue, Method, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{MethodFilter, MethodRouter};
+
+use crate::handlers::create_error_response;
+
+/// Routes `allowed` to `handler`, see `with_allow` for the other methods.
+pub fn methods<H, T, B>(allowed: &[Method], handler: H) -> MethodRouter<B>
+where
+    H: Handler<T, B>,
+    T: 'static,
+    B: Send + 'static,
+{
+    let filter = allowed
+        .iter()
+        .filter_map(|method| MethodFilter::try_from(method.clone()).ok())
+        .fold(MethodFilter::empty(), |filter, method| filter | method);
+
+    with_allow(axum::routing::on(filter, handler), allowed)
+}
+
+/// Answers the methods `router` has no handler for: OPTIONS with a 204, anything else
+/// with a JSON:API 405, both listing `allowed` in `Allow`. `allowed` must match the
+/// router, axum only fills in `Allow` on routes without layers.
+pub fn with_allow<B>(router: MethodRouter<B>, allowed: &[Method]) -> MethodRouter<B>
+where
+    B: Send + 'static,
+{
+    let allow = allow_header(allowed);
+
+    router.fallback(
+        (move |method: Method| {
+            let allow = allow.clone();
+
+            async move { method_not_allowed(&method, &allow) }
+        })
+        .into_service(),
+    )
+}
+
+fn method_not_allowed(method: &Method, allow: &Arc<HeaderValue>) -> Response {
+    let mut resp = if method == Method::OPTIONS {
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        create_error_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "405",
+            "Method not allowed",
+            &format!("The method {method} is not allowed on this resource."),
+        )
+        .into_response()
+    };
+
+    resp.headers_mut().insert(ALLOW, allow.as_ref().clone());
+
+    resp
+}
+
+/// Drops the body of HEAD responses to `GET` routes, keeping `Content-Length`. Like
+/// `Allow`, the router only does it for routes without layers.
+pub async fn strip_head_body<B>(req: Request<B>, next: Next<B>) -> Response {
+    let head = req.method() == Method::HEAD;
+
+    let resp = next.run(req).await;
+
+    if head {
+        resp.map(|_| boxed(Empty::new()))
+    } else {
+        resp
+    }
+}
+
+/// `allowed` plus `HEAD` for `GET`, which the router serves with the `GET` handler,
+/// and `OPTIONS`, which is answered here.
+fn allow_header(allowed: &[Method]) -> Arc<HeaderValue> {
+    let mut methods: Vec<&str> = vec![];
+
+    for method in allowed {
+        methods.push(method.as_str());
+
+        if method == Method::GET && !allowed.contains(&Method::HEAD) {
+            methods.push(Method::HEAD.as_str());
+        }
+    }
+
+    if !allowed.contains(&Method::OPTIONS) {
+        methods.push(Method::OPTIONS.as_str());
+    }
+
+    // Method names are tokens, always valid in a header value.
+    Arc::new(HeaderValue::from_str(&methods.join(", ")).unwrap_or(HeaderValue::from_static("")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allow_header() {
+        assert_eq!(
+            *allow_header(&[Method::GET, Method::PATCH, Method::DELETE]),
+            "GET, HEAD, PATCH, DELETE, OPTIONS"
+        );
+        assert_eq!(*allow_header(&[Method::POST]), "POST, OPTIONS");
+        assert_eq!(
+            *allow_header(&[Method::GET, Method::HEAD, Method::OPTIONS]),
+            "GET, HEAD, OPTIONS"
+        );
+    }
+}
@@ -1,56 +1,209 @@
+use axum::body::{boxed, Body, BoxBody, Bytes};
+use axum::extract::MatchedPath;
+use axum::http::{header, HeaderMap, Method, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use futures::future::BoxFuture;
+use futures::TryStreamExt;
+use http_body::SizeHint;
 use metrics::{Counter, Gauge, Histogram};
-use metrics_exporter_prometheus::PrometheusBuilder;
-use std::time::Duration;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use metrics_util::layers::FanoutBuilder;
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use std::collections::HashSet;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
+use tower::Service;
+
+use crate::access_log::UNMATCHED_ROUTE;
+use crate::conf::MetricsConf;
+use crate::otlp_metrics::otlp_pipeline;
+use crate::reload::SharedLiveConf;
+
+/// Label used once `MAX_LABEL_SETS` is reached, and for non-standard methods.
+pub const OVERFLOW_LABEL: &str = "other";
+
+/// Upper bound of distinct `method`/`route` and `method`/`route`/`status` label sets.
+pub const MAX_LABEL_SETS: usize = 500;
+
+pub(crate) const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+pub(crate) const SIZE_BUCKETS: &[f64] = &[
+    128.0, 512.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0,
+];
+
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
 
 #[derive(Debug)]
 pub struct AppMetrics {
+    handle: Option<PrometheusHandle>,
+    otlp: Option<SdkMeterProvider>,
+    labels: LabelGuard,
+
     // HTTP metrics
-    pub http_requests_total: Counter,
-    pub http_request_duration: Histogram,
-    #[allow(dead_code)]
     pub http_active_connections: Gauge,
 
     // NATS metrics
+    pub nats_pending_requests: Gauge,
     pub nats_requests_total: Counter,
     pub nats_request_duration: Histogram,
     pub nats_errors_total: Counter,
 }
 
 impl AppMetrics {
-    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let builder = PrometheusBuilder::new();
-        let (recorder, _handle) = builder.build()?;
-        metrics::set_global_recorder(recorder)?;
+    pub fn new(conf: &MetricsConf) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut fanout = FanoutBuilder::default();
+        let mut handle = None;
+        let mut otlp = None;
+
+        if conf.prometheus {
+            let recorder = builder()?.build_recorder();
+            let upkeep = recorder.handle();
+
+            handle = Some(recorder.handle());
+            fanout = fanout.add_recorder(recorder);
+
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+
+                loop {
+                    interval.tick().await;
+                    upkeep.run_upkeep();
+                }
+            });
+        }
+
+        if let Some(otlp_conf) = &conf.otlp {
+            let (recorder, provider) = otlp_pipeline(otlp_conf)?;
+
+            fanout = fanout.add_recorder(recorder);
+            otlp = Some(provider);
+        }
+
+        metrics::set_global_recorder(fanout.build())?;
 
         Ok(Self {
+            otlp,
+            ..Self::with_handle(handle)
+        })
+    }
+
+    /// Registers the metrics with whichever recorder is current.
+    fn with_handle(handle: Option<PrometheusHandle>) -> Self {
+        Self {
+            handle,
+            otlp: None,
+            labels: LabelGuard::new(MAX_LABEL_SETS),
+
             // HTTP metrics
-            http_requests_total: metrics::counter!("http_requests_total"),
-            http_request_duration: metrics::histogram!("http_request_duration_seconds"),
             http_active_connections: metrics::gauge!("http_active_connections"),
 
             // NATS metrics
+            nats_pending_requests: metrics::gauge!("nats_pending_requests"),
             nats_requests_total: metrics::counter!("nats_requests_total"),
             nats_request_duration: metrics::histogram!("nats_request_duration_seconds"),
             nats_errors_total: metrics::counter!("nats_errors_total"),
-        })
+        }
     }
 
-    pub async fn render(&self) -> Result<String, Box<dyn std::error::Error>> {
-        // The handle is a Future that serves metrics, not directly renderable
-        // For now, return empty metrics data as this is complex to implement correctly
-        Ok("# HELP http_requests_total Total HTTP requests\n# TYPE http_requests_total counter\nhttp_requests_total 0\n".to_string())
+    pub fn prometheus_enabled(&self) -> bool {
+        self.handle.is_some()
+    }
+
+    /// The Prometheus text format, `None` when only OTLP export is enabled.
+    pub fn render(&self) -> Option<String> {
+        self.handle.as_ref().map(PrometheusHandle::render)
+    }
+
+    /// Exports what was recorded since the last OTLP export and stops the exporter.
+    pub fn shutdown(&self) {
+        if let Some(provider) = &self.otlp {
+            if let Err(e) = provider.shutdown() {
+                tracing::error!(error = %e, "failed to flush OTLP metrics");
+            }
+        }
     }
 
     pub fn record_http_request(
         &self,
-        _method: &str,
-        _route: &str,
-        _status: u16,
+        method: &Method,
+        route: &str,
+        status: u16,
         duration: Duration,
     ) {
-        // Simplified metrics recording without complex labels for now
-        self.http_requests_total.increment(1);
-        self.http_request_duration.record(duration.as_secs_f64());
+        let (counter_method, counter_route, status) =
+            self.labels.admit_with_status(method, route, status);
+
+        metrics::counter!(
+            "http_requests_total",
+            "method" => counter_method,
+            "route" => counter_route,
+            "status" => status,
+        )
+        .increment(1);
+
+        let (method, route) = self.labels.admit(method, route);
+
+        metrics::histogram!(
+            "http_request_duration_seconds",
+            "method" => method,
+            "route" => route,
+        )
+        .record(duration.as_secs_f64());
+    }
+
+    /// Counts the request as in flight until the returned guard is dropped.
+    pub fn track_in_flight(&self, method: &Method, route: &str) -> GaugeGuard {
+        let (_, route) = self.labels.admit(method, route);
+
+        GaugeGuard::new(metrics::gauge!("http_in_flight_requests", "route" => route))
+    }
+
+    /// Records the size of the request body once it's dropped, counting the chunks as
+    /// they are read so chunked bodies are measured too.
+    pub fn count_request_body(&self, method: &Method, route: &str, body: Body) -> Body {
+        let (_, route) = self.labels.admit(method, route);
+        let histogram = metrics::histogram!("http_request_body_size_bytes", "route" => route);
+
+        count_request_body(body, move |bytes| histogram.record(bytes as f64))
+    }
+
+    /// Records the size of the response body once it's sent or dropped.
+    pub fn count_response_body(&self, method: &Method, route: &str, body: BoxBody) -> BoxBody {
+        let (_, route) = self.labels.admit(method, route);
+        let histogram = metrics::histogram!("http_response_body_size_bytes", "route" => route);
+
+        boxed(CountedBody::new(body, move |bytes| {
+            histogram.record(bytes as f64)
+        }))
+    }
+
+    pub fn record_body_validation(&self, method: &Method, route: &str, valid: bool) {
+        let (method, route) = self.labels.admit(method, route);
+
+        metrics::counter!(
+            "http_request_validations_total",
+            "method" => method,
+            "route" => route,
+            "result" => if valid { "valid" } else { "invalid" },
+        )
+        .increment(1);
+    }
+
+    pub fn record_panic(&self, method: &Method, route: &str) {
+        let (method, route) = self.labels.admit(method, route);
+
+        metrics::counter!("panics_total", "method" => method, "route" => route).increment(1);
+    }
+
+    pub fn record_cors_rejection(&self, route: &str) {
+        let (_, route) = self.labels.admit(&Method::OPTIONS, route);
+
+        metrics::counter!("http_cors_rejections_total", "route" => route).increment(1);
     }
 
     pub fn record_nats_request(&self, _subject: &str, success: bool, duration: Duration) {
@@ -67,3 +220,517 @@ impl AppMetrics {
         // Business metrics removed - not needed for now
     }
 }
+
+fn builder() -> Result<PrometheusBuilder, Box<dyn std::error::Error>> {
+    Ok(PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), DURATION_BUCKETS)?
+        .set_buckets_for_metric(Matcher::Suffix("_bytes".to_string()), SIZE_BUCKETS)?)
+}
+
+/// Increments a gauge while alive, so cancelled requests and closed connections
+/// are accounted for as well.
+#[derive(Debug)]
+pub struct GaugeGuard(Gauge);
+
+impl GaugeGuard {
+    pub fn new(gauge: Gauge) -> Self {
+        gauge.increment(1.0);
+        GaugeGuard(gauge)
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.decrement(1.0);
+    }
+}
+
+/// Counts the chunks of a request body as they are read, handing the total to
+/// `on_end` once the body is dropped, so chunked bodies are measured too.
+pub(crate) fn count_request_body<F>(body: Body, on_end: F) -> Body
+where
+    F: FnOnce(u64) + Send + 'static,
+{
+    let mut size = BodySize::new(on_end);
+
+    Body::wrap_stream(body.inspect_ok(move |chunk| size.count(chunk)))
+}
+
+/// Adds up the bytes of a body and hands them to `on_end` when dropped, so a body that
+/// is only partly read is reported with what was read.
+struct BodySize<F: FnOnce(u64)> {
+    on_end: Option<F>,
+    bytes: u64,
+}
+
+impl<F: FnOnce(u64)> BodySize<F> {
+    fn new(on_end: F) -> Self {
+        BodySize {
+            on_end: Some(on_end),
+            bytes: 0,
+        }
+    }
+
+    fn count(&mut self, chunk: &Bytes) {
+        self.bytes += chunk.len() as u64;
+    }
+}
+
+impl<F: FnOnce(u64)> Drop for BodySize<F> {
+    fn drop(&mut self) {
+        if let Some(on_end) = self.on_end.take() {
+            on_end(self.bytes);
+        }
+    }
+}
+
+/// Forwards a body with its size hint, so the `Content-Length` derived from it is kept,
+/// and hands the bytes sent to `on_end` once the body is dropped.
+pub(crate) struct CountedBody<B, F: FnOnce(u64)> {
+    inner: B,
+    size: BodySize<F>,
+}
+
+impl<B, F: FnOnce(u64)> CountedBody<B, F> {
+    pub(crate) fn new(inner: B, on_end: F) -> Self {
+        CountedBody {
+            inner,
+            size: BodySize::new(on_end),
+        }
+    }
+}
+
+impl<B, F> http_body::Body for CountedBody<B, F>
+where
+    B: http_body::Body<Data = Bytes> + Unpin,
+    F: FnOnce(u64) + Unpin,
+{
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, B::Error>>> {
+        let chunk = ready!(Pin::new(&mut self.inner).poll_data(cx));
+
+        if let Some(Ok(chunk)) = &chunk {
+            self.size.count(chunk);
+        }
+
+        Poll::Ready(chunk)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, B::Error>> {
+        Pin::new(&mut self.inner).poll_trailers(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Wraps the make-service to count open connections, hyper drops the
+/// per-connection service when the connection closes.
+#[derive(Clone)]
+pub struct CountConnections<M> {
+    inner: M,
+    gauge: Gauge,
+}
+
+impl<M> CountConnections<M> {
+    pub fn new(inner: M, metrics: Option<&AppMetrics>) -> Self {
+        CountConnections {
+            inner,
+            gauge: metrics.map_or_else(Gauge::noop, |metrics| {
+                metrics.http_active_connections.clone()
+            }),
+        }
+    }
+}
+
+impl<M, T> Service<T> for CountConnections<M>
+where
+    M: Service<T>,
+    M::Future: Send + 'static,
+{
+    type Response = CountedConnection<M::Response>;
+    type Error = M::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let guard = GaugeGuard::new(self.gauge.clone());
+        let future = self.inner.call(target);
+
+        Box::pin(async move {
+            let inner = future.await?;
+
+            Ok(CountedConnection {
+                inner,
+                _guard: guard,
+            })
+        })
+    }
+}
+
+pub struct CountedConnection<S> {
+    inner: S,
+    _guard: GaugeGuard,
+}
+
+impl<S, R> Service<R> for CountedConnection<S>
+where
+    S: Service<R>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: R) -> Self::Future {
+        self.inner.call(req)
+    }
+}
+
+/// Caps the distinct label sets so an unexpected spread of routes, methods or
+/// statuses can't flood Prometheus; sets past the cap are reported as `other`.
+#[derive(Debug)]
+struct LabelGuard {
+    seen: Mutex<HashSet<(String, String, Option<u16>)>>,
+    limit: usize,
+}
+
+impl LabelGuard {
+    fn new(limit: usize) -> Self {
+        LabelGuard {
+            seen: Mutex::new(HashSet::new()),
+            limit,
+        }
+    }
+
+    fn admit(&self, method: &Method, route: &str) -> (String, String) {
+        self.admit_key(method, route, None)
+            .unwrap_or_else(|| (OVERFLOW_LABEL.to_string(), OVERFLOW_LABEL.to_string()))
+    }
+
+    /// Like `admit`, with the status counted as part of the label set.
+    fn admit_with_status(
+        &self,
+        method: &Method,
+        route: &str,
+        status: u16,
+    ) -> (String, String, String) {
+        match self.admit_key(method, route, Some(status)) {
+            Some((method, route)) => (method, route, status.to_string()),
+            None => (
+                OVERFLOW_LABEL.to_string(),
+                OVERFLOW_LABEL.to_string(),
+                OVERFLOW_LABEL.to_string(),
+            ),
+        }
+    }
+
+    fn admit_key(
+        &self,
+        method: &Method,
+        route: &str,
+        status: Option<u16>,
+    ) -> Option<(String, String)> {
+        let method = match *method {
+            Method::GET
+            | Method::HEAD
+            | Method::POST
+            | Method::PUT
+            | Method::DELETE
+            | Method::PATCH
+            | Method::OPTIONS => method.as_str(),
+            _ => OVERFLOW_LABEL,
+        };
+
+        let key = (method.to_string(), route.to_string(), status);
+        let mut seen = self.seen.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if seen.contains(&key) || seen.len() < self.limit {
+            seen.insert(key.clone());
+            Some((key.0, key.1))
+        } else {
+            None
+        }
+    }
+}
+
+/// Counts every request, including fallbacks and responses produced by other
+/// layers such as the body limit.
+pub async fn record_metrics(
+    req: Request<Body>,
+    next: Next<Body>,
+    metrics: Arc<AppMetrics>,
+    live: SharedLiveConf,
+    cors_enabled: bool,
+) -> Response {
+    let start = Instant::now();
+    let method = req.method().clone();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(UNMATCHED_ROUTE, |path| path.as_str())
+        .to_string();
+
+    if cors_enabled {
+        let rejected = req
+            .headers()
+            .get(header::ORIGIN)
+            .is_some_and(|origin| !live.snapshot().is_origin_allowed(origin.as_bytes()));
+
+        if rejected {
+            metrics.record_cors_rejection(&route);
+        }
+    }
+
+    let req = req.map(|body| metrics.count_request_body(&method, &route, body));
+    let in_flight = metrics.track_in_flight(&method, &route);
+
+    let resp = next.run(req).await;
+
+    drop(in_flight);
+
+    metrics.record_http_request(&method, &route, resp.status().as_u16(), start.elapsed());
+
+    resp.map(|body| metrics.count_response_body(&method, &route, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body::Body as _;
+
+    fn render(record: impl FnOnce(&AppMetrics)) -> String {
+        let recorder = builder().unwrap().build_recorder();
+        let handle = recorder.handle();
+
+        metrics::with_local_recorder(&recorder, || {
+            record(&AppMetrics::with_handle(Some(handle.clone())));
+        });
+
+        handle.render()
+    }
+
+    #[test]
+    fn test_requests_are_labeled_by_route_template() {
+        let output = render(|metrics| {
+            metrics.record_http_request(
+                &Method::GET,
+                "/api/v1/portfolios/:pid",
+                200,
+                Duration::from_millis(20),
+            );
+            metrics.record_http_request(&Method::GET, UNMATCHED_ROUTE, 404, Duration::ZERO);
+        });
+
+        assert!(
+            output.contains(
+                r#"http_requests_total{method="GET",route="/api/v1/portfolios/:pid",status="200"} 1"#
+            ),
+            "{output}"
+        );
+        assert!(
+            output
+                .contains(r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#),
+            "{output}"
+        );
+        assert!(
+            output.contains(
+                r#"http_request_duration_seconds_bucket{method="GET",route="/api/v1/portfolios/:pid",le="0.025"} 1"#
+            ),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn test_label_sets_are_capped() {
+        let guard = LabelGuard::new(2);
+
+        assert_eq!(
+            guard.admit(&Method::GET, "/a"),
+            ("GET".to_string(), "/a".to_string())
+        );
+        assert_eq!(
+            guard.admit(&Method::POST, "/a"),
+            ("POST".to_string(), "/a".to_string())
+        );
+        assert_eq!(
+            guard.admit(&Method::GET, "/b"),
+            (OVERFLOW_LABEL.to_string(), OVERFLOW_LABEL.to_string())
+        );
+        assert_eq!(
+            guard.admit(&Method::GET, "/a"),
+            ("GET".to_string(), "/a".to_string())
+        );
+    }
+
+    #[test]
+    fn test_statuses_count_towards_the_cap() {
+        let guard = LabelGuard::new(2);
+
+        assert_eq!(
+            guard.admit_with_status(&Method::GET, "/a", 200),
+            ("GET".to_string(), "/a".to_string(), "200".to_string())
+        );
+        assert_eq!(
+            guard.admit_with_status(&Method::GET, "/a", 404),
+            ("GET".to_string(), "/a".to_string(), "404".to_string())
+        );
+        assert_eq!(
+            guard.admit_with_status(&Method::GET, "/a", 500),
+            (
+                OVERFLOW_LABEL.to_string(),
+                OVERFLOW_LABEL.to_string(),
+                OVERFLOW_LABEL.to_string()
+            )
+        );
+        assert_eq!(
+            guard.admit(&Method::GET, "/a"),
+            (OVERFLOW_LABEL.to_string(), OVERFLOW_LABEL.to_string())
+        );
+    }
+
+    #[test]
+    fn test_unknown_methods_share_a_label() {
+        let guard = LabelGuard::new(10);
+        let method = Method::from_bytes(b"PURGE").unwrap();
+
+        assert_eq!(guard.admit(&method, "/a").0, OVERFLOW_LABEL);
+    }
+
+    #[test]
+    fn test_cors_rejections_are_counted() {
+        let output = render(|metrics| metrics.record_cors_rejection("/api/v1/plans"));
+
+        assert!(
+            output.contains(r#"http_cors_rejections_total{route="/api/v1/plans"} 1"#),
+            "{output}"
+        );
+    }
+
+    #[test]
+    fn test_body_validations_are_counted_by_result() {
+        let output = render(|metrics| {
+            metrics.record_body_validation(&Method::POST, "/api/v1/portfolios", true);
+            metrics.record_body_validation(&Method::POST, "/api/v1/portfolios", false);
+            metrics.record_body_validation(&Method::POST, "/api/v1/portfolios", false);
+        });
+
+        assert!(
+            output.contains(
+                r#"http_request_validations_total{method="POST",route="/api/v1/portfolios",result="invalid"} 2"#
+            ),
+            "{output}"
+        );
+        assert!(output.contains(r#"result="valid"} 1"#), "{output}");
+    }
+
+    #[test]
+    fn test_in_flight_requests_are_released_on_drop() {
+        let recorder = builder().unwrap().build_recorder();
+        let handle = recorder.handle();
+
+        metrics::with_local_recorder(&recorder, || {
+            let metrics = AppMetrics::with_handle(Some(handle.clone()));
+            let first = metrics.track_in_flight(&Method::GET, "/api/v1/plans");
+            let second = metrics.track_in_flight(&Method::GET, "/api/v1/plans");
+
+            drop(first);
+            assert!(handle
+                .render()
+                .contains(r#"http_in_flight_requests{route="/api/v1/plans"} 1"#));
+
+            drop(second);
+            assert!(handle
+                .render()
+                .contains(r#"http_in_flight_requests{route="/api/v1/plans"} 0"#));
+        });
+    }
+
+    fn chunked(chunks: Vec<String>) -> Body {
+        Body::wrap_stream(futures::stream::iter(
+            chunks.into_iter().map(Ok::<_, std::io::Error>),
+        ))
+    }
+
+    #[test]
+    fn test_body_sizes_are_counted_as_they_stream() {
+        let output = render(|metrics| {
+            let route = "/api/v1/portfolios";
+
+            let body = chunked(vec!["a".repeat(200), "b".repeat(100)]);
+            let body = metrics.count_request_body(&Method::POST, route, body);
+            assert_eq!(body.size_hint().exact(), None);
+            futures::executor::block_on(hyper::body::to_bytes(body)).unwrap();
+
+            let body = boxed(Body::from("c".repeat(1000)));
+            let body = metrics.count_response_body(&Method::POST, route, body);
+            assert_eq!(body.size_hint().exact(), Some(1000));
+            futures::executor::block_on(hyper::body::to_bytes(body)).unwrap();
+        });
+
+        assert!(
+            output.contains(
+                r#"http_request_body_size_bytes_bucket{route="/api/v1/portfolios",le="128"} 0"#
+            ),
+            "{output}"
+        );
+        assert!(
+            output.contains(
+                r#"http_request_body_size_bytes_bucket{route="/api/v1/portfolios",le="512"} 1"#
+            ),
+            "{output}"
+        );
+        assert!(
+            output.contains(
+                r#"http_response_body_size_bytes_bucket{route="/api/v1/portfolios",le="1024"} 1"#
+            ),
+            "{output}"
+        );
+        assert!(
+            output
+                .contains(r#"http_response_body_size_bytes_sum{route="/api/v1/portfolios"} 1000"#),
+            "{output}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_connections_are_counted_until_closed() {
+        let recorder = builder().unwrap().build_recorder();
+        let handle = recorder.handle();
+        let metrics = metrics::with_local_recorder(&recorder, || {
+            AppMetrics::with_handle(Some(handle.clone()))
+        });
+
+        let mut make_service = CountConnections::new(
+            tower::service_fn(|_: ()| async { Ok::<_, std::convert::Infallible>(()) }),
+            Some(&metrics),
+        );
+
+        let first = make_service.call(()).await.unwrap();
+        let second = make_service.call(()).await.unwrap();
+        assert!(handle.render().contains("http_active_connections 2"));
+
+        drop(first);
+        drop(second);
+        assert!(handle.render().contains("http_active_connections 0"));
+    }
+}
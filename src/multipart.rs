@@ -0,0 +1,337 @@
+use axum::body::Bytes;
+use futures::{Stream, StreamExt};
+
+use crate::json_api::{split_unquoted, unquote};
+use crate::request_body::BodyError;
+
+/// Part headers longer than this are rejected instead of buffered.
+const MAX_HEADERS_BYTES: usize = 8 * 1024;
+
+#[derive(Debug)]
+pub enum MultipartError {
+    Body(BodyError),
+    /// Detail for the client.
+    Malformed(&'static str),
+}
+
+impl From<axum::Error> for MultipartError {
+    fn from(e: axum::Error) -> Self {
+        MultipartError::Body(e.into())
+    }
+}
+
+/// The `boundary` of a `multipart/form-data` content type.
+pub fn boundary(content_type: &str) -> Option<String> {
+    let mut parts = split_unquoted(content_type, ';').into_iter();
+
+    if !parts
+        .next()?
+        .trim()
+        .eq_ignore_ascii_case("multipart/form-data")
+    {
+        return None;
+    }
+
+    parts
+        .filter_map(|part| part.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| unquote(value.trim()))
+        .filter(|boundary| (1..=70).contains(&boundary.len()))
+}
+
+/// Field name, file name and content type of a form part.
+#[derive(Debug, Default, PartialEq)]
+pub struct PartHeaders {
+    pub name: String,
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+}
+
+enum State {
+    Preamble,
+    /// After a delimiter, either the next part's headers or the closing `--`.
+    Delimiter,
+    Content,
+    Done,
+}
+
+/// Reads `multipart/form-data` part by part without holding more than a chunk of
+/// the body in memory.
+pub struct Multipart<S> {
+    body: S,
+    buf: Vec<u8>,
+    delimiter: Vec<u8>,
+    state: State,
+}
+
+impl<S> Multipart<S>
+where
+    S: Stream<Item = Result<Bytes, axum::Error>> + Unpin,
+{
+    pub fn new(body: S, boundary: &str) -> Self {
+        Multipart {
+            body,
+            // The first delimiter may come without the preceding line break.
+            buf: b"\r\n".to_vec(),
+            delimiter: format!("\r\n--{boundary}").into_bytes(),
+            state: State::Preamble,
+        }
+    }
+
+    async fn fill(&mut self) -> Result<(), MultipartError> {
+        match self.body.next().await {
+            Some(chunk) => {
+                self.buf.extend_from_slice(&chunk?);
+                Ok(())
+            }
+            None => Err(MultipartError::Malformed(
+                "The multipart body ends before its closing boundary.",
+            )),
+        }
+    }
+
+    /// Moves to the next part, skipping what is left of the current one. `None` after
+    /// the closing boundary.
+    pub async fn next_part(&mut self) -> Result<Option<PartHeaders>, MultipartError> {
+        loop {
+            match self.state {
+                State::Done => return Ok(None),
+                State::Content => while self.next_chunk().await?.is_some() {},
+                State::Preamble => match find(&self.buf, &self.delimiter) {
+                    Some(i) => {
+                        self.buf.drain(..i + self.delimiter.len());
+                        self.state = State::Delimiter;
+                    }
+                    None => {
+                        // Keep what could be the start of the delimiter.
+                        let keep = self.delimiter.len() - 1;
+                        if self.buf.len() > keep {
+                            self.buf.drain(..self.buf.len() - keep);
+                        }
+                        self.fill().await?;
+                    }
+                },
+                State::Delimiter => {
+                    while self.buf.len() < 2 {
+                        self.fill().await?;
+                    }
+
+                    if self.buf.starts_with(b"--") {
+                        self.state = State::Done;
+                        return Ok(None);
+                    }
+
+                    let end = loop {
+                        if let Some(end) = find(&self.buf, b"\r\n\r\n") {
+                            break end;
+                        }
+
+                        if self.buf.len() > MAX_HEADERS_BYTES {
+                            return Err(MultipartError::Malformed(
+                                "The part headers are too long.",
+                            ));
+                        }
+
+                        self.fill().await?;
+                    };
+
+                    let headers = parse_headers(&self.buf[..end])?;
+                    self.buf.drain(..end + 4);
+                    self.state = State::Content;
+
+                    return Ok(Some(headers));
+                }
+            }
+        }
+    }
+
+    /// The next piece of the current part's content, `None` at its end.
+    pub async fn next_chunk(&mut self) -> Result<Option<Bytes>, MultipartError> {
+        if !matches!(self.state, State::Content) {
+            return Ok(None);
+        }
+
+        loop {
+            if let Some(i) = find(&self.buf, &self.delimiter) {
+                let chunk: Vec<u8> = self.buf.drain(..i).collect();
+                self.buf.drain(..self.delimiter.len());
+                self.state = State::Delimiter;
+
+                return Ok((!chunk.is_empty()).then(|| Bytes::from(chunk)));
+            }
+
+            let keep = self.delimiter.len() - 1;
+            if self.buf.len() > keep {
+                let chunk: Vec<u8> = self.buf.drain(..self.buf.len() - keep).collect();
+                return Ok(Some(Bytes::from(chunk)));
+            }
+
+            self.fill().await?;
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// `raw` starts with the rest of the delimiter line, which may only hold whitespace.
+fn parse_headers(raw: &[u8]) -> Result<PartHeaders, MultipartError> {
+    let raw = std::str::from_utf8(raw)
+        .map_err(|_| MultipartError::Malformed("The part headers are not valid UTF-8."))?;
+    let mut lines = raw.split("\r\n");
+
+    if !lines.next().unwrap_or_default().trim().is_empty() {
+        return Err(MultipartError::Malformed(
+            "The multipart boundary is followed by other data.",
+        ));
+    }
+
+    let mut headers = PartHeaders::default();
+    let mut form_data = false;
+
+    for line in lines {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or(MultipartError::Malformed("A part header is malformed."))?;
+
+        if name.trim().eq_ignore_ascii_case("content-type") {
+            headers.content_type = Some(value.trim().to_string());
+            continue;
+        }
+
+        if !name.trim().eq_ignore_ascii_case("content-disposition") {
+            continue;
+        }
+
+        let mut params = split_unquoted(value, ';').into_iter();
+        form_data = params
+            .next()
+            .is_some_and(|kind| kind.trim().eq_ignore_ascii_case("form-data"));
+
+        for (param, value) in params.filter_map(|param| param.split_once('=')) {
+            match param.trim().to_ascii_lowercase().as_str() {
+                "name" => headers.name = unquote(value.trim()),
+                "filename" => headers.file_name = Some(unquote(value.trim())),
+                _ => {}
+            }
+        }
+    }
+
+    if form_data {
+        Ok(headers)
+    } else {
+        Err(MultipartError::Malformed(
+            "A part has no form-data Content-Disposition.",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = "preamble\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"format\"\r\n\
+        \r\n\
+        ibkr\r\n\
+        --XyZ\r\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"trades; 2024.csv\"\r\n\
+        Content-Type: text/csv\r\n\
+        \r\n\
+        date,ticker\r\n2024-01-02,AAPL --XyZ\r\n\
+        --XyZ--\r\n\
+        epilogue";
+
+    fn form(
+        body: &str,
+        chunk_size: usize,
+    ) -> Multipart<impl Stream<Item = Result<Bytes, axum::Error>> + Unpin> {
+        let chunks: Vec<_> = body
+            .as_bytes()
+            .chunks(chunk_size)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+
+        Multipart::new(futures::stream::iter(chunks), "XyZ")
+    }
+
+    async fn content<S>(form: &mut Multipart<S>) -> String
+    where
+        S: Stream<Item = Result<Bytes, axum::Error>> + Unpin,
+    {
+        let mut content = vec![];
+        while let Some(chunk) = form.next_chunk().await.unwrap() {
+            content.extend_from_slice(&chunk);
+        }
+        String::from_utf8(content).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_parts_across_chunk_sizes() {
+        for chunk_size in [1, 2, 7, 64, BODY.len()] {
+            let mut form = form(BODY, chunk_size);
+
+            let part = form.next_part().await.unwrap().unwrap();
+            assert_eq!(part.name, "format");
+            assert_eq!(content(&mut form).await, "ibkr");
+
+            let part = form.next_part().await.unwrap().unwrap();
+            assert_eq!(
+                part,
+                PartHeaders {
+                    name: "file".to_string(),
+                    file_name: Some("trades; 2024.csv".to_string()),
+                    content_type: Some("text/csv".to_string()),
+                }
+            );
+            assert_eq!(
+                content(&mut form).await,
+                "date,ticker\r\n2024-01-02,AAPL --XyZ",
+                "chunk size {chunk_size}"
+            );
+
+            assert!(form.next_part().await.unwrap().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unread_content_is_skipped() {
+        let mut form = form(BODY, 5);
+
+        form.next_part().await.unwrap();
+        let part = form.next_part().await.unwrap().unwrap();
+
+        assert_eq!(part.name, "file");
+    }
+
+    #[tokio::test]
+    async fn test_truncated_body_is_malformed() {
+        let mut form = form(&BODY[..BODY.len() - 30], 16);
+
+        form.next_part().await.unwrap();
+        form.next_part().await.unwrap();
+
+        assert!(matches!(
+            form.next_part().await,
+            Err(MultipartError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_boundary() {
+        assert_eq!(
+            boundary("multipart/form-data; boundary=\"a b\"").as_deref(),
+            Some("a b")
+        );
+        assert_eq!(
+            boundary("Multipart/Form-Data;charset=utf-8;BOUNDARY=x1").as_deref(),
+            Some("x1")
+        );
+        assert_eq!(boundary("multipart/form-data"), None);
+        assert_eq!(boundary("application/json; boundary=x"), None);
+    }
+}
@@ -0,0 +1,443 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_nats::connection::State;
+use async_nats::{Client, ConnectError, ConnectOptions, HeaderMap};
+use axum::BoxError;
+use rand::Rng;
+use tokio::sync::RwLock;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::conf::{Conf, ConfError, ConnectRetryConf, NatsConf};
+
+/// Remaining time budget in milliseconds when the request was published.
+pub const TIMEOUT_HEADER: &str = "timeout";
+/// Absolute deadline as unix time in milliseconds, responders may drop work past it.
+pub const DEADLINE_HEADER: &str = "deadline";
+
+/// NATS client shared by the handlers, `None` until the first connection succeeds.
+pub type SharedClient = Arc<RwLock<Option<Client>>>;
+
+const CONNECTED_POLL: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum NatsError {
+    Conf(ConfError),
+    Connect(ConnectError),
+}
+
+impl fmt::Display for NatsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NatsError::Conf(e) => write!(f, "{e}"),
+            NatsError::Connect(e) => write!(f, "NATS connect error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for NatsError {}
+
+impl From<ConfError> for NatsError {
+    fn from(e: ConfError) -> Self {
+        NatsError::Conf(e)
+    }
+}
+
+impl From<ConnectError> for NatsError {
+    fn from(e: ConnectError) -> Self {
+        NatsError::Connect(e)
+    }
+}
+
+/// Builds connect options with authentication and TLS applied. Secrets are resolved on
+/// every call, so rotated secret files are picked up by the next connection attempt.
+pub async fn connect_options(conf: &NatsConf) -> Result<ConnectOptions, ConfError> {
+    let mut options = ConnectOptions::new()
+        .ping_interval(Duration::from_millis(conf.ping_interval_ms))
+        .request_timeout(Some(Duration::from_millis(conf.request_timeout_ms)));
+
+    let auth = &conf.auth;
+
+    if let (Some(user), Some(password)) = (&auth.user, &auth.password) {
+        options = options.user_and_password(user.resolve()?, password.resolve()?);
+    }
+
+    if let Some(token) = &auth.token {
+        options = options.token(token.resolve()?);
+    }
+
+    if let Some(seed) = &auth.nkey_seed {
+        options = options.nkey(seed.resolve()?);
+    }
+
+    if let Some(path) = &auth.credentials_file {
+        options = options
+            .credentials_file(path)
+            .await
+            .map_err(|e| ConfError {
+                message: format!("can't load NATS credentials file {path}, {e}"),
+            })?;
+    }
+
+    if let Some(tls) = &conf.tls {
+        options = options.require_tls(tls.required);
+
+        if let Some(ca_file) = &tls.ca_file {
+            options = options.add_root_certificates(PathBuf::from(ca_file));
+        }
+
+        if let (Some(cert_file), Some(key_file)) = (&tls.cert_file, &tls.key_file) {
+            options =
+                options.add_client_certificate(PathBuf::from(cert_file), PathBuf::from(key_file));
+        }
+    }
+
+    Ok(options)
+}
+
+/// Connects to the configured servers, retrying connection failures with backoff.
+/// Configuration errors are returned immediately since retrying can't fix them.
+pub async fn connect_with_retry(conf: &NatsConf) -> Result<Client, NatsError> {
+    let retry = &conf.connect_retry;
+    let servers = conf.server_urls();
+    let mut attempt: u32 = 0;
+
+    loop {
+        attempt += 1;
+
+        match connect_options(conf)
+            .await?
+            .connect(servers.as_slice())
+            .await
+        {
+            Ok(client) => {
+                info!(servers = ?servers, attempt, "connected to NATS");
+
+                return Ok(client);
+            }
+            Err(e) => {
+                if retry.max_attempts != 0 && attempt >= retry.max_attempts {
+                    error!(error = %e, servers = ?servers, attempt, "giving up connecting to NATS");
+
+                    return Err(e.into());
+                }
+
+                let delay = backoff_delay(retry, attempt, &mut rand::thread_rng());
+
+                warn!(
+                    error = %e,
+                    servers = ?servers,
+                    attempt,
+                    delay_ms = delay.as_millis() as u64,
+                    "failed to connect to NATS, retrying"
+                );
+
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+/// Connects in the background and publishes the client into `shared` once it is up.
+/// Once `connect_retry.max_attempts` is used up the process exits like an eager
+/// start would, instead of staying up and never becoming ready.
+pub fn spawn_connect(conf: NatsConf, shared: SharedClient) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        match connect_with_retry(&conf).await {
+            Ok(client) => *shared.write().await = Some(client),
+            Err(e) => {
+                error!(error = %e, "NATS is unreachable, exiting");
+
+                std::process::exit(1);
+            }
+        }
+    })
+}
+
+pub async fn is_connected(shared: &SharedClient) -> bool {
+    matches!(
+        shared.read().await.as_ref().map(|c| c.connection_state()),
+        Some(State::Connected)
+    )
+}
+
+/// Backend request deadlines resolved from config, keyed by route template.
+#[derive(Debug, Clone)]
+pub struct Deadlines {
+    default: Duration,
+    routes: HashMap<String, Duration>,
+}
+
+impl Deadlines {
+    pub fn from_conf(conf: &Conf) -> Self {
+        Deadlines {
+            default: Duration::from_millis(conf.nats.request_timeout_ms),
+            routes: conf
+                .routes
+                .iter()
+                .filter_map(|(route, route_conf)| {
+                    route_conf
+                        .timeout_ms
+                        .map(|ms| (route.clone(), Duration::from_millis(ms)))
+                })
+                .collect(),
+        }
+    }
+
+    pub fn for_route(&self, route: &str) -> Duration {
+        self.routes.get(route).copied().unwrap_or(self.default)
+    }
+}
+
+/// Adds the remaining time budget to the outgoing request headers.
+pub fn insert_deadline_headers(headers: &mut HeaderMap, remaining: Duration) {
+    let deadline = SystemTime::now()
+        .checked_add(remaining)
+        .and_then(|deadline| deadline.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+
+    headers.insert(TIMEOUT_HEADER, remaining.as_millis().to_string());
+    headers.insert(DEADLINE_HEADER, deadline.as_millis().to_string());
+}
+
+/// Exponential backoff capped at `max_backoff_ms`, spread by `jitter` in both directions.
+pub fn backoff_delay<R: Rng>(retry: &ConnectRetryConf, attempt: u32, rng: &mut R) -> Duration {
+    let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+    let base = (retry.initial_backoff_ms as f64 * retry.multiplier.max(1.0).powi(exponent))
+        .min(retry.max_backoff_ms as f64);
+
+    let jitter = retry.jitter.clamp(0.0, 1.0);
+    let factor = if jitter > 0.0 {
+        rng.gen_range(1.0 - jitter..=1.0 + jitter)
+    } else {
+        1.0
+    };
+
+    Duration::from_millis((base * factor).round() as u64)
+}
+
+/// Warns when the stream capturing `subject` keeps messages longer than `max_age`.
+/// The stream belongs to the operator and may capture other subjects, so it's only read;
+/// waits for the first connection and checks once.
+pub fn spawn_retention_check(subject: String, max_age: Duration, nats: SharedClient) {
+    tokio::spawn(async move {
+        let client = loop {
+            if let Some(client) = nats.read().await.clone() {
+                break client;
+            }
+
+            tokio::time::sleep(CONNECTED_POLL).await;
+        };
+
+        match stream_max_age(&client, &subject).await {
+            Ok((stream, stream_max_age)) if exceeds_max_age(stream_max_age, max_age) => warn!(
+                stream,
+                subject,
+                stream_max_age_secs = stream_max_age.as_secs(),
+                max_age_secs = max_age.as_secs(),
+                "stream keeps bearer tokens too long, lower its max age"
+            ),
+            Ok(_) => {}
+            Err(e) => warn!(
+                error = %e,
+                subject,
+                "can't check the retention of the stream capturing the subject"
+            ),
+        }
+    });
+}
+
+async fn stream_max_age(client: &Client, subject: &str) -> Result<(String, Duration), BoxError> {
+    let jetstream = async_nats::jetstream::new(client.clone());
+    let name = jetstream.stream_by_subject(subject).await?;
+    let max_age = jetstream
+        .get_stream(&name)
+        .await?
+        .cached_info()
+        .config
+        .max_age;
+
+    Ok((name, max_age))
+}
+
+/// A zero max age keeps messages forever.
+fn exceeds_max_age(stream_max_age: Duration, max_age: Duration) -> bool {
+    stream_max_age.is_zero() || stream_max_age > max_age
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn retry(jitter: f64) -> ConnectRetryConf {
+        ConnectRetryConf {
+            max_attempts: 5,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1_000,
+            multiplier: 2.0,
+            jitter,
+        }
+    }
+
+    #[test]
+    fn test_backoff_grows_exponentially() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let retry = retry(0.0);
+
+        assert_eq!(
+            backoff_delay(&retry, 1, &mut rng),
+            Duration::from_millis(100)
+        );
+        assert_eq!(
+            backoff_delay(&retry, 2, &mut rng),
+            Duration::from_millis(200)
+        );
+        assert_eq!(
+            backoff_delay(&retry, 3, &mut rng),
+            Duration::from_millis(400)
+        );
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let retry = retry(0.0);
+
+        assert_eq!(
+            backoff_delay(&retry, 10, &mut rng),
+            Duration::from_millis(1_000)
+        );
+        assert_eq!(
+            backoff_delay(&retry, u32::MAX, &mut rng),
+            Duration::from_millis(1_000)
+        );
+    }
+
+    #[test]
+    fn test_backoff_jitter_stays_in_bounds() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let retry = retry(0.5);
+
+        for _ in 0..100 {
+            let delay = backoff_delay(&retry, 2, &mut rng);
+            assert!(delay >= Duration::from_millis(100));
+            assert!(delay <= Duration::from_millis(300));
+        }
+    }
+
+    fn nats_conf(config_json: &str) -> NatsConf {
+        serde_json::from_str(config_json).expect("should parse nats conf")
+    }
+
+    #[tokio::test]
+    async fn test_connect_options_resolves_secrets() {
+        let conf = nats_conf(r#"{"host": "a", "auth": {"user": "u", "password": "p"}}"#);
+
+        assert!(connect_options(&conf).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_connect_options_fails_on_missing_secret() {
+        let conf = nats_conf(
+            r#"{"host": "a", "auth": {"token": {"env": "HTTP2_TEST_SURELY_MISSING_TOKEN"}}}"#,
+        );
+
+        assert!(connect_options(&conf).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connect_options_fails_on_missing_credentials_file() {
+        let conf = nats_conf(
+            r#"{"host": "a", "auth": {"credentials_file": "/nonexistent/gateway.creds"}}"#,
+        );
+
+        let err = connect_options(&conf).await.expect_err("should fail");
+        assert!(err.message.contains("/nonexistent/gateway.creds"));
+    }
+
+    #[tokio::test]
+    async fn test_connect_with_retry_does_not_retry_conf_errors() {
+        let conf = nats_conf(
+            r#"{"host": "a", "auth": {"nkey_seed": {"file": "/nonexistent/seed"}}, "connect_retry": {"max_attempts": 0}}"#,
+        );
+
+        assert!(matches!(
+            connect_with_retry(&conf).await,
+            Err(NatsError::Conf(_))
+        ));
+    }
+
+    #[test]
+    fn test_deadlines_route_override() {
+        let conf: Conf = serde_json::from_str(
+            r#"{
+                "listen_port": 8000,
+                "enable_cors": false,
+                "nats": {"host": "a", "request_timeout_ms": 3000},
+                "allowed_origins": [],
+                "is_debug": false,
+                "routes": {
+                    "/api/v1/securities/:sid/annual-balance-sheet": {"timeout_ms": 20000},
+                    "/api/v1/plans": {}
+                }
+            }"#,
+        )
+        .expect("should parse conf");
+
+        let deadlines = Deadlines::from_conf(&conf);
+
+        assert_eq!(
+            deadlines.for_route("/api/v1/securities/:sid/annual-balance-sheet"),
+            Duration::from_secs(20)
+        );
+        assert_eq!(deadlines.for_route("/api/v1/plans"), Duration::from_secs(3));
+        assert_eq!(deadlines.for_route("/api/v1/users"), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_insert_deadline_headers() {
+        let mut headers = HeaderMap::new();
+        let now_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("clock should be after epoch")
+            .as_millis();
+
+        insert_deadline_headers(&mut headers, Duration::from_millis(1500));
+
+        assert_eq!(
+            headers.get(TIMEOUT_HEADER).map(|v| v.to_string()),
+            Some("1500".to_string())
+        );
+
+        let deadline: u128 = headers
+            .get(DEADLINE_HEADER)
+            .expect("deadline header should be set")
+            .to_string()
+            .parse()
+            .expect("deadline should be a number");
+        assert!(deadline >= now_ms + 1500);
+    }
+
+    #[tokio::test]
+    async fn test_not_connected_without_client() {
+        let shared: SharedClient = Arc::new(RwLock::new(None));
+
+        assert!(!is_connected(&shared).await);
+    }
+
+    #[test]
+    fn test_exceeds_max_age() {
+        let hour = Duration::from_secs(3600);
+
+        assert!(exceeds_max_age(Duration::ZERO, hour));
+        assert!(exceeds_max_age(hour * 24, hour));
+        assert!(!exceeds_max_age(hour, hour));
+        assert!(!exceeds_max_age(Duration::from_secs(60), hour));
+    }
+}
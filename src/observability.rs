@@ -1,64 +1,75 @@
 use crate::conf::Conf;
+use crate::log_control::{base_filter, install, LogControl};
+use crate::log_format::{otel_layer, CorrelationLayer, JsonFormat};
 use crate::metrics::AppMetrics;
+use crate::panics::install_panic_hook;
 use std::sync::Arc;
-use tracing_subscriber::{filter::LevelFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
+use tracing::{error, info, warn};
+use tracing_log::LogTracer;
+use tracing_subscriber::{fmt, layer::SubscriberExt, reload, EnvFilter};
 
-pub fn init_observability() -> Result<Arc<AppMetrics>, Box<dyn std::error::Error>> {
-    // Try to load config early to determine log level
-    let log_level = match Conf::new() {
-        Ok(conf) => {
-            if conf.is_debug {
-                LevelFilter::DEBUG
-            } else {
-                LevelFilter::INFO
-            }
-        }
-        Err(_) => LevelFilter::INFO, // Default to INFO if config fails
+pub fn init_observability(conf: &Conf) -> Result<Arc<AppMetrics>, Box<dyn std::error::Error>> {
+    let base = base_filter(conf);
+
+    let (filter, filter_error) = match EnvFilter::try_new(&base) {
+        Ok(filter) => (filter, None),
+        Err(e) => (EnvFilter::new("info"), Some(e)),
     };
 
-    let subscriber_result = tracing_subscriber::registry()
-        .with(log_level)
-        .with(
-            fmt::layer()
-                .json()
-                .with_target(true)
-                .with_level(true)
-                .with_thread_ids(false)
-                .with_line_number(false)
-                .with_current_span(false)
-                .with_span_list(false),
-        )
-        .try_init();
+    let (filter, filter_handle) = reload::Layer::new(filter);
+
+    let subscriber = tracing_subscriber::registry()
+        .with(filter)
+        .with(otel_layer())
+        .with(CorrelationLayer)
+        .with(fmt::layer().event_format(JsonFormat));
 
     // Handle the case where subscriber is already initialized (graceful fallback)
-    match subscriber_result {
-        Ok(_) => println!("Tracing subscriber initialized successfully"),
+    match tracing::subscriber::set_global_default(subscriber) {
+        Ok(_) => {
+            install(Arc::new(LogControl::new(
+                filter_handle,
+                base.clone(),
+                conf.log.revert_after_minutes,
+            )));
+
+            // Third-party crates still log through `log`, forward their records into tracing.
+            if let Err(e) = LogTracer::init() {
+                warn!(error = %e, "log records are not forwarded to tracing");
+            }
+
+            info!("tracing subscriber initialized");
+        }
         Err(e) => {
-            println!("WARNING: Tracing subscriber already initialized: {}", e);
-            eprintln!("WARNING: Tracing subscriber already initialized: {}", e);
-            // Continue anyway - this is not fatal
+            warn!(error = %e, "tracing subscriber already initialized");
         }
     }
 
-    // Initialize Prometheus metrics with fallback
-    let metrics = match AppMetrics::new() {
-        Ok(metrics) => {
-            println!("Prometheus metrics initialized successfully");
-            Arc::new(metrics)
-        }
+    install_panic_hook();
+
+    if let Some(e) = filter_error {
+        warn!(filter = %base, error = %e, "invalid log filter, falling back to info");
+    }
+
+    let metrics = match AppMetrics::new(&conf.metrics) {
+        Ok(metrics) => Arc::new(metrics),
         Err(e) => {
-            println!("ERROR: Failed to initialize metrics: {}", e);
-            eprintln!("ERROR: Failed to initialize metrics: {}", e);
-            println!("Continuing without metrics collection (graceful degradation)");
-            // For now, fail fast - but could be changed to graceful degradation
+            error!(error = %e, "failed to initialize metrics");
             return Err(e);
         }
     };
 
-    println!("Observability system initialized");
+    info!(is_debug = conf.is_debug, "observability initialized");
+
     Ok(metrics)
 }
 
-pub fn shutdown_observability() {
-    // Simplified shutdown for now
+/// Flushes exporters that push on an interval.
+pub async fn shutdown_observability(metrics: Option<Arc<AppMetrics>>) {
+    if let Some(metrics) = metrics {
+        // The SDK blocks until the exporter task on the runtime has flushed.
+        if let Err(e) = tokio::task::spawn_blocking(move || metrics.shutdown()).await {
+            error!(error = %e, "metrics shutdown task failed");
+        }
+    }
 }
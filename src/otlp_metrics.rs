@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder,
+    SharedString, Unit,
+};
+use opentelemetry::metrics::{Meter, MeterProvider as _};
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{MetricExporter, WithExportConfig};
+use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+use opentelemetry_sdk::{runtime, Resource};
+
+use crate::conf::OtlpMetricsConf;
+use crate::metrics::{DURATION_BUCKETS, SIZE_BUCKETS};
+
+/// Builds the OTLP export pipeline, the provider exports on `interval_ms` and flushes
+/// on shutdown.
+pub fn otlp_pipeline(
+    conf: &OtlpMetricsConf,
+) -> Result<(OtlpRecorder, SdkMeterProvider), Box<dyn std::error::Error>> {
+    let exporter = MetricExporter::builder()
+        .with_tonic()
+        .with_endpoint(&conf.endpoint)
+        .with_timeout(Duration::from_millis(conf.timeout_ms))
+        .build()?;
+
+    let reader = PeriodicReader::builder(exporter, runtime::Tokio)
+        .with_interval(Duration::from_millis(conf.interval_ms))
+        .with_timeout(Duration::from_millis(conf.timeout_ms))
+        .build();
+
+    let provider = SdkMeterProvider::builder()
+        .with_reader(reader)
+        .with_resource(Resource::new([
+            KeyValue::new("service.name", env!("CARGO_PKG_NAME")),
+            KeyValue::new("service.version", env!("CARGO_PKG_VERSION")),
+        ]))
+        .build();
+
+    let recorder = OtlpRecorder::new(provider.meter(env!("CARGO_PKG_NAME")));
+
+    Ok((recorder, provider))
+}
+
+/// Forwards the `metrics` facade to OpenTelemetry instruments of the same name.
+///
+/// Handles are cached per key so counters and gauges keep their value across the
+/// per-request registrations done by the `metrics` macros.
+#[derive(Debug)]
+pub struct OtlpRecorder {
+    meter: Meter,
+    counters: Mutex<HashMap<Key, Arc<OtlpCounter>>>,
+    gauges: Mutex<HashMap<Key, Arc<OtlpGauge>>>,
+    histograms: Mutex<HashMap<Key, Arc<OtlpHistogram>>>,
+}
+
+impl OtlpRecorder {
+    pub fn new(meter: Meter) -> Self {
+        OtlpRecorder {
+            meter,
+            counters: Mutex::default(),
+            gauges: Mutex::default(),
+            histograms: Mutex::default(),
+        }
+    }
+}
+
+fn attributes(key: &Key) -> Vec<KeyValue> {
+    key.labels()
+        .map(|label| KeyValue::new(label.key().to_string(), label.value().to_string()))
+        .collect()
+}
+
+fn boundaries(name: &str) -> Option<&'static [f64]> {
+    if name.ends_with("_seconds") {
+        Some(DURATION_BUCKETS)
+    } else if name.ends_with("_bytes") {
+        Some(SIZE_BUCKETS)
+    } else {
+        None
+    }
+}
+
+impl Recorder for OtlpRecorder {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+        let mut counters = self.counters.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let counter = counters.entry(key.clone()).or_insert_with(|| {
+            Arc::new(OtlpCounter {
+                counter: self.meter.u64_counter(key.name().to_string()).build(),
+                attributes: attributes(key),
+                total: AtomicU64::new(0),
+            })
+        });
+
+        Counter::from_arc(counter.clone())
+    }
+
+    fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+        let mut gauges = self.gauges.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let gauge = gauges.entry(key.clone()).or_insert_with(|| {
+            Arc::new(OtlpGauge {
+                gauge: self.meter.f64_gauge(key.name().to_string()).build(),
+                attributes: attributes(key),
+                value: Mutex::new(0.0),
+            })
+        });
+
+        Gauge::from_arc(gauge.clone())
+    }
+
+    fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+        let mut histograms = self
+            .histograms
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let histogram = histograms.entry(key.clone()).or_insert_with(|| {
+            let builder = self.meter.f64_histogram(key.name().to_string());
+            let builder = match boundaries(key.name()) {
+                Some(boundaries) => builder.with_boundaries(boundaries.to_vec()),
+                None => builder,
+            };
+
+            Arc::new(OtlpHistogram {
+                histogram: builder.build(),
+                attributes: attributes(key),
+            })
+        });
+
+        Histogram::from_arc(histogram.clone())
+    }
+}
+
+#[derive(Debug)]
+struct OtlpCounter {
+    counter: opentelemetry::metrics::Counter<u64>,
+    attributes: Vec<KeyValue>,
+    total: AtomicU64,
+}
+
+impl CounterFn for OtlpCounter {
+    fn increment(&self, value: u64) {
+        self.total.fetch_add(value, Ordering::Relaxed);
+        self.counter.add(value, &self.attributes);
+    }
+
+    fn absolute(&self, value: u64) {
+        let previous = self.total.fetch_max(value, Ordering::Relaxed);
+
+        if value > previous {
+            self.counter.add(value - previous, &self.attributes);
+        }
+    }
+}
+
+/// OpenTelemetry gauges only take absolute values, so the current value is kept here
+/// for increments and decrements. It's recorded while the lock is held, otherwise a
+/// concurrent update could record an older value last.
+#[derive(Debug)]
+struct OtlpGauge {
+    gauge: opentelemetry::metrics::Gauge<f64>,
+    attributes: Vec<KeyValue>,
+    value: Mutex<f64>,
+}
+
+impl OtlpGauge {
+    fn update(&self, f: impl Fn(f64) -> f64) {
+        let mut value = self.value.lock().unwrap_or_else(PoisonError::into_inner);
+
+        *value = f(*value);
+        self.gauge.record(*value, &self.attributes);
+    }
+}
+
+impl GaugeFn for OtlpGauge {
+    fn increment(&self, value: f64) {
+        self.update(|current| current + value);
+    }
+
+    fn decrement(&self, value: f64) {
+        self.update(|current| current - value);
+    }
+
+    fn set(&self, value: f64) {
+        self.update(|_| value);
+    }
+}
+
+#[derive(Debug)]
+struct OtlpHistogram {
+    histogram: opentelemetry::metrics::Histogram<f64>,
+    attributes: Vec<KeyValue>,
+}
+
+impl HistogramFn for OtlpHistogram {
+    fn record(&self, value: f64) {
+        self.histogram.record(value, &self.attributes);
+    }
+}
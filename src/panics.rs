@@ -0,0 +1,174 @@
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::{Cell, RefCell};
+use std::future::{poll_fn, Future};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Once};
+
+use axum::extract::MatchedPath;
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use futures::FutureExt;
+use tracing::error;
+
+use crate::access_log::UNMATCHED_ROUTE;
+use crate::handlers::create_error_response;
+use crate::metrics::AppMetrics;
+
+thread_local! {
+    /// Set while `catch_panic` polls a request, the panic is reported there instead.
+    static CATCHING: Cell<bool> = const { Cell::new(false) };
+    static BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+static HOOK: Once = Once::new();
+
+/// Captures a backtrace for every panic so `catch_panic` can log it. Panics outside a
+/// request still go through the previous hook.
+pub fn install_panic_hook() {
+    HOOK.call_once(|| {
+        let previous = panic::take_hook();
+
+        panic::set_hook(Box::new(move |info| {
+            if CATCHING.get() {
+                BACKTRACE.set(Some(Backtrace::force_capture()));
+            } else {
+                previous(info);
+            }
+        }));
+    });
+}
+
+struct CatchingGuard(bool);
+
+impl CatchingGuard {
+    fn enter() -> Self {
+        CatchingGuard(CATCHING.replace(true))
+    }
+}
+
+impl Drop for CatchingGuard {
+    fn drop(&mut self) {
+        CATCHING.set(self.0);
+    }
+}
+
+/// Turns a panic in the layers and handlers below into a JSON:API 500, logging the
+/// payload and backtrace with the route.
+pub async fn catch_panic<B>(
+    req: Request<B>,
+    next: Next<B>,
+    metrics: Option<Arc<AppMetrics>>,
+) -> Response {
+    let method = req.method().clone();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(UNMATCHED_ROUTE, |path| path.as_str())
+        .to_string();
+
+    let mut resp = Box::pin(next.run(req));
+
+    let result = AssertUnwindSafe(poll_fn(|cx| {
+        let _guard = CatchingGuard::enter();
+        resp.as_mut().poll(cx)
+    }))
+    .catch_unwind()
+    .await;
+
+    let payload = match result {
+        Ok(resp) => return resp,
+        Err(payload) => payload,
+    };
+
+    let backtrace = BACKTRACE
+        .take()
+        .map_or_else(|| "unavailable".to_string(), |bt| bt.to_string());
+
+    error!(
+        method = %method,
+        route,
+        panic = panic_message(payload.as_ref()),
+        backtrace,
+        "request handler panicked"
+    );
+
+    if let Some(metrics) = metrics {
+        metrics.record_panic(&method, &route);
+    }
+
+    create_error_response(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "500",
+        "Internal server error",
+        "The request could not be completed.",
+    )
+    .into_response()
+}
+
+/// The message of `panic!` and `expect`, which is either a `&str` or a `String`.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("non-string panic payload")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::request_id::propagate_request_id;
+    use axum::body::Body;
+    use axum::routing::get;
+    use axum::{middleware, Router};
+    use tower::ServiceExt;
+
+    async fn panics() -> &'static str {
+        panic!("portfolio missing");
+    }
+
+    #[tokio::test]
+    async fn test_panic_becomes_json_api_error() {
+        install_panic_hook();
+
+        let app = Router::new()
+            .route("/boom", get(panics))
+            .route("/fine", get(|| async { "ok" }))
+            .layer(middleware::from_fn(|req, next| {
+                catch_panic(req, next, None)
+            }))
+            .layer(middleware::from_fn(propagate_request_id));
+
+        let resp = app
+            .clone()
+            .oneshot(Request::get("/boom").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let request_id = resp.headers()["x-request-id"].to_str().unwrap().to_string();
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["errors"][0]["code"], "500");
+        assert_eq!(body["errors"][0]["meta"]["request_id"], request_id);
+        assert!(!CATCHING.get());
+
+        let resp = app
+            .oneshot(Request::get("/fine").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_panic_message() {
+        assert_eq!(panic_message(&"static"), "static");
+        assert_eq!(panic_message(&"owned".to_string()), "owned");
+        assert_eq!(panic_message(&42), "non-string panic payload");
+    }
+}
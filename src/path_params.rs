@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use axum::http::{Response, StatusCode};
+use http_body::Full;
+use uuid::Uuid;
+
+use crate::handlers::create_errors_response;
+use crate::responses::errors::{Error, ErrorSource};
+
+/// What a path parameter may contain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParamType {
+    /// Any UUID form, normalized to lowercase hyphenated.
+    Uuid,
+    /// 1 or more, normalized without leading zeros.
+    PositiveInt,
+    /// ASCII letters, digits, `-` and `_`, up to 128 characters.
+    Slug,
+    /// Exchange symbol such as `AAPL`, `BRK.B` or `RDS-A`.
+    Ticker,
+    /// Printable ASCII up to 4096 characters, e.g. a JWT or base64 token. Exempt from
+    /// the segment check as the backend takes it as a value, not a path.
+    Opaque,
+}
+
+impl ParamType {
+    /// The value in the form sent to the backend, `None` when it doesn't match.
+    fn normalize(self, value: &str) -> Option<String> {
+        match self {
+            ParamType::Uuid => Uuid::parse_str(value)
+                .ok()
+                .map(|uuid| uuid.hyphenated().to_string()),
+            ParamType::PositiveInt => value
+                .bytes()
+                .all(|b| b.is_ascii_digit())
+                .then(|| value.parse::<u64>().ok())
+                .flatten()
+                .filter(|n| *n > 0)
+                .map(|n| n.to_string()),
+            ParamType::Slug => (value.len() <= 128
+                && !value.is_empty()
+                && value
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'))
+            .then(|| value.to_string()),
+            ParamType::Ticker => is_ticker(value).then(|| value.to_string()),
+            ParamType::Opaque => ((1..=4096).contains(&value.len())
+                && !matches!(value, "." | "..")
+                && value.bytes().all(|b| b.is_ascii_graphic()))
+            .then(|| value.to_string()),
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            ParamType::Uuid => "a UUID",
+            ParamType::PositiveInt => "a positive integer",
+            ParamType::Slug => "a slug of letters, digits, - and _",
+            ParamType::Ticker => "a ticker symbol",
+            ParamType::Opaque => "a token of up to 4096 printable characters",
+        }
+    }
+}
+
+/// `[A-Za-z0-9]{1,10}` with an optional `.` or `-` share class suffix of up to 5.
+fn is_ticker(value: &str) -> bool {
+    let is_part = |part: &str, max: usize| {
+        (1..=max).contains(&part.len()) && part.bytes().all(|b| b.is_ascii_alphanumeric())
+    };
+
+    match value.split_once(['.', '-']) {
+        Some((symbol, class)) => is_part(symbol, 10) && is_part(class, 5),
+        None => is_part(value, 10),
+    }
+}
+
+/// Segments that could change the meaning of a path once the backend joins them back.
+fn is_unsafe_segment(value: &str) -> bool {
+    matches!(value, "." | "..")
+        || value
+            .chars()
+            .any(|c| c == '/' || c == '\\' || c.is_control())
+}
+
+/// Path parameter types of each route template.
+#[derive(Debug, Clone, Default)]
+pub struct PathParams(Arc<HashMap<String, HashMap<&'static str, ParamType>>>);
+
+impl PathParams {
+    pub fn new<'a>(
+        routes: impl IntoIterator<Item = (&'a str, &'a [(&'static str, ParamType)])>,
+    ) -> Self {
+        PathParams(Arc::new(
+            routes
+                .into_iter()
+                .map(|(route, types)| (route.to_string(), types.iter().copied().collect()))
+                .collect(),
+        ))
+    }
+
+    /// Normalizes the values of `route` in place and returns the error response for
+    /// values that don't match. A value of the wrong type gets a 404 as it can't name
+    /// a resource, an unsafe segment a 400. Undeclared names only get the segment check.
+    pub fn reject(
+        &self,
+        route: &str,
+        values: &mut HashMap<String, String>,
+    ) -> Option<Response<Full<Bytes>>> {
+        let types = self.0.get(route);
+        let mut names: Vec<&String> = values.keys().collect();
+        names.sort();
+
+        let mut errors = vec![];
+        let mut status = StatusCode::NOT_FOUND;
+        let mut normalized = vec![];
+
+        for name in names {
+            let value = &values[name];
+            let param_type = types.and_then(|types| types.get(name.as_str()));
+
+            if param_type != Some(&ParamType::Opaque) && is_unsafe_segment(value) {
+                status = StatusCode::BAD_REQUEST;
+                errors.push(param_error(
+                    StatusCode::BAD_REQUEST,
+                    "Bad request",
+                    name,
+                    format!("{name} is not a valid path segment."),
+                ));
+                continue;
+            }
+
+            let Some(param_type) = param_type else {
+                continue;
+            };
+
+            match param_type.normalize(value) {
+                Some(value) => normalized.push((name.clone(), value)),
+                None => errors.push(param_error(
+                    StatusCode::NOT_FOUND,
+                    "Not found",
+                    name,
+                    format!("{name} must be {}.", param_type.describe()),
+                )),
+            }
+        }
+
+        if !errors.is_empty() {
+            // A 400 takes precedence, the request is malformed regardless of the types.
+            errors.retain(|error| error.code == status.as_str());
+
+            return Some(create_errors_response(status, errors));
+        }
+
+        values.extend(normalized);
+
+        None
+    }
+}
+
+fn param_error(status: StatusCode, title: &str, name: &str, detail: String) -> Error {
+    Error {
+        code: status.as_str().to_string(),
+        title: title.to_string(),
+        detail,
+        source: Some(ErrorSource {
+            parameter: Some(name.to_string()),
+            ..Default::default()
+        }),
+        meta: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROUTE: &str = "/routes/:uid/:pid/:sid/:id";
+
+    fn params() -> PathParams {
+        PathParams::new([(
+            ROUTE,
+            [
+                ("uid", ParamType::Uuid),
+                ("pid", ParamType::PositiveInt),
+                ("sid", ParamType::Ticker),
+                ("id", ParamType::Slug),
+            ]
+            .as_slice(),
+        )])
+    }
+
+    fn values(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    async fn body(resp: Response<Full<Bytes>>) -> serde_json::Value {
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[test]
+    fn test_values_are_normalized() {
+        let mut values = values(&[
+            ("uid", "{67E55044-10B1-426F-9247-BB680E5FE0C8}"),
+            ("pid", "0042"),
+            ("sid", "BRK.B"),
+            ("other", "anything"),
+        ]);
+
+        assert!(params().reject(ROUTE, &mut values).is_none());
+
+        assert_eq!(values["uid"], "67e55044-10b1-426f-9247-bb680e5fe0c8");
+        assert_eq!(values["pid"], "42");
+        assert_eq!(values["sid"], "BRK.B");
+        assert_eq!(values["other"], "anything");
+    }
+
+    #[test]
+    fn test_type_rules() {
+        assert_eq!(ParamType::PositiveInt.normalize("0"), None);
+        assert_eq!(ParamType::PositiveInt.normalize("+1"), None);
+        assert_eq!(
+            ParamType::PositiveInt.normalize("99999999999999999999999"),
+            None
+        );
+        assert_eq!(
+            ParamType::Slug.normalize("code_1-a"),
+            Some("code_1-a".to_string())
+        );
+        assert_eq!(ParamType::Slug.normalize("a.b"), None);
+        assert_eq!(ParamType::Slug.normalize(""), None);
+        assert!(is_ticker("RDS-A"));
+        assert!(is_ticker("AAPL"));
+        assert!(!is_ticker("AAPL."));
+        assert!(!is_ticker("TOOLONGTICKER"));
+        assert!(!is_ticker("A.B.C"));
+        assert_eq!(ParamType::Opaque.normalize(".."), None);
+        assert_eq!(ParamType::Opaque.normalize("a b"), None);
+        assert_eq!(ParamType::Opaque.normalize(&"a".repeat(4097)), None);
+    }
+
+    #[test]
+    fn test_types_are_per_route() {
+        let params = PathParams::new([
+            ("/tokens/:id", [("id", ParamType::Opaque)].as_slice()),
+            ("/codes/:id", [("id", ParamType::Slug)].as_slice()),
+        ]);
+
+        let token = "eyJhbGciOiJIUzI1NiJ9.eyJzdWIiOiI0MiJ9.sig/+=";
+        let mut values = values(&[("id", token)]);
+
+        assert!(params.reject("/tokens/:id", &mut values).is_none());
+        assert_eq!(values["id"], token);
+        assert!(params.reject("/codes/:id", &mut values).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_type_mismatch_is_not_found() {
+        let resp = params()
+            .reject(ROUTE, &mut values(&[("pid", "abc"), ("uid", "not-a-uuid")]))
+            .expect("values don't match");
+
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+
+        let body = body(resp).await;
+        assert_eq!(body["errors"][0]["source"]["parameter"], "pid");
+        assert_eq!(body["errors"][1]["source"]["parameter"], "uid");
+    }
+
+    #[tokio::test]
+    async fn test_unsafe_segment_is_bad_request() {
+        for value in ["..", "../../x", "a\\b"] {
+            let resp = params()
+                .reject(ROUTE, &mut values(&[("uid", value), ("pid", "abc")]))
+                .expect("value is unsafe");
+
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{value}");
+
+            let body = body(resp).await;
+            assert_eq!(body["errors"].as_array().unwrap().len(), 1);
+            assert_eq!(body["errors"][0]["source"]["parameter"], "uid");
+        }
+    }
+}
@@ -0,0 +1,353 @@
+use std::collections::{HashMap, HashSet};
+
+use axum::http::HeaderMap;
+use percent_encoding::percent_decode_str;
+use serde_json::{Map, Value};
+
+use crate::conf::{Conf, RedactRouteConf};
+
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Path params that are secrets whatever the config says, e.g. one-time codes.
+const BUILTIN_PATH_PARAMS: &[(&str, &str)] = &[
+    ("/api/v1/confirmation-codes/:id", "id"),
+    ("/api/v1/password-confirmation-codes/:id", "id"),
+    ("/api/v1/refresh-tokens/:refresh-token", "refresh-token"),
+];
+
+/// What to mask on one route, the global rules merged with the route's own.
+#[derive(Debug, Clone, Default)]
+pub struct Redaction {
+    headers: HashSet<String>,
+    query_params: HashSet<String>,
+    json_pointers: Vec<String>,
+    path_params: HashSet<String>,
+}
+
+impl Redaction {
+    fn extend(&mut self, conf: &RedactRouteConf) {
+        self.headers
+            .extend(conf.headers.iter().map(|name| name.to_ascii_lowercase()));
+        self.query_params
+            .extend(conf.query_params.iter().map(|name| name.to_lowercase()));
+        self.json_pointers
+            .extend(conf.json_pointers.iter().cloned());
+        self.path_params.extend(conf.path_params.iter().cloned());
+    }
+
+    /// Header names and values as a JSON object, sensitive values masked.
+    pub fn headers(&self, headers: &HeaderMap) -> String {
+        let headers: Map<String, Value> = headers
+            .iter()
+            .map(|(name, value)| {
+                let value = if self.headers.contains(name.as_str()) {
+                    REDACTED.to_string()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes()).into_owned()
+                };
+
+                (name.to_string(), Value::from(value))
+            })
+            .collect();
+
+        Value::Object(headers).to_string()
+    }
+
+    /// `path?query` with sensitive path params of `route` and query params masked.
+    pub fn uri(&self, route: &str, path_and_query: &str) -> String {
+        let (path, query) = match path_and_query.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (path_and_query, None),
+        };
+
+        let mut redacted = self.path(route, path);
+
+        if let Some(query) = query {
+            redacted.push('?');
+            redacted.push_str(&self.query(query));
+        }
+
+        redacted
+    }
+
+    fn path(&self, route: &str, path: &str) -> String {
+        if self.path_params.is_empty() {
+            return path.to_string();
+        }
+
+        let mut template = route.split('/');
+
+        path.split('/')
+            .map(|segment| match template.next() {
+                Some(param)
+                    if param
+                        .strip_prefix(':')
+                        .is_some_and(|name| self.path_params.contains(name)) =>
+                {
+                    REDACTED
+                }
+                _ => segment,
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    fn query(&self, query: &str) -> String {
+        query
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((key, _)) if self.is_sensitive_query_param(key) => {
+                    format!("{key}={REDACTED}")
+                }
+                _ => pair.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    /// Keys are compared decoded and case-insensitively, as `acc%65ss_token` or `Token`
+    /// may reach the backend under the same name.
+    fn is_sensitive_query_param(&self, key: &str) -> bool {
+        let key = percent_decode_str(key).decode_utf8_lossy().to_lowercase();
+
+        self.query_params.contains(&key)
+    }
+
+    /// Path params with sensitive values masked.
+    pub fn path_params(&self, params: &HashMap<String, String>) -> String {
+        let params: Map<String, Value> = params
+            .iter()
+            .map(|(name, value)| {
+                let value = if self.path_params.contains(name) {
+                    REDACTED
+                } else {
+                    value.as_str()
+                };
+
+                (name.clone(), Value::from(value))
+            })
+            .collect();
+
+        Value::Object(params).to_string()
+    }
+
+    /// A JSON body with the values at the sensitive pointers masked, other bodies are
+    /// only described by their size since they can't be inspected.
+    pub fn body(&self, body: &[u8]) -> String {
+        if body.is_empty() {
+            return String::new();
+        }
+
+        match serde_json::from_slice::<Value>(body) {
+            Ok(mut json) => {
+                for pointer in &self.json_pointers {
+                    if let Some(value) = json.pointer_mut(pointer) {
+                        *value = Value::from(REDACTED);
+                    }
+                }
+
+                json.to_string()
+            }
+            Err(_) => format!("<{} bytes, not JSON>", body.len()),
+        }
+    }
+}
+
+/// Redaction rules resolved from config, keyed by route template.
+#[derive(Debug, Clone)]
+pub struct RedactionPolicy {
+    default: Redaction,
+    routes: HashMap<String, Redaction>,
+}
+
+impl RedactionPolicy {
+    pub fn from_conf(conf: &Conf) -> Self {
+        let mut default = Redaction::default();
+
+        default.extend(&RedactRouteConf {
+            headers: conf.redaction.headers.clone(),
+            query_params: conf.redaction.query_params.clone(),
+            json_pointers: conf.redaction.json_pointers.clone(),
+            path_params: vec![],
+        });
+
+        let mut routes: HashMap<String, Redaction> = HashMap::new();
+
+        for (route, param) in BUILTIN_PATH_PARAMS {
+            routes
+                .entry(route.to_string())
+                .or_insert_with(|| default.clone())
+                .path_params
+                .insert(param.to_string());
+        }
+
+        for (route, route_conf) in &conf.routes {
+            routes
+                .entry(route.clone())
+                .or_insert_with(|| default.clone())
+                .extend(&route_conf.redact);
+        }
+
+        RedactionPolicy { default, routes }
+    }
+
+    pub fn for_route(&self, route: &str) -> &Redaction {
+        self.routes.get(route).unwrap_or(&self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::RouteConf;
+    use axum::http::HeaderValue;
+
+    const TOKEN: &str = "eyJhbGciOiJIUzI1NiJ9.c2VjcmV0.sig";
+
+    fn policy(configure: impl FnOnce(&mut Conf)) -> RedactionPolicy {
+        let mut conf = Conf::default();
+        configure(&mut conf);
+        RedactionPolicy::from_conf(&conf)
+    }
+
+    #[test]
+    fn test_headers_are_masked_case_insensitively() {
+        let policy = policy(|conf| conf.redaction.headers.push("X-Session".to_string()));
+        let mut headers = HeaderMap::new();
+
+        headers.insert(
+            "authorization",
+            HeaderValue::from_str(&format!("Bearer {TOKEN}")).unwrap(),
+        );
+        headers.insert("x-session", HeaderValue::from_static("s3cr3t"));
+        headers.insert("user-agent", HeaderValue::from_static("curl/8.0"));
+
+        let redacted = policy.for_route("/api/v1/plans").headers(&headers);
+
+        assert!(!redacted.contains(TOKEN), "{redacted}");
+        assert!(!redacted.contains("s3cr3t"), "{redacted}");
+        assert!(redacted.contains("curl/8.0"), "{redacted}");
+    }
+
+    #[test]
+    fn test_query_params_are_masked() {
+        let policy = policy(|_| {});
+
+        let uri = policy.for_route("/api/v1/portfolios").uri(
+            "/api/v1/portfolios",
+            &format!("/api/v1/portfolios?access_token={TOKEN}&page=2"),
+        );
+
+        assert_eq!(uri, "/api/v1/portfolios?access_token=[REDACTED]&page=2");
+    }
+
+    #[test]
+    fn test_encoded_query_params_are_masked() {
+        let policy = policy(|_| {});
+
+        let uri = policy.for_route("/api/v1/portfolios").uri(
+            "/api/v1/portfolios",
+            &format!("/api/v1/portfolios?acc%65ss_token={TOKEN}&Access_Token={TOKEN}&page=2"),
+        );
+
+        assert_eq!(
+            uri,
+            "/api/v1/portfolios?acc%65ss_token=[REDACTED]&Access_Token=[REDACTED]&page=2"
+        );
+    }
+
+    #[test]
+    fn test_builtin_path_params_are_masked() {
+        let policy = policy(|_| {});
+        let route = "/api/v1/confirmation-codes/:id";
+
+        assert_eq!(
+            policy
+                .for_route(route)
+                .uri(route, "/api/v1/confirmation-codes/483920"),
+            "/api/v1/confirmation-codes/[REDACTED]"
+        );
+
+        let params = HashMap::from([("id".to_string(), "483920".to_string())]);
+        assert!(!policy
+            .for_route(route)
+            .path_params(&params)
+            .contains("483920"));
+    }
+
+    #[test]
+    fn test_builtin_refresh_token_is_masked() {
+        let policy = policy(|_| {});
+        let route = "/api/v1/refresh-tokens/:refresh-token";
+
+        assert_eq!(
+            policy
+                .for_route(route)
+                .uri(route, "/api/v1/refresh-tokens/rt-5f1c0e9a"),
+            "/api/v1/refresh-tokens/[REDACTED]"
+        );
+    }
+
+    #[test]
+    fn test_route_path_params_from_conf() {
+        let policy = policy(|conf| {
+            conf.routes.insert(
+                "/api/v1/users/:uid".to_string(),
+                RouteConf {
+                    redact: RedactRouteConf {
+                        path_params: vec!["uid".to_string()],
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            );
+        });
+
+        assert_eq!(
+            policy
+                .for_route("/api/v1/users/:uid")
+                .uri("/api/v1/users/:uid", "/api/v1/users/42"),
+            "/api/v1/users/[REDACTED]"
+        );
+        assert_eq!(
+            policy
+                .for_route("/api/v1/portfolios/:pid")
+                .uri("/api/v1/portfolios/:pid", "/api/v1/portfolios/42"),
+            "/api/v1/portfolios/42"
+        );
+    }
+
+    #[test]
+    fn test_body_pointers_are_masked() {
+        let policy = policy(|conf| {
+            conf.routes.insert(
+                "/api/v1/sessions".to_string(),
+                RouteConf {
+                    redact: RedactRouteConf {
+                        json_pointers: vec!["/data/attributes/otp".to_string()],
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            );
+        });
+
+        let body = br#"{"data":{"type":"sessions","attributes":{"email":"a@b.c","password":"hunter2","otp":"123456"}}}"#;
+        let redacted = policy.for_route("/api/v1/sessions").body(body);
+
+        assert!(!redacted.contains("hunter2"), "{redacted}");
+        assert!(!redacted.contains("123456"), "{redacted}");
+        assert!(redacted.contains("a@b.c"), "{redacted}");
+    }
+
+    #[test]
+    fn test_non_json_body_is_not_logged() {
+        let policy = policy(|_| {});
+
+        let redacted = policy
+            .for_route("/api/v1/sessions")
+            .body(b"password=hunter2");
+
+        assert_eq!(redacted, "<16 bytes, not JSON>");
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+use crate::access_log::AccessLogPolicy;
+use crate::conf::{CliArgs, Conf, ConfError, RouteMode};
+use crate::cors::AllowedOrigins;
+use crate::json_api::MediaTypePolicy;
+use crate::log_control::{base_filter, log_control};
+use crate::nats::Deadlines;
+use crate::redact::RedactionPolicy;
+use crate::request_body::RequestBodyPolicy;
+use crate::security_headers::SecurityHeadersPolicy;
+
+/// Settings that can change without a restart, rebuilt from the config on SIGHUP.
+/// The log filter is swapped separately through `log_control`, everything else in
+/// the config is read once at startup.
+#[derive(Debug)]
+pub struct LiveConf {
+    pub allowed_origins: AllowedOrigins,
+    pub disabled_routes: HashSet<String>,
+    pub deadlines: Deadlines,
+    pub access_log: AccessLogPolicy,
+    pub redaction: RedactionPolicy,
+    pub security_headers: SecurityHeadersPolicy,
+    pub json_api: MediaTypePolicy,
+    pub request_body: RequestBodyPolicy,
+}
+
+impl LiveConf {
+    pub fn from_conf(conf: &Conf) -> Self {
+        LiveConf {
+            allowed_origins: AllowedOrigins::new(&conf.allowed_origins),
+            disabled_routes: conf
+                .routes
+                .iter()
+                .filter(|(_, route_conf)| !route_conf.enabled)
+                .map(|(route, _)| route.clone())
+                .collect(),
+            deadlines: Deadlines::from_conf(conf),
+            access_log: AccessLogPolicy::from_conf(conf),
+            redaction: RedactionPolicy::from_conf(conf),
+            security_headers: SecurityHeadersPolicy::from_conf(conf),
+            json_api: MediaTypePolicy::from_conf(conf),
+            request_body: RequestBodyPolicy::from_conf(conf),
+        }
+    }
+
+    pub fn is_route_enabled(&self, route: &str) -> bool {
+        !self.disabled_routes.contains(route)
+    }
+
+    pub fn is_origin_allowed(&self, origin: &[u8]) -> bool {
+        std::str::from_utf8(origin).is_ok_and(|origin| self.allowed_origins.is_allowed(origin))
+    }
+}
+
+/// Handlers take a snapshot per request, so requests in flight finish with the
+/// settings they started with while a reload swaps in new ones.
+#[derive(Debug, Clone)]
+pub struct SharedLiveConf(Arc<RwLock<Arc<LiveConf>>>);
+
+impl SharedLiveConf {
+    pub fn new(conf: &Conf) -> Self {
+        SharedLiveConf(Arc::new(RwLock::new(Arc::new(LiveConf::from_conf(conf)))))
+    }
+
+    pub fn snapshot(&self) -> Arc<LiveConf> {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn replace(&self, live: LiveConf) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(live);
+    }
+}
+
+/// Re-reads and validates the config, then swaps the reloadable settings.
+/// On error nothing is changed and the previous settings stay active.
+pub fn reload(args: &CliArgs, startup: &Conf, live: &SharedLiveConf) -> Result<(), ConfError> {
+    let conf = Conf::load(args, std::env::vars())?;
+
+    warn_restart_required(startup, &conf);
+
+    live.replace(LiveConf::from_conf(&conf));
+
+    if let Some(control) = log_control() {
+        control.set_base(base_filter(&conf), conf.log.revert_after_minutes);
+    }
+
+    info!(
+        allowed_origins = conf.allowed_origins.len(),
+        routes = conf.routes.len(),
+        "configuration reloaded"
+    );
+
+    Ok(())
+}
+
+/// Runs reloads on the blocking pool so reading the config file doesn't stall the
+/// signal task. Reloads run one at a time, a signal sent during a reload waits for it.
+pub struct Reloader {
+    args: CliArgs,
+    startup: Conf,
+    live: SharedLiveConf,
+    running: Mutex<()>,
+}
+
+impl Reloader {
+    pub fn new(args: CliArgs, startup: Conf, live: SharedLiveConf) -> Arc<Self> {
+        Arc::new(Reloader {
+            args,
+            startup,
+            live,
+            running: Mutex::new(()),
+        })
+    }
+
+    pub fn spawn(self: &Arc<Self>) {
+        let reloader = self.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let _running = reloader
+                .running
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+
+            if let Err(e) = reload(&reloader.args, &reloader.startup, &reloader.live) {
+                error!(error = %e, "configuration reload failed, keeping the previous configuration");
+            }
+        });
+    }
+}
+
+fn warn_restart_required(startup: &Conf, conf: &Conf) {
+    let changed = [
+        (
+            "listen_port",
+            differs(&startup.listen_port, &conf.listen_port),
+        ),
+        (
+            "enable_cors",
+            differs(&startup.enable_cors, &conf.enable_cors),
+        ),
+        ("nats", differs(&startup.nats, &conf.nats)),
+        ("shutdown", differs(&startup.shutdown, &conf.shutdown)),
+        ("metrics", differs(&startup.metrics, &conf.metrics)),
+        ("cors", differs(&startup.cors, &conf.cors)),
+        (
+            "admin.listener",
+            differs(&startup.admin.listener, &conf.admin.listener),
+        ),
+        (
+            "routes.*.request_schema",
+            differs(&request_schemas(startup), &request_schemas(conf)),
+        ),
+        ("jobs", differs(&startup.jobs, &conf.jobs)),
+        ("imports", differs(&startup.imports, &conf.imports)),
+        (
+            "routes.*.mode",
+            differs(&async_routes(startup), &async_routes(conf)),
+        ),
+    ];
+
+    for (key, _) in changed.iter().filter(|(_, changed)| *changed) {
+        warn!(
+            key,
+            "changed setting requires a restart, keeping the running value"
+        );
+    }
+}
+
+fn request_schemas(conf: &Conf) -> BTreeMap<&String, &String> {
+    conf.routes
+        .iter()
+        .filter_map(|(route, route_conf)| Some((route, route_conf.request_schema.as_ref()?)))
+        .collect()
+}
+
+fn async_routes(conf: &Conf) -> BTreeSet<&String> {
+    conf.routes
+        .iter()
+        .filter(|(_, route_conf)| route_conf.mode == RouteMode::Async)
+        .map(|(route, _)| route)
+        .collect()
+}
+
+fn differs<T: Serialize>(a: &T, b: &T) -> bool {
+    serde_json::to_value(a).ok() != serde_json::to_value(b).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::RouteConf;
+    use std::io::Write;
+
+    fn config_file(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().expect("should create temp file");
+        write!(file, "{}", contents).expect("should write config");
+        file
+    }
+
+    fn args_for(file: &tempfile::NamedTempFile) -> CliArgs {
+        CliArgs {
+            config_path: Some(file.path().to_string_lossy().to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_live_conf_disabled_routes() {
+        let mut conf = Conf::default();
+        conf.routes.insert(
+            "/api/v1/plans".to_string(),
+            RouteConf {
+                enabled: false,
+                ..Default::default()
+            },
+        );
+        conf.routes
+            .insert("/api/v1/users".to_string(), RouteConf::default());
+
+        let live = LiveConf::from_conf(&conf);
+
+        assert!(!live.is_route_enabled("/api/v1/plans"));
+        assert!(live.is_route_enabled("/api/v1/users"));
+        assert!(live.is_route_enabled("/api/v1/sectors"));
+    }
+
+    #[test]
+    fn test_reload_swaps_settings() {
+        let startup = Conf::default();
+        let live = SharedLiveConf::new(&startup);
+        let before = live.snapshot();
+
+        let file = config_file(
+            r#"{
+                "listen_port": 8000,
+                "enable_cors": false,
+                "nats": {"host": "localhost:4222"},
+                "allowed_origins": ["https://stockwayup.com"],
+                "is_debug": false,
+                "routes": {"/api/v1/plans": {"enabled": false}}
+            }"#,
+        );
+
+        reload(&args_for(&file), &startup, &live).expect("reload should succeed");
+
+        let after = live.snapshot();
+        assert!(after.is_origin_allowed(b"https://stockwayup.com"));
+        assert!(!after.is_route_enabled("/api/v1/plans"));
+        assert!(!before.is_origin_allowed(b"https://stockwayup.com"));
+    }
+
+    #[test]
+    fn test_reload_keeps_previous_settings_on_invalid_config() {
+        let startup = Conf {
+            allowed_origins: vec!["http://localhost".to_string()],
+            ..Default::default()
+        };
+        let live = SharedLiveConf::new(&startup);
+
+        let file = config_file(
+            r#"{
+                "listen_port": 8000,
+                "enable_cors": false,
+                "nats": {"host": "localhost:4222"},
+                "allowed_origins": ["not an origin"],
+                "is_debug": false
+            }"#,
+        );
+
+        assert!(reload(&args_for(&file), &startup, &live).is_err());
+        assert!(live.snapshot().is_origin_allowed(b"http://localhost"));
+    }
+}
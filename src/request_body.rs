@@ -0,0 +1,338 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::task::{Context, Poll};
+
+use async_nats::jetstream::object_store::ObjectInfo;
+use async_nats::Client;
+use axum::body::{boxed, Bytes, HttpBody};
+use axum::extract::{BodyStream, MatchedPath};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::{Request, Response as HttpResponse, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::BoxError;
+use futures::future::BoxFuture;
+use futures::{Stream, StreamExt, TryStreamExt};
+use http_body::{Full, LengthLimitError, Limited};
+use tokio_util::io::StreamReader;
+use tower::{Layer, Service};
+use tracing::warn;
+use uuid::Uuid;
+
+use crate::conf::{BodyUpload, Conf};
+use crate::handlers::{create_error_response, JSON_API_TYPE};
+use crate::reload::SharedLiveConf;
+
+/// Object store bucket of a staged body.
+pub const BUCKET_HEADER: &str = "body-bucket";
+/// Object name of a staged body, the request itself carries an empty body.
+pub const OBJECT_HEADER: &str = "body-object";
+
+/// Body limits and upload modes resolved from config, keyed by route template.
+#[derive(Debug, Clone)]
+pub struct RequestBodyPolicy {
+    default_limit: usize,
+    limits: HashMap<String, usize>,
+    bucket: Option<String>,
+    staged: HashSet<String>,
+}
+
+impl RequestBodyPolicy {
+    pub fn from_conf(conf: &Conf) -> Self {
+        RequestBodyPolicy {
+            default_limit: conf.request_body.limit_bytes,
+            limits: conf
+                .routes
+                .iter()
+                .filter_map(|(route, route_conf)| {
+                    route_conf
+                        .body_limit_bytes
+                        .map(|limit| (route.clone(), limit))
+                })
+                .collect(),
+            bucket: conf.request_body.object_store_bucket.clone(),
+            staged: conf
+                .routes
+                .iter()
+                .filter(|(_, route_conf)| route_conf.upload == BodyUpload::ObjectStore)
+                .map(|(route, _)| route.clone())
+                .collect(),
+        }
+    }
+
+    pub fn limit(&self, route: &str) -> usize {
+        self.limits
+            .get(route)
+            .copied()
+            .unwrap_or(self.default_limit)
+    }
+
+    pub fn bucket(&self) -> Option<&str> {
+        self.bucket.as_deref()
+    }
+
+    /// The bucket to stage the route's bodies in, `None` for inline bodies.
+    pub fn staging_bucket(&self, route: &str) -> Option<&str> {
+        self.bucket().filter(|_| self.staged.contains(route))
+    }
+}
+
+/// JSON:API 413 for a body over `limit` bytes.
+pub fn too_large(limit: usize) -> HttpResponse<Full<Bytes>> {
+    create_error_response(
+        StatusCode::PAYLOAD_TOO_LARGE,
+        "413",
+        "Payload too large",
+        &format!("The request body exceeds the limit of {limit} bytes."),
+    )
+}
+
+/// Applies the route's body limit: a declared `Content-Length` over it is rejected
+/// up front, a longer chunked body fails when read. Both get a JSON:API 413.
+#[derive(Clone)]
+pub struct RequestBodyLimitLayer {
+    live: SharedLiveConf,
+}
+
+impl RequestBodyLimitLayer {
+    pub fn new(live: SharedLiveConf) -> Self {
+        RequestBodyLimitLayer { live }
+    }
+}
+
+impl<S> Layer<S> for RequestBodyLimitLayer {
+    type Service = RequestBodyLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RequestBodyLimit {
+            inner,
+            live: self.live.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RequestBodyLimit<S> {
+    inner: S,
+    live: SharedLiveConf,
+}
+
+impl<S, B, ResBody> Service<Request<B>> for RequestBodyLimit<S>
+where
+    S: Service<Request<Limited<B>>, Response = HttpResponse<ResBody>>,
+    S::Future: Send + 'static,
+    ResBody: HttpBody<Data = Bytes> + Send + 'static,
+    ResBody::Error: Into<BoxError>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map_or("", |path| path.as_str());
+        let limit = self.live.snapshot().request_body.limit(route);
+
+        let declared = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+
+        if declared.is_some_and(|length| length > limit as u64) {
+            return Box::pin(async move { Ok(too_large(limit).into_response()) });
+        }
+
+        let resp = self.inner.call(req.map(|body| Limited::new(body, limit)));
+
+        Box::pin(async move {
+            let resp = resp.await?;
+
+            // Extractors reject an over-long body with a plain text 413.
+            let is_json_api = resp
+                .headers()
+                .get(CONTENT_TYPE)
+                .is_some_and(|value| value == JSON_API_TYPE);
+
+            if resp.status() == StatusCode::PAYLOAD_TOO_LARGE && !is_json_api {
+                Ok(too_large(limit).into_response())
+            } else {
+                Ok(resp.map(boxed))
+            }
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum BodyError {
+    /// Longer than the route's limit.
+    TooLarge,
+    Read(axum::Error),
+    Store(String),
+}
+
+impl From<axum::Error> for BodyError {
+    fn from(e: axum::Error) -> Self {
+        if is_length_limit(&e) {
+            BodyError::TooLarge
+        } else {
+            BodyError::Read(e)
+        }
+    }
+}
+
+fn is_length_limit(e: &axum::Error) -> bool {
+    std::error::Error::source(e).is_some_and(|source| source.is::<LengthLimitError>())
+}
+
+/// Buffers an inline body.
+pub async fn read_body(mut body: BodyStream) -> Result<Bytes, BodyError> {
+    let mut buf = vec![];
+
+    while let Some(chunk) = body.next().await {
+        buf.extend_from_slice(&chunk?);
+    }
+
+    Ok(Bytes::from(buf))
+}
+
+#[derive(Debug)]
+pub enum StageError<E> {
+    /// The staged stream failed.
+    Body(E),
+    Store(String),
+}
+
+/// Streams `body` into the object store chunk by chunk under a new name. Chunks
+/// written before a failure have no object and are left to the bucket's max age.
+pub async fn stage<S, E>(
+    client: &Client,
+    bucket: &str,
+    body: S,
+) -> Result<ObjectInfo, StageError<E>>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    let store = async_nats::jetstream::new(client.clone())
+        .get_object_store(bucket)
+        .await
+        .map_err(|e| StageError::Store(format!("can't open object store {bucket}, {e}")))?;
+
+    let mut failed = None;
+    let mut reader = StreamReader::new(body.map_err(|e| {
+        failed = Some(e);
+        io::Error::other("request body failed")
+    }));
+
+    let name = Uuid::new_v4().to_string();
+    let result = store.put(name.as_str(), &mut reader).await;
+    drop(reader);
+
+    match (result, failed) {
+        (_, Some(e)) => Err(StageError::Body(e)),
+        (Ok(object), None) => Ok(object),
+        (Err(e), None) => Err(StageError::Store(format!(
+            "can't put object into {bucket}, {e}"
+        ))),
+    }
+}
+
+/// Stages a request body as a whole.
+pub async fn stage_body(
+    client: &Client,
+    bucket: &str,
+    body: BodyStream,
+) -> Result<ObjectInfo, BodyError> {
+    stage(client, bucket, body).await.map_err(|e| match e {
+        StageError::Body(e) => e.into(),
+        StageError::Store(e) => BodyError::Store(e),
+    })
+}
+
+/// Removes an object the backend never received, failures only leave garbage behind.
+pub async fn discard_staged(client: &Client, bucket: &str, name: &str) {
+    let store = match async_nats::jetstream::new(client.clone())
+        .get_object_store(bucket)
+        .await
+    {
+        Ok(store) => store,
+        Err(e) => {
+            warn!(error = %e, bucket, object = name, "can't discard staged body");
+            return;
+        }
+    };
+
+    if let Err(e) = store.delete(name).await {
+        warn!(error = %e, bucket, object = name, "can't discard staged body");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::RouteConf;
+    use axum::body::Body;
+    use axum::routing::put;
+    use axum::{Json, Router};
+    use tower::ServiceExt;
+
+    const IMPORTS: &str = "/api/v1/portfolios/:pid/imports";
+
+    fn conf() -> Conf {
+        let mut conf = Conf::default();
+        conf.request_body.limit_bytes = 16;
+        conf.request_body.object_store_bucket = Some("uploads".to_string());
+        conf.routes.insert(
+            IMPORTS.to_string(),
+            RouteConf {
+                body_limit_bytes: Some(1024),
+                upload: BodyUpload::ObjectStore,
+                ..Default::default()
+            },
+        );
+        conf
+    }
+
+    #[test]
+    fn test_policy_per_route() {
+        let policy = RequestBodyPolicy::from_conf(&conf());
+
+        assert_eq!(policy.limit(IMPORTS), 1024);
+        assert_eq!(policy.limit("/api/v1/sessions"), 16);
+        assert_eq!(policy.staging_bucket(IMPORTS), Some("uploads"));
+        assert_eq!(policy.staging_bucket("/api/v1/sessions"), None);
+    }
+
+    #[tokio::test]
+    async fn test_extractor_rejection_becomes_json_api() {
+        let live = SharedLiveConf::new(&conf());
+        let app = Router::new()
+            .route(
+                "/filter",
+                put(|Json(value): Json<serde_json::Value>| async { Json(value) }),
+            )
+            .layer(RequestBodyLimitLayer::new(live));
+
+        // No Content-Length, the extractor hits the limit while buffering.
+        let body = Body::wrap_stream(futures::stream::iter(
+            ["{\"filter\": ", "\"info,http2=debug\"}"].map(Ok::<_, io::Error>),
+        ));
+        let resp = app
+            .oneshot(
+                Request::put("/filter")
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(body)
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(resp.headers()[CONTENT_TYPE], JSON_API_TYPE);
+    }
+}
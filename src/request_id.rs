@@ -0,0 +1,119 @@
+use axum::http::{HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::fmt;
+use std::sync::Arc;
+use uuid::Uuid;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    static CURRENT: RequestId;
+}
+
+/// Identifies a request in responses, logs and the NATS `id` header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(Arc<str>);
+
+impl RequestId {
+    pub fn generate() -> Self {
+        RequestId(Uuid::new_v4().to_string().into())
+    }
+
+    /// Accepts a client-supplied id of up to 128 characters from `[A-Za-z0-9._:-]`.
+    pub fn parse(value: &str) -> Option<Self> {
+        let valid = !value.is_empty()
+            && value.len() <= MAX_REQUEST_ID_LEN
+            && value
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':'));
+
+        valid.then(|| RequestId(value.into()))
+    }
+
+    pub fn from_request<B>(req: &Request<B>) -> Self {
+        req.headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(RequestId::parse)
+            .unwrap_or_else(RequestId::generate)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The id of the request being handled by the current task.
+    pub fn current() -> Option<RequestId> {
+        CURRENT.try_with(|id| id.clone()).ok()
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Assigns the request id, makes it available to handlers and echoes it in the response.
+pub async fn propagate_request_id<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let id = RequestId::from_request(&req);
+
+    req.extensions_mut().insert(id.clone());
+
+    let mut resp = CURRENT.scope(id.clone(), next.run(req)).await;
+
+    if let Ok(value) = HeaderValue::from_str(id.as_str()) {
+        resp.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_accepts_uuid_and_opaque_ids() {
+        assert!(RequestId::parse("8d771b67-ed4e-4775-9033-af0e5c20c5f0").is_some());
+        assert!(RequestId::parse("web:checkout.42_a").is_some());
+    }
+
+    #[test]
+    fn test_parse_rejects_invalid_ids() {
+        assert!(RequestId::parse("").is_none());
+        assert!(RequestId::parse("has space").is_none());
+        assert!(RequestId::parse("line\nbreak").is_none());
+        assert!(RequestId::parse("ünïcode").is_none());
+        assert!(RequestId::parse(&"a".repeat(MAX_REQUEST_ID_LEN + 1)).is_none());
+        assert!(RequestId::parse(&"a".repeat(MAX_REQUEST_ID_LEN)).is_some());
+    }
+
+    #[test]
+    fn test_from_request_replaces_invalid_header() {
+        let req = Request::builder()
+            .header(REQUEST_ID_HEADER, "not valid!")
+            .body(())
+            .unwrap();
+
+        let id = RequestId::from_request(&req);
+
+        assert_ne!(id.as_str(), "not valid!");
+        assert!(Uuid::parse_str(id.as_str()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_current_is_scoped_to_the_task() {
+        assert!(RequestId::current().is_none());
+
+        let id = RequestId::parse("req-1").unwrap();
+
+        CURRENT
+            .scope(id.clone(), async move {
+                assert_eq!(RequestId::current(), Some(id));
+            })
+            .await;
+    }
+}
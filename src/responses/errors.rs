@@ -10,6 +10,26 @@ pub struct Error {
     pub code: String,
     pub title: String,
     pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<ErrorSource>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<ErrorMeta>,
+}
+
+/// What part of the request caused the error.
+#[derive(Serialize, Default)]
+pub struct ErrorSource {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pointer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parameter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct ErrorMeta {
+    pub request_id: String,
 }
 
 #[cfg(test)]
@@ -23,6 +43,8 @@ mod tests {
             code: "404".to_string(),
             title: "Not Found".to_string(),
             detail: "The requested resource was not found".to_string(),
+            source: None,
+            meta: None,
         };
 
         let json = serde_json::to_string(&error).expect("Should serialize error");
@@ -39,11 +61,15 @@ mod tests {
                     code: "400".to_string(),
                     title: "Bad Request".to_string(),
                     detail: "Invalid request format".to_string(),
+                    source: None,
+                    meta: None,
                 },
                 Error {
                     code: "401".to_string(),
                     title: "Unauthorized".to_string(),
                     detail: "Authentication required".to_string(),
+                    source: None,
+                    meta: None,
                 },
             ],
         };
@@ -70,6 +96,8 @@ mod tests {
             code: "".to_string(),
             title: "".to_string(),
             detail: "".to_string(),
+            source: None,
+            meta: None,
         };
 
         let json = serde_json::to_string(&error).expect("Should serialize error with empty fields");
@@ -77,4 +105,51 @@ mod tests {
         assert!(json.contains("\"title\":\"\""));
         assert!(json.contains("\"detail\":\"\""));
     }
+
+    #[test]
+    fn test_error_meta_serialization() {
+        let error = Error {
+            code: "404".to_string(),
+            title: "Not Found".to_string(),
+            detail: "".to_string(),
+            source: None,
+            meta: Some(ErrorMeta {
+                request_id: "req-1".to_string(),
+            }),
+        };
+
+        let json = serde_json::to_string(&error).expect("Should serialize error with meta");
+        assert!(json.contains("\"meta\":{\"request_id\":\"req-1\"}"));
+    }
+
+    #[test]
+    fn test_error_without_meta_omits_it() {
+        let error = Error {
+            code: "404".to_string(),
+            title: "Not Found".to_string(),
+            detail: "".to_string(),
+            source: None,
+            meta: None,
+        };
+
+        let json = serde_json::to_string(&error).expect("Should serialize error");
+        assert!(!json.contains("meta"));
+    }
+
+    #[test]
+    fn test_error_source_serialization() {
+        let error = Error {
+            code: "415".to_string(),
+            title: "Unsupported Media Type".to_string(),
+            detail: "".to_string(),
+            source: Some(ErrorSource {
+                header: Some("Content-Type".to_string()),
+                ..Default::default()
+            }),
+            meta: None,
+        };
+
+        let json = serde_json::to_string(&error).expect("Should serialize error with source");
+        assert!(json.contains("\"source\":{\"header\":\"Content-Type\"}"));
+    }
 }
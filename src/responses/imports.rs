@@ -0,0 +1,23 @@
+use serde_derive::{Deserialize, Serialize};
+
+use super::links::Links;
+
+#[derive(Serialize, Deserialize)]
+pub struct Import {
+    pub data: ImportData,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImportData {
+    pub id: String,
+    pub r#type: String,
+    pub attributes: ImportAttributes,
+    pub links: Links,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ImportAttributes {
+    pub status: String,
+    pub file_name: Option<String>,
+    pub size: usize,
+}
@@ -0,0 +1,25 @@
+use serde_derive::{Deserialize, Serialize};
+
+use super::links::Links;
+
+#[derive(Serialize, Deserialize)]
+pub struct Job {
+    pub data: JobData,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JobData {
+    pub id: String,
+    pub r#type: String,
+    pub attributes: JobAttributes,
+    pub links: Links,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JobAttributes {
+    pub status: String,
+    /// Status code of the backend's reply, once there is one.
+    pub code: Option<u16>,
+    /// The backend's reply document.
+    pub result: Option<serde_json::Value>,
+}
@@ -0,0 +1,7 @@
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct Links {
+    #[serde(rename = "self")]
+    pub self_link: String,
+}
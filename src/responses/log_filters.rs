@@ -0,0 +1,44 @@
+use serde_derive::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct LogFilters {
+    pub data: LogFiltersData,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LogFiltersData {
+    pub id: String,
+    pub r#type: String,
+    pub attributes: LogFilterAttributes,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LogFilterAttributes {
+    pub filter: String,
+    pub base_filter: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn test_log_filters_serialization() {
+        let log_filters = LogFilters {
+            data: LogFiltersData {
+                id: "current".to_string(),
+                r#type: "log-filters".to_string(),
+                attributes: LogFilterAttributes {
+                    filter: "debug".to_string(),
+                    base_filter: "info".to_string(),
+                },
+            },
+        };
+
+        let json = serde_json::to_string(&log_filters).expect("Should serialize log filters");
+        assert!(json.contains("\"type\":\"log-filters\""));
+        assert!(json.contains("\"filter\":\"debug\""));
+        assert!(json.contains("\"base_filter\":\"info\""));
+    }
+}
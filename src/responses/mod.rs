@@ -1,2 +1,6 @@
 pub mod errors;
+pub mod imports;
+pub mod jobs;
+pub mod links;
+pub mod log_filters;
 pub mod statuses;
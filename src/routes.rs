@@ -1,156 +1,417 @@
-use async_nats::Client;
-use axum::http::{header, HeaderValue};
 use axum::{
+    body::{Bytes, HttpBody},
     http::Method,
-    routing::{any, delete, get, post},
-    Extension, Router,
+    middleware,
+    routing::{any, get, MethodRouter},
+    BoxError, Extension, Router,
 };
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tower_http::{cors::CorsLayer, limit::RequestBodyLimitLayer, trace::TraceLayer};
+use tower_http::trace::TraceLayer;
 use tracing::info_span;
 
+use crate::access_log::access_log;
+use crate::admin::{
+    check_admin_access, get_log_filter, update_log_filter, AdminAccess, AdminToken,
+};
+use crate::conf::Conf;
+use crate::cors::cors_layer;
 use crate::handlers::*;
-use crate::metrics::AppMetrics;
+use crate::imports::{create_import, IMPORTS_ROUTE, IMPORT_ROUTE};
+use crate::jobs::{job_status, Jobs, JOB_ROUTE};
+use crate::json_api::check_media_types;
+use crate::methods::{methods, strip_head_body, with_allow};
+use crate::metrics::{record_metrics, AppMetrics};
+use crate::nats::SharedClient;
+use crate::panics::catch_panic;
+use crate::path_params::{ParamType, PathParams};
+use crate::reload::SharedLiveConf;
+use crate::request_body::RequestBodyLimitLayer;
+use crate::request_id::{propagate_request_id, RequestId};
+use crate::schemas::RequestSchemas;
+use crate::security_headers::security_headers;
+use crate::shutdown::{cut_off_on_timeout, SharedDrain};
 
-const BODY_SIZE: usize = 1024 * 250;
 const API_V1: &str = "/api/v1";
 
+/// Public routes with the types of their path parameters, which the handlers check
+/// before anything is sent over NATS.
+struct ApiRoutes<B> {
+    router: Router<B>,
+    params: Vec<(String, &'static [(&'static str, ParamType)])>,
+}
+
+impl<B> ApiRoutes<B>
+where
+    B: HttpBody + Send + 'static,
+{
+    fn route(
+        mut self,
+        template: &str,
+        params: &'static [(&'static str, ParamType)],
+        service: MethodRouter<B>,
+    ) -> Self {
+        self.router = self.router.route(template, service);
+        self.params.push((template.to_string(), params));
+        self
+    }
+
+    fn path_params(&self) -> PathParams {
+        PathParams::new(
+            self.params
+                .iter()
+                .map(|(route, types)| (route.as_str(), *types)),
+        )
+    }
+}
+
+fn api_routes<B>() -> ApiRoutes<B>
+where
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    Bytes: From<B::Data>,
+{
+    ApiRoutes {
+        router: Router::new(),
+        params: vec![],
+    }
+    .route(
+        &format!("{}/statuses", API_V1),
+        &[],
+        methods(&[Method::GET], health_check),
+    )
+    .route(
+        &format!("{}/users", API_V1),
+        &[],
+        methods(&[Method::POST], proxy),
+    )
+    .route(
+        &format!("{}/users/:uid/news", API_V1),
+        &[("uid", ParamType::Uuid)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/users/:uid/earnings", API_V1),
+        &[("uid", ParamType::Uuid)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/users/:uid/dividends", API_V1),
+        &[("uid", ParamType::Uuid)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/users/:uid", API_V1),
+        &[("uid", ParamType::Uuid)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/users/:uid/day-prices", API_V1),
+        &[("uid", ParamType::Uuid)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/users/:uid/day-price-periods", API_V1),
+        &[("uid", ParamType::Uuid)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/users/:uid/view-history", API_V1),
+        &[("uid", ParamType::Uuid)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/refresh-tokens", API_V1),
+        &[],
+        methods(&[Method::POST], proxy),
+    )
+    .route(
+        &format!("{}/refresh-tokens/:refresh-token", API_V1),
+        &[("refresh-token", ParamType::Opaque)],
+        methods(&[Method::DELETE], proxy),
+    )
+    .route(
+        &format!("{}/sessions", API_V1),
+        &[],
+        methods(&[Method::POST], proxy),
+    )
+    .route(
+        &format!("{}/confirmation-codes", API_V1),
+        &[],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/confirmation-codes/:id", API_V1),
+        &[("id", ParamType::Slug)],
+        methods(&[Method::POST], proxy),
+    )
+    .route(
+        &format!("{}/password-confirmation-codes", API_V1),
+        &[],
+        methods(&[Method::POST], proxy),
+    )
+    .route(
+        &format!("{}/password-confirmation-codes/:id", API_V1),
+        &[("id", ParamType::Slug)],
+        methods(&[Method::POST], proxy),
+    )
+    .route(
+        &format!("{}/plans", API_V1),
+        &[],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/portfolios", API_V1),
+        &[],
+        methods(&[Method::GET, Method::POST], proxy),
+    )
+    .route(
+        &format!("{}/portfolios/:pid", API_V1),
+        &[("pid", ParamType::PositiveInt)],
+        methods(&[Method::GET, Method::PATCH, Method::DELETE], proxy),
+    )
+    .route(
+        IMPORTS_ROUTE,
+        &[("pid", ParamType::PositiveInt)],
+        methods(&[Method::POST], create_import),
+    )
+    .route(
+        IMPORT_ROUTE,
+        &[("pid", ParamType::PositiveInt), ("iid", ParamType::Uuid)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        JOB_ROUTE,
+        &[("jid", ParamType::Uuid)],
+        methods(&[Method::GET], job_status),
+    )
+    .route(
+        &format!("{}/portfolios/:pid/relationships/securities", API_V1),
+        &[("pid", ParamType::PositiveInt)],
+        methods(&[Method::POST, Method::DELETE], proxy),
+    )
+    .route(
+        &format!("{}/portfolios/:pid/securities/:sid/transactions", API_V1),
+        &[("pid", ParamType::PositiveInt), ("sid", ParamType::Ticker)],
+        methods(&[Method::GET, Method::POST], proxy),
+    )
+    .route(
+        &format!(
+            "{}/portfolios/:pid/securities/:sid/transactions/:tid",
+            API_V1
+        ),
+        &[
+            ("pid", ParamType::PositiveInt),
+            ("sid", ParamType::Ticker),
+            ("tid", ParamType::PositiveInt),
+        ],
+        methods(&[Method::GET, Method::PATCH, Method::DELETE], proxy),
+    )
+    .route(
+        &format!("{}/portfolios/:pid/securities", API_V1),
+        &[("pid", ParamType::PositiveInt)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/portfolios/:pid/news", API_V1),
+        &[("pid", ParamType::PositiveInt)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/portfolios/:pid/earnings", API_V1),
+        &[("pid", ParamType::PositiveInt)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/portfolios/:pid/dividends", API_V1),
+        &[("pid", ParamType::PositiveInt)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/portfolios/:pid/day-prices", API_V1),
+        &[("pid", ParamType::PositiveInt)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/portfolios/:pid/day-price-periods", API_V1),
+        &[("pid", ParamType::PositiveInt)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/securities", API_V1),
+        &[],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/securities/:sid/news", API_V1),
+        &[("sid", ParamType::Ticker)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/securities/:sid/day-prices", API_V1),
+        &[("sid", ParamType::Ticker)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/securities/:sid/day-price-periods", API_V1),
+        &[("sid", ParamType::Ticker)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/securities/:sid/quarterly-balance-sheet", API_V1),
+        &[("sid", ParamType::Ticker)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/securities/:sid/annual-balance-sheet", API_V1),
+        &[("sid", ParamType::Ticker)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/securities/:sid/quarterly-income-statements", API_V1),
+        &[("sid", ParamType::Ticker)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/securities/:sid/annual-income-statements", API_V1),
+        &[("sid", ParamType::Ticker)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/securities/:sid", API_V1),
+        &[("sid", ParamType::Ticker)],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/countries", API_V1),
+        &[],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/currencies", API_V1),
+        &[],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/sectors", API_V1),
+        &[],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/industries", API_V1),
+        &[],
+        methods(&[Method::GET], proxy),
+    )
+    .route(
+        &format!("{}/exchanges", API_V1),
+        &[],
+        methods(&[Method::GET], proxy),
+    )
+}
+
+/// Route templates of the public API, the valid keys of the `routes` config section.
+pub fn route_templates() -> Vec<String> {
+    api_routes::<axum::body::Body>()
+        .params
+        .into_iter()
+        .map(|(route, _)| route)
+        .collect()
+}
+
+/// Probes, metrics and admin endpoints.
+fn admin_routes<B>(metrics_enabled: bool) -> Router<B>
+where
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    let router = Router::new()
+        .route("/readyz", methods(&[Method::GET], readiness_check))
+        .route(
+            "/admin/log-filter",
+            with_allow(
+                get(get_log_filter).put(update_log_filter),
+                &[Method::GET, Method::PUT],
+            ),
+        );
+
+    // Add /metrics endpoint if metrics are available
+    if metrics_enabled {
+        router.route("/metrics", methods(&[Method::GET], metrics_handler))
+    } else {
+        router
+    }
+}
+
+/// Router for the admin listener, every path is checked against `access`.
+pub fn build_admin_routes(
+    conf: &Conf,
+    access: AdminAccess,
+    drain: SharedDrain,
+    nats: SharedClient,
+    metrics: Option<Arc<AppMetrics>>,
+) -> Router {
+    admin_routes(metrics.as_ref().is_some_and(|m| m.prometheus_enabled()))
+        .fallback(any(not_found))
+        .layer(middleware::from_fn(strip_head_body))
+        .layer(Extension(nats))
+        .layer(Extension(drain))
+        .layer(Extension(AdminToken::from_conf(conf)))
+        .layer(Extension(metrics.clone()))
+        .layer(middleware::from_fn(move |req, next| {
+            check_admin_access(req, next, access.clone())
+        }))
+        .layer(middleware::from_fn(move |req, next| {
+            catch_panic(req, next, metrics.clone())
+        }))
+        .layer(middleware::from_fn(propagate_request_id))
+}
+
 pub fn build_routes(
-    allowed_origins: Vec<String>,
-    enable_cors: bool,
-    nats: Arc<RwLock<Client>>,
+    conf: &Conf,
+    live: SharedLiveConf,
+    drain: SharedDrain,
+    nats: SharedClient,
     metrics: Option<Arc<AppMetrics>>,
+    schemas: RequestSchemas,
 ) -> Router {
-    let cors_layer = if enable_cors {
-        let mut origins: Vec<HeaderValue> = Vec::new();
-
-        for origin in allowed_origins {
-            match HeaderValue::from_str(origin.as_str()) {
-                Ok(header_value) => origins.push(header_value),
-                Err(e) => {
-                    tracing::error!(error = %e, origin = %origin, "invalid CORS origin header value, skipping");
-                    continue;
-                }
-            }
-        }
-
-        Some(
-            CorsLayer::new()
-                .allow_origin(origins)
-                .allow_methods([Method::GET, Method::POST, Method::DELETE, Method::PATCH])
-                .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION]),
-        )
-    } else {
-        None
-    };
+    let access_log_live = live.clone();
+    let metrics_live = live.clone();
+    let security_headers_live = live.clone();
+    let json_api_live = live.clone();
+    let body_limit_live = live.clone();
 
-    let mut router = Router::new().route(&format!("{}/statuses", API_V1), get(health_check));
+    let cut_off_drain = drain.clone();
 
-    // Add /metrics endpoint if metrics are available
-    if metrics.is_some() {
-        router = router.route("/metrics", get(metrics_handler));
+    let cors_layer = conf.enable_cors.then(|| cors_layer(conf, live.clone()));
+
+    let api = api_routes();
+    let path_params = api.path_params();
+    let mut router = api.router;
+
+    // Moved to the admin listener when one is configured
+    if conf.admin.listener.is_none() {
+        router = router.merge(admin_routes(
+            metrics.as_ref().is_some_and(|m| m.prometheus_enabled()),
+        ));
     }
 
     let router = router
-        .route(&format!("{}/users", API_V1), post(proxy))
-        .route(&format!("{}/users/:uid/news", API_V1), get(proxy))
-        .route(&format!("{}/users/:uid/earnings", API_V1), get(proxy))
-        .route(&format!("{}/users/:uid/dividends", API_V1), get(proxy))
-        .route(&format!("{}/users/:uid", API_V1), get(proxy))
-        .route(&format!("{}/users/:uid/day-prices", API_V1), get(proxy))
-        .route(
-            &format!("{}/users/:uid/day-price-periods", API_V1),
-            get(proxy),
-        )
-        .route(&format!("{}/users/:uid/view-history", API_V1), get(proxy))
-        .route(&format!("{}/refresh-tokens", API_V1), post(proxy))
-        .route(
-            &format!("{}/refresh-tokens/:refresh-token", API_V1),
-            delete(proxy),
-        )
-        .route(&format!("{}/sessions", API_V1), post(proxy))
-        .route(&format!("{}/confirmation-codes", API_V1), get(proxy))
-        .route(&format!("{}/confirmation-codes/:id", API_V1), post(proxy))
-        .route(
-            &format!("{}/password-confirmation-codes", API_V1),
-            post(proxy),
-        )
-        .route(
-            &format!("{}/password-confirmation-codes/:id", API_V1),
-            post(proxy),
-        )
-        .route(&format!("{}/plans", API_V1), get(proxy))
-        .route(&format!("{}/portfolios", API_V1), get(proxy).post(proxy))
-        .route(
-            &format!("{}/portfolios/:pid", API_V1),
-            get(proxy).patch(proxy).delete(proxy),
-        )
-        .route(
-            &format!("{}/portfolios/:pid/relationships/securities", API_V1),
-            post(proxy).delete(proxy),
-        )
-        .route(
-            &format!("{}/portfolios/:pid/securities/:sid/transactions", API_V1),
-            get(proxy).post(proxy),
-        )
-        .route(
-            &format!(
-                "{}/portfolios/:pid/securities/:sid/transactions/:tid",
-                API_V1
-            ),
-            get(proxy).patch(proxy).delete(proxy),
-        )
-        .route(
-            &format!("{}/portfolios/:pid/securities", API_V1),
-            get(proxy),
-        )
-        .route(&format!("{}/portfolios/:pid/news", API_V1), get(proxy))
-        .route(&format!("{}/portfolios/:pid/earnings", API_V1), get(proxy))
-        .route(&format!("{}/portfolios/:pid/dividends", API_V1), get(proxy))
-        .route(
-            &format!("{}/portfolios/:pid/day-prices", API_V1),
-            get(proxy),
-        )
-        .route(
-            &format!("{}/portfolios/:pid/day-price-periods", API_V1),
-            get(proxy),
-        )
-        .route(&format!("{}/securities", API_V1), get(proxy))
-        .route(&format!("{}/securities/:sid/news", API_V1), get(proxy))
-        .route(
-            &format!("{}/securities/:sid/day-prices", API_V1),
-            get(proxy),
-        )
-        .route(
-            &format!("{}/securities/:sid/day-price-periods", API_V1),
-            get(proxy),
-        )
-        .route(
-            &format!("{}/securities/:sid/quarterly-balance-sheet", API_V1),
-            get(proxy),
-        )
-        .route(
-            &format!("{}/securities/:sid/annual-balance-sheet", API_V1),
-            get(proxy),
-        )
-        .route(
-            &format!("{}/securities/:sid/quarterly-income-statements", API_V1),
-            get(proxy),
-        )
-        .route(
-            &format!("{}/securities/:sid/annual-income-statements", API_V1),
-            get(proxy),
-        )
-        .route(&format!("{}/securities/:sid", API_V1), get(proxy))
-        .route(&format!("{}/countries", API_V1), get(proxy))
-        .route(&format!("{}/currencies", API_V1), get(proxy))
-        .route(&format!("{}/sectors", API_V1), get(proxy))
-        .route(&format!("{}/industries", API_V1), get(proxy))
-        .route(&format!("{}/exchanges", API_V1), get(proxy))
+        .fallback(any(not_found))
+        .layer(middleware::from_fn(strip_head_body))
         .layer(Extension(nats))
-        .layer(Extension(metrics))
-        .layer(RequestBodyLimitLayer::new(BODY_SIZE));
+        .layer(Extension(live))
+        .layer(Extension(drain))
+        .layer(Extension(AdminToken::from_conf(conf)))
+        .layer(Extension(metrics.clone()))
+        .layer(Extension(schemas))
+        .layer(Extension(path_params))
+        .layer(Extension(Jobs::from_conf(conf)))
+        .layer(Extension(conf.imports.clone()))
+        .layer(RequestBodyLimitLayer::new(body_limit_live))
+        .layer(middleware::from_fn(move |req, next| {
+            check_media_types(req, next, json_api_live.clone())
+        }));
 
     let router = if let Some(cors) = cors_layer {
         router.layer(cors)
@@ -158,6 +419,35 @@ pub fn build_routes(
         router
     };
 
+    // Inside metrics and the access log so requests cut off at the drain timeout are
+    // recorded as the 503 they become.
+    let router = router.layer(middleware::from_fn(move |req, next| {
+        cut_off_on_timeout(req, next, cut_off_drain.clone())
+    }));
+
+    // Inside metrics and the access log so a panic is recorded as the 500 it becomes.
+    let panic_metrics = metrics.clone();
+    let router = router.layer(middleware::from_fn(move |req, next| {
+        catch_panic(req, next, panic_metrics.clone())
+    }));
+
+    // Outside CORS and the body limit so their responses are counted too.
+    let router = if let Some(metrics) = metrics {
+        let cors_enabled = conf.enable_cors;
+
+        router.layer(middleware::from_fn(move |req, next| {
+            record_metrics(
+                req,
+                next,
+                metrics.clone(),
+                metrics_live.clone(),
+                cors_enabled,
+            )
+        }))
+    } else {
+        router
+    };
+
     router
         .layer(
             TraceLayer::new_for_http()
@@ -167,14 +457,27 @@ pub fn build_routes(
                         .get::<axum::extract::MatchedPath>()
                         .map(|mp| mp.as_str())
                         .unwrap_or("unknown");
+
+                    let request_id = request
+                        .extensions()
+                        .get::<RequestId>()
+                        .map(|id| id.as_str())
+                        .unwrap_or_default();
                     
                     info_span!(
                         "http_request",
                         method = %request.method(),
                         route = matched_path,
                         version = ?request.version(),
+                        request_id,
                     )
                 }),
         )
-        .fallback(any(not_found))
+        .layer(middleware::from_fn(move |req, next| {
+            access_log(req, next, access_log_live.clone())
+        }))
+        .layer(middleware::from_fn(move |req, next| {
+            security_headers(req, next, security_headers_live.clone())
+        }))
+        .layer(middleware::from_fn(propagate_request_id))
 }
@@ -0,0 +1,339 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use axum::http::{Method, Response, StatusCode};
+use http_body::Full;
+use jsonschema::error::{TypeKind, ValidationErrorKind};
+use jsonschema::{ValidationError, Validator};
+use serde_json::Value;
+
+use crate::conf::{Conf, ConfError};
+use crate::handlers::{create_error_response, create_errors_response};
+use crate::responses::errors::{Error, ErrorSource};
+
+/// Compiled `routes.<template>.request_schema` files keyed by route template.
+#[derive(Clone, Default)]
+pub struct RequestSchemas(Arc<HashMap<String, Validator>>);
+
+impl RequestSchemas {
+    /// Reads and compiles every configured schema, any unreadable or invalid file is
+    /// an error so the service doesn't start with a check missing.
+    pub fn load(conf: &Conf) -> Result<Self, ConfError> {
+        let mut schemas = HashMap::new();
+
+        for (route, route_conf) in &conf.routes {
+            let Some(path) = &route_conf.request_schema else {
+                continue;
+            };
+
+            let key = format!("routes.{route}.request_schema");
+
+            let contents = std::fs::read_to_string(path).map_err(|e| ConfError {
+                message: format!("can't read {key} {path}, {e}"),
+            })?;
+
+            let schema: Value = serde_json::from_str(&contents).map_err(|e| ConfError {
+                message: format!("{key} {path} is not valid JSON, {e}"),
+            })?;
+
+            let validator = jsonschema::validator_for(&schema).map_err(|e| ConfError {
+                message: format!("{key} {path} is not a valid JSON Schema, {e}"),
+            })?;
+
+            schemas.insert(route.clone(), validator);
+        }
+
+        Ok(RequestSchemas(Arc::new(schemas)))
+    }
+
+    /// Checks a POST, PUT or PATCH body against the route's schema. `None` when there
+    /// is nothing to check, otherwise the error response for an invalid body.
+    pub fn check(
+        &self,
+        route: &str,
+        method: &Method,
+        body: &[u8],
+    ) -> Option<Result<(), Response<Full<Bytes>>>> {
+        if !matches!(*method, Method::POST | Method::PUT | Method::PATCH) {
+            return None;
+        }
+
+        let validator = self.0.get(route)?;
+
+        let Ok(document) = serde_json::from_slice::<Value>(body) else {
+            return Some(Err(create_error_response(
+                StatusCode::BAD_REQUEST,
+                "400",
+                "Bad request",
+                "The request body is not valid JSON.",
+            )));
+        };
+
+        let errors: Vec<Error> = validator
+            .iter_errors(&document)
+            .map(|error| Error {
+                code: "422".to_string(),
+                title: "Invalid request body".to_string(),
+                detail: detail(&error),
+                source: Some(ErrorSource {
+                    pointer: Some(pointer(&error)),
+                    ..Default::default()
+                }),
+                meta: None,
+            })
+            .collect();
+
+        if errors.is_empty() {
+            Some(Ok(()))
+        } else {
+            Some(Err(create_errors_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                errors,
+            )))
+        }
+    }
+}
+
+/// Describes a failed check from the schema side only. The offending value can be a
+/// password or token, so it's never echoed back.
+fn detail(error: &ValidationError) -> String {
+    let problem = match &error.kind {
+        ValidationErrorKind::Type {
+            kind: TypeKind::Single(kind),
+        } => format!("must be of type {kind}"),
+        ValidationErrorKind::Type {
+            kind: TypeKind::Multiple(kinds),
+        } => format!(
+            "must be of type {}",
+            kinds
+                .into_iter()
+                .map(|kind| kind.to_string())
+                .collect::<Vec<_>>()
+                .join(" or ")
+        ),
+        ValidationErrorKind::Required { property } => {
+            format!("is missing the required property {property}")
+        }
+        ValidationErrorKind::AdditionalProperties { unexpected }
+        | ValidationErrorKind::UnevaluatedProperties { unexpected } => {
+            format!("has unexpected properties {}", unexpected.join(", "))
+        }
+        ValidationErrorKind::Constant { expected_value } => format!("must be {expected_value}"),
+        ValidationErrorKind::Enum { options } => format!("must be one of {options}"),
+        ValidationErrorKind::MinLength { limit } => {
+            format!("must be at least {limit} characters long")
+        }
+        ValidationErrorKind::MaxLength { limit } => {
+            format!("must be at most {limit} characters long")
+        }
+        ValidationErrorKind::Minimum { limit } => format!("must be at least {limit}"),
+        ValidationErrorKind::Maximum { limit } => format!("must be at most {limit}"),
+        ValidationErrorKind::ExclusiveMinimum { limit } => format!("must be greater than {limit}"),
+        ValidationErrorKind::ExclusiveMaximum { limit } => format!("must be less than {limit}"),
+        ValidationErrorKind::MultipleOf { multiple_of } => {
+            format!("must be a multiple of {multiple_of}")
+        }
+        ValidationErrorKind::MinItems { limit } => format!("must have at least {limit} items"),
+        ValidationErrorKind::MaxItems { limit } => format!("must have at most {limit} items"),
+        ValidationErrorKind::UniqueItems => "must not contain duplicate items".to_string(),
+        ValidationErrorKind::MinProperties { limit } => {
+            format!("must have at least {limit} properties")
+        }
+        ValidationErrorKind::MaxProperties { limit } => {
+            format!("must have at most {limit} properties")
+        }
+        ValidationErrorKind::Pattern { pattern } => format!("must match the pattern {pattern}"),
+        ValidationErrorKind::Format { format } => format!("must be a valid {format}"),
+        _ => "does not match the schema".to_string(),
+    };
+
+    match error.instance_path.as_str() {
+        "" => format!("The body {problem}."),
+        path => format!("{path} {problem}."),
+    }
+}
+
+/// JSON pointer to the offending value, a missing member points at the member itself
+/// rather than at the object lacking it.
+fn pointer(error: &ValidationError) -> String {
+    let path = error.instance_path.as_str();
+
+    match &error.kind {
+        ValidationErrorKind::Required {
+            property: Value::String(property),
+        } => format!("{path}/{}", property.replace('~', "~0").replace('/', "~1")),
+        _ => path.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::RouteConf;
+    use std::io::Write;
+
+    const ROUTE: &str = "/api/v1/portfolios";
+
+    fn schema_file(schema: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().expect("should create temp file");
+        write!(file, "{}", schema).expect("should write schema");
+        file
+    }
+
+    fn schemas(file: &tempfile::NamedTempFile) -> Result<RequestSchemas, ConfError> {
+        let mut conf = Conf::default();
+        conf.routes.insert(
+            ROUTE.to_string(),
+            RouteConf {
+                request_schema: Some(file.path().display().to_string()),
+                ..Default::default()
+            },
+        );
+        RequestSchemas::load(&conf)
+    }
+
+    async fn errors(resp: Response<Full<Bytes>>) -> Value {
+        let body = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn portfolio_schema() -> tempfile::NamedTempFile {
+        schema_file(
+            r#"{
+                "type": "object",
+                "required": ["data"],
+                "properties": {
+                    "data": {
+                        "type": "object",
+                        "required": ["type", "attributes"],
+                        "properties": {
+                            "type": {"const": "portfolios"},
+                            "attributes": {
+                                "type": "object",
+                                "required": ["name"],
+                                "properties": {"name": {"type": "string", "minLength": 1}}
+                            }
+                        }
+                    }
+                }
+            }"#,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_invalid_body_points_at_fields() {
+        let file = portfolio_schema();
+        let schemas = schemas(&file).unwrap();
+
+        let resp = schemas
+            .check(
+                ROUTE,
+                &Method::POST,
+                br#"{"data": {"type": "users", "attributes": {}}}"#,
+            )
+            .expect("route has a schema")
+            .expect_err("body is invalid");
+
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let body = errors(resp).await;
+        let mut pointers: Vec<_> = body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|error| error["source"]["pointer"].as_str().unwrap().to_string())
+            .collect();
+        pointers.sort();
+
+        assert_eq!(pointers, ["/data/attributes/name", "/data/type"]);
+        assert_eq!(body["errors"][0]["code"], "422");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_values_are_not_echoed_back() {
+        let file = schema_file(
+            r#"{
+                "type": "object",
+                "properties": {
+                    "password": {"type": "string", "minLength": 32, "pattern": "^[a-f0-9]+$"},
+                    "token": {"enum": ["a", "b"]},
+                    "pin": {"type": "integer"}
+                }
+            }"#,
+        );
+        let schemas = schemas(&file).unwrap();
+
+        let resp = schemas
+            .check(
+                ROUTE,
+                &Method::POST,
+                br#"{"password": "hunter2-secret", "token": "tok-9f8e7d", "pin": "4321"}"#,
+            )
+            .unwrap()
+            .unwrap_err();
+
+        let body = errors(resp).await;
+        let details = body["errors"].to_string();
+
+        assert!(
+            details.contains("/password must be at least 32 characters long."),
+            "{details}"
+        );
+        assert!(!details.contains("hunter2-secret"), "{details}");
+        assert!(!details.contains("tok-9f8e7d"), "{details}");
+        assert!(!details.contains("4321"), "{details}");
+    }
+
+    #[tokio::test]
+    async fn test_malformed_json_is_a_bad_request() {
+        let file = portfolio_schema();
+
+        let resp = schemas(&file)
+            .unwrap()
+            .check(ROUTE, &Method::POST, b"{\"data\":")
+            .unwrap()
+            .unwrap_err();
+
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_valid_body_and_unchecked_requests() {
+        let file = portfolio_schema();
+        let schemas = schemas(&file).unwrap();
+
+        let body = br#"{"data": {"type": "portfolios", "attributes": {"name": "Main"}}}"#;
+        assert!(schemas
+            .check(ROUTE, &Method::POST, body)
+            .is_some_and(|result| result.is_ok()));
+
+        assert!(schemas.check(ROUTE, &Method::GET, b"").is_none());
+        assert!(schemas.check("/api/v1/plans", &Method::POST, b"").is_none());
+    }
+
+    #[test]
+    fn test_load_rejects_bad_schemas() {
+        let file = schema_file(r#"{"type": "no-such-type"}"#);
+        let err = schemas(&file).err().expect("invalid schema should fail");
+        assert!(
+            err.message
+                .contains("routes./api/v1/portfolios.request_schema"),
+            "{}",
+            err.message
+        );
+
+        let file = schema_file("{");
+        assert!(schemas(&file).is_err());
+
+        let mut conf = Conf::default();
+        conf.routes.insert(
+            ROUTE.to_string(),
+            RouteConf {
+                request_schema: Some("/nonexistent/schema.json".to_string()),
+                ..Default::default()
+            },
+        );
+        assert!(RequestSchemas::load(&conf).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+
+use axum::extract::MatchedPath;
+use axum::http::header::{
+    CONTENT_SECURITY_POLICY, REFERRER_POLICY, STRICT_TRANSPORT_SECURITY, X_CONTENT_TYPE_OPTIONS,
+};
+use axum::http::{HeaderName, HeaderValue, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+
+use crate::access_log::UNMATCHED_ROUTE;
+use crate::conf::{Conf, SecurityHeadersRouteConf};
+use crate::reload::SharedLiveConf;
+
+const CROSS_ORIGIN_RESOURCE_POLICY: HeaderName =
+    HeaderName::from_static("cross-origin-resource-policy");
+
+type Headers = Vec<(HeaderName, HeaderValue)>;
+
+/// Security headers resolved from config, keyed by route template.
+#[derive(Debug, Clone)]
+pub struct SecurityHeadersPolicy {
+    default: Headers,
+    routes: HashMap<String, Headers>,
+}
+
+impl SecurityHeadersPolicy {
+    pub fn from_conf(conf: &Conf) -> Self {
+        let global = &conf.security_headers;
+
+        if !global.enabled {
+            return SecurityHeadersPolicy {
+                default: vec![],
+                routes: HashMap::new(),
+            };
+        }
+
+        let resolve = |overrides: &SecurityHeadersRouteConf| -> Headers {
+            [
+                (
+                    STRICT_TRANSPORT_SECURITY,
+                    overrides
+                        .strict_transport_security
+                        .as_ref()
+                        .unwrap_or(&global.strict_transport_security),
+                ),
+                (
+                    X_CONTENT_TYPE_OPTIONS,
+                    overrides
+                        .content_type_options
+                        .as_ref()
+                        .unwrap_or(&global.content_type_options),
+                ),
+                (
+                    REFERRER_POLICY,
+                    overrides
+                        .referrer_policy
+                        .as_ref()
+                        .unwrap_or(&global.referrer_policy),
+                ),
+                (
+                    CROSS_ORIGIN_RESOURCE_POLICY,
+                    overrides
+                        .cross_origin_resource_policy
+                        .as_ref()
+                        .unwrap_or(&global.cross_origin_resource_policy),
+                ),
+                (
+                    CONTENT_SECURITY_POLICY,
+                    overrides
+                        .content_security_policy
+                        .as_ref()
+                        .unwrap_or(&global.content_security_policy),
+                ),
+            ]
+            .into_iter()
+            // Empty values leave the header out, invalid ones are rejected by `Conf::validate`.
+            .filter(|(_, value)| !value.is_empty())
+            .filter_map(|(name, value)| Some((name, HeaderValue::from_str(value).ok()?)))
+            .collect()
+        };
+
+        let default = resolve(&SecurityHeadersRouteConf::default());
+
+        SecurityHeadersPolicy {
+            routes: conf
+                .routes
+                .iter()
+                .map(|(route, route_conf)| (route.clone(), resolve(&route_conf.security_headers)))
+                .filter(|(_, headers)| *headers != default)
+                .collect(),
+            default,
+        }
+    }
+
+    fn for_route(&self, route: &str) -> &Headers {
+        self.routes.get(route).unwrap_or(&self.default)
+    }
+
+    /// Sets the route's headers, replacing whatever handlers or the backend set.
+    fn apply(&self, route: &str, resp: &mut Response) {
+        for (name, value) in self.for_route(route) {
+            resp.headers_mut().insert(name.clone(), value.clone());
+        }
+    }
+}
+
+/// Adds the security headers to every response, including fallbacks and responses
+/// produced by other layers.
+pub async fn security_headers<B>(req: Request<B>, next: Next<B>, live: SharedLiveConf) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(UNMATCHED_ROUTE, |path| path.as_str())
+        .to_string();
+
+    let mut resp = next.run(req).await;
+
+    live.snapshot().security_headers.apply(&route, &mut resp);
+
+    resp
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::RouteConf;
+
+    fn policy(configure: impl FnOnce(&mut Conf)) -> SecurityHeadersPolicy {
+        let mut conf = Conf::default();
+        configure(&mut conf);
+        SecurityHeadersPolicy::from_conf(&conf)
+    }
+
+    #[test]
+    fn test_defaults_are_applied() {
+        let mut resp = Response::default();
+
+        policy(|_| {}).apply("/api/v1/plans", &mut resp);
+
+        let headers = resp.headers();
+        assert_eq!(headers[X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(headers[REFERRER_POLICY], "no-referrer");
+        assert_eq!(headers[CROSS_ORIGIN_RESOURCE_POLICY], "same-origin");
+        assert!(headers.contains_key(STRICT_TRANSPORT_SECURITY));
+        assert!(headers[CONTENT_SECURITY_POLICY]
+            .to_str()
+            .unwrap()
+            .contains("default-src 'none'"));
+    }
+
+    #[test]
+    fn test_handler_values_are_replaced() {
+        let mut resp = Response::default();
+        resp.headers_mut()
+            .insert(CONTENT_SECURITY_POLICY, HeaderValue::from_static("*"));
+        resp.headers_mut()
+            .append(CONTENT_SECURITY_POLICY, HeaderValue::from_static("*"));
+
+        policy(|_| {}).apply("/api/v1/plans", &mut resp);
+
+        let values: Vec<_> = resp
+            .headers()
+            .get_all(CONTENT_SECURITY_POLICY)
+            .iter()
+            .collect();
+        assert_eq!(values, ["default-src 'none'; frame-ancestors 'none'"]);
+    }
+
+    #[test]
+    fn test_route_overrides() {
+        let policy = policy(|conf| {
+            conf.routes.insert(
+                "/api/v1/users/:uid/news".to_string(),
+                RouteConf {
+                    security_headers: SecurityHeadersRouteConf {
+                        cross_origin_resource_policy: Some("cross-origin".to_string()),
+                        strict_transport_security: Some(String::new()),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+            );
+        });
+
+        let mut resp = Response::default();
+        policy.apply("/api/v1/users/:uid/news", &mut resp);
+        assert_eq!(resp.headers()[CROSS_ORIGIN_RESOURCE_POLICY], "cross-origin");
+        assert!(!resp.headers().contains_key(STRICT_TRANSPORT_SECURITY));
+        assert_eq!(resp.headers()[X_CONTENT_TYPE_OPTIONS], "nosniff");
+
+        let mut resp = Response::default();
+        policy.apply(UNMATCHED_ROUTE, &mut resp);
+        assert_eq!(resp.headers()[CROSS_ORIGIN_RESOURCE_POLICY], "same-origin");
+    }
+
+    #[test]
+    fn test_disabled_sets_nothing() {
+        let mut resp = Response::default();
+
+        policy(|conf| conf.security_headers.enabled = false).apply("/api/v1/plans", &mut resp);
+
+        assert!(resp.headers().is_empty());
+    }
+}
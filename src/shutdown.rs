@@ -0,0 +1,138 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use axum::http::{Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use tokio::sync::Notify;
+
+use crate::handlers::create_error_response;
+
+/// Tracks readiness and in-flight proxied requests while the service shuts down.
+#[derive(Debug, Default)]
+pub struct Drain {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    cutting_off: AtomicBool,
+    cut_off: Notify,
+}
+
+pub type SharedDrain = Arc<Drain>;
+
+impl Drain {
+    /// Marks the service as not ready, load balancers stop sending new requests.
+    pub fn start_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Ends every request still running, called when the drain timeout is reached.
+    pub fn cut_off(&self) {
+        self.cutting_off.store(true, Ordering::SeqCst);
+        self.cut_off.notify_waiters();
+    }
+
+    /// Resolves once `cut_off` is called.
+    pub async fn cut_off_requested(&self) {
+        let notified = self.cut_off.notified();
+
+        if self.cutting_off.load(Ordering::SeqCst) {
+            return;
+        }
+
+        notified.await;
+    }
+
+    /// Counts a request as in flight until the returned guard is dropped.
+    pub fn track(self: &Arc<Self>) -> InFlightGuard {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+
+        InFlightGuard {
+            drain: self.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct InFlightGuard {
+    drain: SharedDrain,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.drain.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Drops the handler of a request still running at the drain timeout and answers 503,
+/// so its connection closes before the NATS client does.
+pub async fn cut_off_on_timeout<B>(req: Request<B>, next: Next<B>, drain: SharedDrain) -> Response {
+    tokio::select! {
+        resp = next.run(req) => resp,
+        _ = drain.cut_off_requested() => create_error_response(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "503",
+            "Service unavailable",
+            "The service is shutting down.",
+        )
+        .into_response(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_start_draining() {
+        let drain = Drain::default();
+        assert!(!drain.is_draining());
+
+        drain.start_draining();
+        assert!(drain.is_draining());
+    }
+
+    #[test]
+    fn test_guard_tracks_in_flight() {
+        let drain: SharedDrain = Arc::new(Drain::default());
+
+        let first = drain.track();
+        let second = drain.track();
+        assert_eq!(drain.in_flight(), 2);
+
+        drop(first);
+        assert_eq!(drain.in_flight(), 1);
+
+        drop(second);
+        assert_eq!(drain.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_cut_off_ends_running_requests() {
+        let drain: SharedDrain = Arc::new(Drain::default());
+
+        let waiting = tokio::spawn({
+            let drain = drain.clone();
+            async move {
+                let _in_flight = drain.track();
+                drain.cut_off_requested().await;
+            }
+        });
+
+        tokio::task::yield_now().await;
+        drain.cut_off();
+
+        waiting.await.expect("request should end");
+        assert_eq!(drain.in_flight(), 0);
+
+        // Requests that start checking after the cut-off end right away.
+        drain.cut_off_requested().await;
+    }
+}
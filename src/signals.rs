@@ -3,6 +3,7 @@ use std::sync::Arc;
 use libc::{SIGINT, SIGTERM};
 use tokio::signal::unix::SignalKind;
 use tokio::sync::Notify;
+use tracing::{error, info};
 
 pub fn listen_signals() -> Arc<Notify> {
     let notify = Arc::new(Notify::new());
@@ -19,22 +20,39 @@ pub fn listen_signals() -> Arc<Notify> {
 
                     notify.notify_waiters();
 
-                    log::info!("shutdown signal received");
+                    info!(signal = signum, "shutdown signal received");
                 });
             }
             Err(e) => {
-                log::error!(
-                    "failed to register signal handler for signal {}: {}",
-                    signum,
-                    e
-                );
+                error!(signal = signum, error = %e, "failed to register signal handler");
                 // Continue with other signals - don't crash the application
                 continue;
             }
         }
     }
 
-    log::info!("waiting for signal");
+    info!("waiting for signal");
 
     notify
 }
+
+/// Calls `on_signal` every time `signum` is received.
+pub fn listen_signal<F>(signum: i32, on_signal: F)
+where
+    F: Fn() + Send + 'static,
+{
+    match tokio::signal::unix::signal(SignalKind::from_raw(signum)) {
+        Ok(mut sig) => {
+            tokio::spawn(async move {
+                while sig.recv().await.is_some() {
+                    info!(signal = signum, "signal received");
+
+                    on_signal();
+                }
+            });
+        }
+        Err(e) => {
+            error!(signal = signum, error = %e, "failed to register signal handler");
+        }
+    }
+}
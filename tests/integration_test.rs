@@ -1,12 +1,18 @@
-use async_nats::Client;
+#![allow(clippy::assertions_on_constants)]
+
 use axum::body::Body;
 use axum::http::{header, Method, Request, StatusCode};
+use axum::Router;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tower::ServiceExt;
 
 use http2::conf::{Conf, NatsConf};
+use http2::nats::SharedClient;
+use http2::reload::SharedLiveConf;
 use http2::routes::build_routes;
+use http2::schemas::RequestSchemas;
+use http2::shutdown::SharedDrain;
 
 #[tokio::test]
 async fn test_full_application_health_check() {
@@ -15,7 +21,7 @@ async fn test_full_application_health_check() {
 
     // Create a mock NATS client (simplified for testing)
     if let Some(mock_client) = create_test_nats_client().await {
-        let app = build_routes(allowed_origins, true, mock_client, None);
+        let app = app(cors_conf(allowed_origins), mock_client);
 
         let request = Request::builder()
             .uri("/api/v1/statuses")
@@ -48,17 +54,19 @@ async fn test_configuration_integration() {
         enable_cors: true,
         nats: NatsConf {
             host: "nats://localhost:4222".to_string(),
+            ..Default::default()
         },
         allowed_origins: vec![
             "http://localhost:3000".to_string(),
             "https://example.com".to_string(),
         ],
         is_debug: true,
+        ..Default::default()
     };
 
     // Test that we can build routes with the configuration
     if let Some(mock_client) = create_test_nats_client().await {
-        let _router = build_routes(test_conf.allowed_origins, test_conf.enable_cors, mock_client, None);
+        let _router = app(test_conf, mock_client);
 
         // Verify the router was created successfully
         assert!(true); // In real tests, you'd verify specific routing behavior
@@ -72,7 +80,7 @@ async fn test_error_response_format() {
     // Test that error responses follow JSON API specification
     let allowed_origins = vec!["http://localhost:3000".to_string()];
     if let Some(mock_client) = create_test_nats_client().await {
-        let app = build_routes(allowed_origins, true, mock_client, None);
+        let app = app(cors_conf(allowed_origins), mock_client);
 
         let request = Request::builder()
             .uri("/nonexistent/endpoint")
@@ -104,7 +112,7 @@ async fn test_cors_functionality() {
         "https://example.com".to_string(),
     ];
     if let Some(mock_client) = create_test_nats_client().await {
-        let app = build_routes(allowed_origins, true, mock_client, None);
+        let app = app(cors_conf(allowed_origins), mock_client);
 
         // Test preflight request
         let request = Request::builder()
@@ -144,7 +152,7 @@ async fn test_request_with_authorization() {
     // Test request handling with authorization header
     let allowed_origins = vec!["http://localhost:3000".to_string()];
     if let Some(mock_client) = create_test_nats_client().await {
-        let app = build_routes(allowed_origins, true, mock_client, None);
+        let app = app(cors_conf(allowed_origins), mock_client);
 
         let request = Request::builder()
             .uri("/api/v1/portfolios")
@@ -175,7 +183,7 @@ async fn test_json_api_content_type() {
     // Test that all responses have correct JSON API content type
     let allowed_origins = vec!["http://localhost:3000".to_string()];
     if let Some(mock_client) = create_test_nats_client().await {
-        let app = build_routes(allowed_origins, true, mock_client, None);
+        let app = app(cors_conf(allowed_origins), mock_client);
 
         // Test one endpoint to verify JSON API content type
         let request = Request::builder()
@@ -198,7 +206,7 @@ async fn test_body_size_enforcement() {
     // Test that request body size limits are enforced
     let allowed_origins = vec!["http://localhost:3000".to_string()];
     if let Some(mock_client) = create_test_nats_client().await {
-        let app = build_routes(allowed_origins, true, mock_client, None);
+        let app = app(cors_conf(allowed_origins), mock_client);
 
         // Create a body that exceeds the 250KB limit
         let oversized_body = "x".repeat(1024 * 260); // 260KB
@@ -219,14 +227,33 @@ async fn test_body_size_enforcement() {
     }
 }
 
+fn app(conf: Conf, nats: SharedClient) -> Router {
+    build_routes(
+        &conf,
+        SharedLiveConf::new(&conf),
+        SharedDrain::default(),
+        nats,
+        None,
+        RequestSchemas::default(),
+    )
+}
+
+fn cors_conf(allowed_origins: Vec<String>) -> Conf {
+    Conf {
+        enable_cors: true,
+        allowed_origins,
+        ..Default::default()
+    }
+}
+
 // Helper function to create a test NATS client
-async fn create_test_nats_client() -> Option<Arc<RwLock<Client>>> {
+async fn create_test_nats_client() -> Option<SharedClient> {
     let options = async_nats::ConnectOptions::new()
         .ping_interval(std::time::Duration::from_secs(10))
         .request_timeout(Some(std::time::Duration::from_secs(10)));
 
     match options.connect("nats://localhost:4222").await {
-        Ok(client) => Some(Arc::new(RwLock::new(client))),
+        Ok(client) => Some(Arc::new(RwLock::new(Some(client)))),
         Err(_) => None, // NATS not available for testing
     }
 }
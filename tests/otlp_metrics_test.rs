@@ -0,0 +1,162 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use opentelemetry_proto::tonic::collector::metrics::v1::metrics_service_server::{
+    MetricsService, MetricsServiceServer,
+};
+use opentelemetry_proto::tonic::collector::metrics::v1::{
+    ExportMetricsServiceRequest, ExportMetricsServiceResponse,
+};
+use opentelemetry_proto::tonic::metrics::v1::{metric, number_data_point, Metric};
+use tokio::sync::mpsc;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+
+use http2::conf::OtlpMetricsConf;
+use http2::otlp_metrics::otlp_pipeline;
+
+/// Records every export request it receives.
+struct StubCollector {
+    exports: mpsc::UnboundedSender<ExportMetricsServiceRequest>,
+}
+
+#[tonic::async_trait]
+impl MetricsService for StubCollector {
+    async fn export(
+        &self,
+        request: tonic::Request<ExportMetricsServiceRequest>,
+    ) -> Result<tonic::Response<ExportMetricsServiceResponse>, tonic::Status> {
+        let _ = self.exports.send(request.into_inner());
+
+        Ok(tonic::Response::new(ExportMetricsServiceResponse {
+            partial_success: None,
+        }))
+    }
+}
+
+async fn start_collector() -> (
+    SocketAddr,
+    mpsc::UnboundedReceiver<ExportMetricsServiceRequest>,
+) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+    let (exports, received) = mpsc::unbounded_channel();
+
+    tokio::spawn(
+        Server::builder()
+            .add_service(MetricsServiceServer::new(StubCollector { exports }))
+            .serve_with_incoming(incoming),
+    );
+
+    (addr, received)
+}
+
+fn otlp_conf(addr: SocketAddr, interval_ms: u64) -> OtlpMetricsConf {
+    OtlpMetricsConf {
+        endpoint: format!("http://{addr}"),
+        interval_ms,
+        timeout_ms: 5000,
+    }
+}
+
+fn find_metric<'a>(request: &'a ExportMetricsServiceRequest, name: &str) -> Option<&'a Metric> {
+    request
+        .resource_metrics
+        .iter()
+        .flat_map(|resource| &resource.scope_metrics)
+        .flat_map(|scope| &scope.metrics)
+        .find(|metric| metric.name == name)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_otlp_metrics_flush_on_shutdown() {
+    let (addr, mut received) = start_collector().await;
+    // Long enough that only the shutdown flush can deliver the data.
+    let (recorder, provider) = otlp_pipeline(&otlp_conf(addr, 3_600_000)).unwrap();
+
+    metrics::with_local_recorder(&recorder, || {
+        for _ in 0..2 {
+            metrics::counter!(
+                "http_requests_total",
+                "method" => "GET",
+                "route" => "/api/v1/plans",
+                "status" => "200",
+            )
+            .increment(1);
+        }
+
+        let connections = metrics::gauge!("http_active_connections");
+        connections.increment(3.0);
+        connections.decrement(1.0);
+
+        metrics::histogram!(
+            "http_request_duration_seconds",
+            "method" => "GET",
+            "route" => "/api/v1/plans",
+        )
+        .record(0.02);
+    });
+
+    tokio::task::spawn_blocking(move || provider.shutdown())
+        .await
+        .unwrap()
+        .expect("shutdown should flush");
+
+    let request = tokio::time::timeout(Duration::from_secs(5), received.recv())
+        .await
+        .expect("collector should receive an export")
+        .unwrap();
+
+    let requests = find_metric(&request, "http_requests_total").expect("counter is exported");
+    let Some(metric::Data::Sum(sum)) = &requests.data else {
+        panic!("counter should be a sum: {requests:?}");
+    };
+    assert_eq!(
+        sum.data_points[0].value,
+        Some(number_data_point::Value::AsInt(2))
+    );
+    assert!(sum.data_points[0]
+        .attributes
+        .iter()
+        .any(|kv| kv.key == "route"));
+
+    let connections = find_metric(&request, "http_active_connections").expect("gauge is exported");
+    let Some(metric::Data::Gauge(gauge)) = &connections.data else {
+        panic!("gauge should be a gauge: {connections:?}");
+    };
+    assert_eq!(
+        gauge.data_points[0].value,
+        Some(number_data_point::Value::AsDouble(2.0))
+    );
+
+    let duration =
+        find_metric(&request, "http_request_duration_seconds").expect("histogram is exported");
+    let Some(metric::Data::Histogram(histogram)) = &duration.data else {
+        panic!("histogram should be a histogram: {duration:?}");
+    };
+    assert_eq!(histogram.data_points[0].count, 1);
+    assert!(histogram.data_points[0].explicit_bounds.contains(&0.025));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_otlp_metrics_export_on_interval() {
+    let (addr, mut received) = start_collector().await;
+    let (recorder, provider) = otlp_pipeline(&otlp_conf(addr, 100)).unwrap();
+
+    metrics::with_local_recorder(&recorder, || {
+        metrics::counter!("nats_requests_total").increment(1);
+    });
+
+    let request = tokio::time::timeout(Duration::from_secs(5), received.recv())
+        .await
+        .expect("collector should receive a periodic export")
+        .unwrap();
+
+    assert!(find_metric(&request, "nats_requests_total").is_some());
+
+    tokio::task::spawn_blocking(move || provider.shutdown())
+        .await
+        .unwrap()
+        .unwrap();
+}
@@ -1,30 +1,413 @@
-use async_nats::Client;
+#![allow(clippy::assertions_on_constants)]
+
 use axum::body::Body;
+use axum::extract::ConnectInfo;
 use axum::http::{header, Method, Request, StatusCode};
+use axum::Router;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use tower::ServiceExt;
 
-use http2::routes::build_routes;
+use http2::admin::AdminAccess;
+use http2::conf::{AdminListenerConf, Conf, RouteConf, RouteMode, Secret};
+use http2::nats::SharedClient;
+use http2::reload::{LiveConf, SharedLiveConf};
+use http2::routes::{build_admin_routes, build_routes};
+use http2::schemas::RequestSchemas;
+use http2::shutdown::SharedDrain;
 
 // Helper function to create a test NATS client
 // Note: This will skip these tests if NATS is not available
-async fn create_test_nats_client() -> Option<Arc<RwLock<Client>>> {
+async fn create_test_nats_client() -> Option<SharedClient> {
     let options = async_nats::ConnectOptions::new()
         .ping_interval(std::time::Duration::from_secs(10))
         .request_timeout(Some(std::time::Duration::from_secs(10)));
 
     match options.connect("nats://localhost:4222").await {
-        Ok(client) => Some(Arc::new(RwLock::new(client))),
+        Ok(client) => Some(Arc::new(RwLock::new(Some(client)))),
         Err(_) => None, // NATS not available for testing
     }
 }
 
+fn app(conf: Conf, nats: SharedClient) -> Router {
+    build_routes(
+        &conf,
+        SharedLiveConf::new(&conf),
+        SharedDrain::default(),
+        nats,
+        None,
+        RequestSchemas::load(&conf).unwrap(),
+    )
+}
+
+fn cors_conf(allowed_origins: Vec<String>) -> Conf {
+    Conf {
+        enable_cors: true,
+        allowed_origins,
+        ..Default::default()
+    }
+}
+
+fn disconnected_nats_client() -> SharedClient {
+    Arc::new(RwLock::new(None))
+}
+
+#[tokio::test]
+async fn test_proxy_unavailable_before_nats_connects() {
+    let allowed_origins = vec!["http://localhost:3000".to_string()];
+    let app = app(cors_conf(allowed_origins), disconnected_nats_client());
+
+    let request = Request::builder()
+        .uri("/api/v1/portfolios")
+        .method(Method::GET)
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let content_type = response.headers().get(header::CONTENT_TYPE).unwrap();
+    assert_eq!(content_type, "application/vnd.api+json");
+}
+
+#[tokio::test]
+async fn test_readiness_before_nats_connects() {
+    let allowed_origins = vec!["http://localhost:3000".to_string()];
+    let app = app(cors_conf(allowed_origins), disconnected_nats_client());
+
+    let request = Request::builder()
+        .uri("/readyz")
+        .method(Method::GET)
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn test_liveness_before_nats_connects() {
+    let allowed_origins = vec!["http://localhost:3000".to_string()];
+    let app = app(cors_conf(allowed_origins), disconnected_nats_client());
+
+    let request = Request::builder()
+        .uri("/api/v1/statuses")
+        .method(Method::GET)
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_readiness_while_draining() {
+    let conf = cors_conf(vec!["http://localhost:3000".to_string()]);
+    let drain = SharedDrain::default();
+    let app = build_routes(
+        &conf,
+        SharedLiveConf::new(&conf),
+        drain.clone(),
+        disconnected_nats_client(),
+        None,
+        RequestSchemas::default(),
+    );
+
+    drain.start_draining();
+
+    let request = Request::builder()
+        .uri("/readyz")
+        .method(Method::GET)
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn test_disabled_route_returns_not_found() {
+    let mut conf = cors_conf(vec!["http://localhost:3000".to_string()]);
+    conf.routes.insert(
+        "/api/v1/plans".to_string(),
+        RouteConf {
+            enabled: false,
+            ..Default::default()
+        },
+    );
+    let app = app(conf, disconnected_nats_client());
+
+    let request = Request::builder()
+        .uri("/api/v1/plans")
+        .method(Method::GET)
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_reload_changes_cors_origins() {
+    let conf = cors_conf(vec!["http://localhost:3000".to_string()]);
+    let live = SharedLiveConf::new(&conf);
+    let app = build_routes(
+        &conf,
+        live.clone(),
+        SharedDrain::default(),
+        disconnected_nats_client(),
+        None,
+        RequestSchemas::default(),
+    );
+
+    let preflight = |origin: &str| {
+        Request::builder()
+            .uri("/api/v1/statuses")
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app
+        .clone()
+        .oneshot(preflight("https://stockwayup.com"))
+        .await
+        .unwrap();
+    assert!(response
+        .headers()
+        .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        .is_none());
+
+    live.replace(LiveConf::from_conf(&cors_conf(vec![
+        "https://stockwayup.com".to_string(),
+    ])));
+
+    let response = app
+        .oneshot(preflight("https://stockwayup.com"))
+        .await
+        .unwrap();
+    assert_eq!(
+        response
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .unwrap(),
+        "https://stockwayup.com"
+    );
+}
+
+#[tokio::test]
+async fn test_cors_wildcard_origin_with_credentials() {
+    let mut conf = cors_conf(vec!["https://*.stockwayup.com".to_string()]);
+    conf.cors.allow_credentials = true;
+    conf.cors.max_age_secs = Some(600);
+
+    let preflight = |origin: &str| {
+        Request::builder()
+            .uri("/api/v1/portfolios/1")
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app(conf.clone(), disconnected_nats_client())
+        .oneshot(preflight("https://app.stockwayup.com"))
+        .await
+        .unwrap();
+
+    let headers = response.headers();
+    assert_eq!(
+        headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+        "https://app.stockwayup.com"
+    );
+    assert_eq!(
+        headers
+            .get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+            .unwrap(),
+        "true"
+    );
+    assert_eq!(headers.get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+    assert!(headers
+        .get(header::ACCESS_CONTROL_ALLOW_METHODS)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .contains("PUT"));
+
+    let response = app(conf, disconnected_nats_client())
+        .oneshot(preflight("https://stockwayup.com.evil.com"))
+        .await
+        .unwrap();
+
+    assert!(response
+        .headers()
+        .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_cors_exposes_configured_headers() {
+    let app = app(
+        cors_conf(vec!["http://localhost:3000".to_string()]),
+        disconnected_nats_client(),
+    );
+
+    let request = Request::builder()
+        .uri("/api/v1/statuses")
+        .method(Method::GET)
+        .header(header::ORIGIN, "http://localhost:3000")
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    let exposed = response
+        .headers()
+        .get(header::ACCESS_CONTROL_EXPOSE_HEADERS)
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    for name in ["x-request-id", "etag", "location"] {
+        assert!(exposed.contains(name), "{exposed}");
+    }
+}
+
+#[tokio::test]
+async fn test_admin_log_filter_disabled_without_token() {
+    let app = app(Conf::default(), disconnected_nats_client());
+
+    let request = Request::builder()
+        .uri("/admin/log-filter")
+        .method(Method::GET)
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_admin_log_filter_requires_token() {
+    let mut conf = Conf::default();
+    conf.admin.token = Some(Secret::Inline("s3cr3t".to_string()));
+    let app = app(conf, disconnected_nats_client());
+
+    let request = Request::builder()
+        .uri("/admin/log-filter")
+        .method(Method::PUT)
+        .header(header::AUTHORIZATION, "Bearer wrong")
+        .header(header::CONTENT_TYPE, "application/vnd.api+json")
+        .body(Body::from(
+            r#"{"data": {"type": "log-filters", "attributes": {"filter": "debug"}}}"#,
+        ))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_request_id_is_echoed() {
+    let app = app(Conf::default(), disconnected_nats_client());
+
+    let request = Request::builder()
+        .uri("/api/v1/statuses")
+        .method(Method::GET)
+        .header("x-request-id", "support-ticket-42")
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers().get("x-request-id").unwrap(),
+        "support-ticket-42"
+    );
+}
+
+#[tokio::test]
+async fn test_invalid_request_id_is_replaced() {
+    let app = app(Conf::default(), disconnected_nats_client());
+
+    let request = Request::builder()
+        .uri("/api/v1/statuses")
+        .method(Method::GET)
+        .header("x-request-id", "x".repeat(200))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    let request_id = response.headers().get("x-request-id").unwrap();
+    assert!(uuid::Uuid::parse_str(request_id.to_str().unwrap()).is_ok());
+}
+
+#[tokio::test]
+async fn test_not_found_error_meta_has_request_id() {
+    let app = app(Conf::default(), disconnected_nats_client());
+
+    let request = Request::builder()
+        .uri("/nonexistent/route")
+        .method(Method::GET)
+        .header("x-request-id", "req-404")
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers().get("x-request-id").unwrap(), "req-404");
+
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(body["errors"][0]["code"], "404");
+    assert_eq!(body["errors"][0]["meta"]["request_id"], "req-404");
+}
+
+#[tokio::test]
+async fn test_proxy_error_meta_has_generated_request_id() {
+    let app = app(Conf::default(), disconnected_nats_client());
+
+    let request = Request::builder()
+        .uri("/api/v1/portfolios")
+        .method(Method::GET)
+        .body(Body::empty())
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let request_id = response.headers().get("x-request-id").unwrap().clone();
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+    assert_eq!(
+        body["errors"][0]["meta"]["request_id"],
+        request_id.to_str().unwrap()
+    );
+}
+
 #[tokio::test]
 async fn test_health_check_route() {
     if let Some(mock_client) = create_test_nats_client().await {
         let allowed_origins = vec!["http://localhost:3000".to_string()];
-        let app = build_routes(allowed_origins, true, mock_client, None);
+        let app = app(cors_conf(allowed_origins), mock_client);
 
         let request = Request::builder()
             .uri("/api/v1/statuses")
@@ -47,7 +430,7 @@ async fn test_health_check_route() {
 async fn test_not_found_route() {
     if let Some(mock_client) = create_test_nats_client().await {
         let allowed_origins = vec!["http://localhost:3000".to_string()];
-        let app = build_routes(allowed_origins, true, mock_client, None);
+        let app = app(cors_conf(allowed_origins), mock_client);
 
         let request = Request::builder()
             .uri("/nonexistent/route")
@@ -70,7 +453,7 @@ async fn test_not_found_route() {
 async fn test_cors_headers() {
     if let Some(mock_client) = create_test_nats_client().await {
         let allowed_origins = vec!["http://localhost:3000".to_string()];
-        let app = build_routes(allowed_origins, true, mock_client, None);
+        let app = app(cors_conf(allowed_origins), mock_client);
 
         let request = Request::builder()
             .uri("/api/v1/statuses")
@@ -99,7 +482,7 @@ async fn test_cors_headers() {
 async fn test_request_body_size_limit() {
     let allowed_origins = vec!["http://localhost:3000".to_string()];
     if let Some(mock_client) = create_test_nats_client().await {
-        let app = build_routes(allowed_origins, true, mock_client, None);
+        let app = app(cors_conf(allowed_origins), mock_client);
 
         // Create a body that's larger than the limit (250KB)
         let large_body = "x".repeat(1024 * 300); // 300KB
@@ -120,12 +503,235 @@ async fn test_request_body_size_limit() {
     }
 }
 
+#[tokio::test]
+async fn test_route_body_limit_is_a_json_api_error() {
+    let mut conf = Conf::default();
+    conf.routes.insert(
+        "/api/v1/sessions".to_string(),
+        RouteConf {
+            body_limit_bytes: Some(1024),
+            ..Default::default()
+        },
+    );
+    let app = app(conf, disconnected_nats_client());
+
+    let declared = Request::builder()
+        .uri("/api/v1/sessions")
+        .method(Method::POST)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from("x".repeat(2048)))
+        .unwrap();
+    let chunked = Request::builder()
+        .uri("/api/v1/sessions")
+        .method(Method::POST)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::wrap_stream(futures::stream::iter(
+            (0..4).map(|_| Ok::<_, std::io::Error>("x".repeat(512))),
+        )))
+        .unwrap();
+
+    for request in [declared, chunked] {
+        let response = app.clone().oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["errors"][0]["code"], "413");
+        assert_eq!(
+            body["errors"][0]["detail"],
+            "The request body exceeds the limit of 1024 bytes."
+        );
+    }
+
+    // Within the limit the request gets as far as NATS.
+    let request = Request::builder()
+        .uri("/api/v1/sessions")
+        .method(Method::POST)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from("x".repeat(512)))
+        .unwrap();
+
+    let response = app.oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+fn import_request(content_type: &str) -> Request<Body> {
+    Request::builder()
+        .uri("/api/v1/portfolios/7/imports")
+        .method(Method::POST)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(header::AUTHORIZATION, "Bearer user-token")
+        .body(Body::from(
+            "--b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.csv\"\r\n\r\nx\r\n--b--\r\n",
+        ))
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_imports_need_a_bucket() {
+    let app = app(Conf::default(), disconnected_nats_client());
+
+    let response = app
+        .oneshot(import_request("multipart/form-data; boundary=b"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_imports_take_multipart() {
+    let mut conf = Conf::default();
+    conf.request_body.object_store_bucket = Some("uploads".to_string());
+    conf.json_api.strict_media_type = true;
+    let app = app(conf, disconnected_nats_client());
+
+    let response = app
+        .clone()
+        .oneshot(import_request("text/csv"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    // Past the media type checks the upload waits for NATS.
+    let response = app
+        .clone()
+        .oneshot(import_request("multipart/form-data; boundary=b"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/portfolios/7/imports/not-an-import")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_imports_need_a_bearer_token() {
+    let mut conf = Conf::default();
+    conf.request_body.object_store_bucket = Some("uploads".to_string());
+    let app = app(conf, disconnected_nats_client());
+
+    let mut request = import_request("multipart/form-data; boundary=b");
+    request.headers_mut().remove(header::AUTHORIZATION);
+
+    let response = app.oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "application/vnd.api+json"
+    );
+}
+
+fn job_request() -> Request<Body> {
+    Request::builder()
+        .uri("/api/v1/jobs/0b9e2d6c-3f5a-4c1e-9d2b-7a8f6e5d4c3b")
+        .header(header::AUTHORIZATION, "Bearer user-token")
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_jobs_need_an_async_route() {
+    let app = app(Conf::default(), disconnected_nats_client());
+
+    let response = app.oneshot(job_request()).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_jobs_wait_for_nats() {
+    let mut conf = Conf::default();
+    conf.routes.insert(
+        "/api/v1/portfolios/:pid".to_string(),
+        RouteConf {
+            mode: RouteMode::Async,
+            ..Default::default()
+        },
+    );
+    let app = app(conf, disconnected_nats_client());
+
+    let response = app.clone().oneshot(job_request()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    // Without a bearer token no job can be read, whether it exists or not.
+    let mut request = job_request();
+    request.headers_mut().remove(header::AUTHORIZATION);
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/jobs/not-a-job")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/portfolios/7")
+                .method(Method::DELETE)
+                .header(header::AUTHORIZATION, "Bearer user-token")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn test_async_requests_need_a_bearer_token() {
+    let mut conf = Conf::default();
+    conf.routes.insert(
+        "/api/v1/portfolios/:pid".to_string(),
+        RouteConf {
+            mode: RouteMode::Async,
+            ..Default::default()
+        },
+    );
+    let app = app(conf, disconnected_nats_client());
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/portfolios/7")
+                .method(Method::DELETE)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "application/vnd.api+json"
+    );
+}
+
 #[tokio::test]
 async fn test_api_v1_routes_exist() {
     let allowed_origins = vec!["http://localhost:3000".to_string()];
     if let Some(mock_client) = create_test_nats_client().await {
         // Just verify that build_routes doesn't panic with various route configurations
-        let _router = build_routes(allowed_origins, true, mock_client, None);
+        let _router = app(cors_conf(allowed_origins), mock_client);
 
         // Test that common API routes would be registered
         // In a complete test, you'd verify each route exists and has correct methods
@@ -139,7 +745,7 @@ async fn test_api_v1_routes_exist() {
 async fn test_route_methods() {
     let allowed_origins = vec!["http://localhost:3000".to_string()];
     if let Some(mock_client) = create_test_nats_client().await {
-        let app = build_routes(allowed_origins, true, mock_client, None);
+        let app = app(cors_conf(allowed_origins), mock_client);
 
         // Test that GET is allowed on portfolios route
         let request = Request::builder()
@@ -167,7 +773,7 @@ async fn test_multiple_allowed_origins() {
     ];
 
     if let Some(mock_client) = create_test_nats_client().await {
-        let _router = build_routes(allowed_origins, true, mock_client, None);
+        let _router = app(cors_conf(allowed_origins), mock_client);
 
         // Verify router builds with multiple origins
         assert!(true); // In real tests, you'd verify CORS behavior for each origin
@@ -175,3 +781,538 @@ async fn test_multiple_allowed_origins() {
         println!("Skipping test: NATS server not available");
     }
 }
+
+#[derive(Clone, Default)]
+struct LogBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl std::io::Write for LogBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Captures events and closed spans with their recorded fields until the guard is dropped.
+fn capture_logs() -> (LogBuffer, tracing::subscriber::DefaultGuard) {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let buffer = LogBuffer::default();
+    let writer = buffer.clone();
+    let subscriber = tracing_subscriber::registry()
+        .with(http2::log_format::otel_layer())
+        .with(http2::log_format::CorrelationLayer)
+        .with(
+            tracing_subscriber::fmt::layer()
+                .event_format(http2::log_format::JsonFormat)
+                .with_writer(move || writer.clone()),
+        )
+        // Spans are written too, so their recorded fields are checked as well.
+        .with(
+            tracing_subscriber::fmt::layer()
+                .json()
+                .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+                .with_writer({
+                    let writer = buffer.clone();
+                    move || writer.clone()
+                }),
+        );
+
+    (buffer, tracing::subscriber::set_default(subscriber))
+}
+
+#[tokio::test]
+async fn test_secrets_never_reach_logs_or_spans() {
+    const TOKEN: &str = "eyJhbGciOiJIUzI1NiJ9.dG9rZW4.c2lnbmF0dXJl";
+    const PASSWORD: &str = "hunter2-password";
+    const CODE: &str = "483920";
+
+    let (buffer, _guard) = capture_logs();
+
+    let app = app(Conf::default(), disconnected_nats_client());
+
+    let requests = vec![
+        Request::builder()
+            .uri("/api/v1/sessions")
+            .method(Method::POST)
+            .header(header::CONTENT_TYPE, "application/vnd.api+json")
+            .body(Body::from(format!(
+                r#"{{"data":{{"type":"sessions","attributes":{{"email":"a@b.c","password":"{PASSWORD}"}}}}}}"#
+            )))
+            .unwrap(),
+        Request::builder()
+            .uri(format!("/api/v1/confirmation-codes/{CODE}"))
+            .method(Method::POST)
+            .header(header::AUTHORIZATION, format!("Bearer {TOKEN}"))
+            .body(Body::empty())
+            .unwrap(),
+        Request::builder()
+            .uri(format!("/api/v1/portfolios?access_token={TOKEN}"))
+            .method(Method::GET)
+            .header(header::AUTHORIZATION, format!("Bearer {TOKEN}"))
+            .body(Body::empty())
+            .unwrap(),
+    ];
+
+    for request in requests {
+        app.clone().oneshot(request).await.unwrap();
+    }
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+
+    assert!(output.contains("request payload"), "{output}");
+    assert!(output.contains("[REDACTED]"), "{output}");
+    assert!(!output.contains(TOKEN), "{output}");
+    assert!(!output.contains(PASSWORD), "{output}");
+    assert!(!output.contains(CODE), "{output}");
+}
+
+#[tokio::test]
+async fn test_refresh_token_never_reaches_access_log_or_spans() {
+    const REFRESH_TOKEN: &str = "rt-5f1c0e9a7b3d4c2e8f6a";
+
+    let (buffer, _guard) = capture_logs();
+
+    let app = app(Conf::default(), disconnected_nats_client());
+
+    app.oneshot(
+        Request::builder()
+            .uri(format!("/api/v1/refresh-tokens/{REFRESH_TOKEN}"))
+            .method(Method::DELETE)
+            .body(Body::empty())
+            .unwrap(),
+    )
+    .await
+    .unwrap();
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+
+    assert!(output.contains("access"), "{output}");
+    assert!(
+        output.contains("/api/v1/refresh-tokens/[REDACTED]"),
+        "{output}"
+    );
+    assert!(!output.contains(REFRESH_TOKEN), "{output}");
+}
+
+#[tokio::test]
+async fn test_access_log_counts_streamed_bodies() {
+    let (buffer, _guard) = capture_logs();
+
+    let app = app(Conf::default(), disconnected_nats_client());
+
+    let body = Body::wrap_stream(futures::stream::iter(
+        [r#"{"data":{"type":"portfolios","#, r#""attributes":{}}}"#]
+            .map(Ok::<_, std::io::Error>),
+    ));
+    let resp = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/portfolios")
+                .method(Method::POST)
+                .header(header::CONTENT_TYPE, "application/vnd.api+json")
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let sent = hyper::body::to_bytes(resp.into_body()).await.unwrap();
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+
+    assert!(output.contains(r#""bytes_in":46"#), "{output}");
+    assert!(
+        output.contains(&format!(r#""bytes_out":{}"#, sent.len())),
+        "{output}"
+    );
+}
+
+fn admin_listener_conf(token: Option<&str>, allowed_ips: &[&str]) -> Conf {
+    let mut conf = Conf::default();
+    conf.admin.listener = Some(AdminListenerConf {
+        bind_address: "127.0.0.1".parse().unwrap(),
+        port: 9090,
+        token: token.map(|token| Secret::Inline(token.to_string())),
+        allowed_ips: allowed_ips.iter().map(|ip| ip.to_string()).collect(),
+    });
+    conf
+}
+
+fn admin_app(conf: &Conf) -> Router {
+    let access = AdminAccess::from_conf(conf.admin.listener.as_ref().unwrap()).unwrap();
+
+    build_admin_routes(
+        conf,
+        access,
+        SharedDrain::default(),
+        disconnected_nats_client(),
+        None,
+    )
+}
+
+fn admin_request(uri: &str, client: &str) -> Request<Body> {
+    let mut request = Request::builder()
+        .uri(uri)
+        .method(Method::GET)
+        .body(Body::empty())
+        .unwrap();
+
+    request
+        .extensions_mut()
+        .insert(ConnectInfo(client.parse::<SocketAddr>().unwrap()));
+
+    request
+}
+
+#[tokio::test]
+async fn test_admin_listener_moves_probes_off_the_public_port() {
+    let conf = admin_listener_conf(None, &[]);
+
+    for uri in ["/readyz", "/admin/log-filter"] {
+        let response = app(conf.clone(), disconnected_nats_client())
+            .oneshot(admin_request(uri, "10.0.0.1:40000"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+    }
+
+    let response = admin_app(&conf)
+        .oneshot(admin_request("/readyz", "10.0.0.1:40000"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let response = admin_app(&conf)
+        .oneshot(admin_request("/api/v1/statuses", "10.0.0.1:40000"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_admin_listener_allowlist() {
+    let conf = admin_listener_conf(None, &["127.0.0.0/8"]);
+
+    let response = admin_app(&conf)
+        .oneshot(admin_request("/readyz", "10.0.0.1:40000"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(response.headers().contains_key("x-request-id"));
+
+    let response = admin_app(&conf)
+        .oneshot(admin_request("/readyz", "127.0.0.1:40000"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn test_admin_listener_token() {
+    let conf = admin_listener_conf(Some("s3cr3t"), &[]);
+
+    let response = admin_app(&conf)
+        .oneshot(admin_request("/readyz", "10.0.0.1:40000"))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let mut request = admin_request("/readyz", "10.0.0.1:40000");
+    request
+        .headers_mut()
+        .insert(header::AUTHORIZATION, "Bearer s3cr3t".parse().unwrap());
+
+    let response = admin_app(&conf).oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    // The header is parsed like on the `/admin` endpoints, which need the `Bearer` scheme.
+    let mut request = admin_request("/readyz", "10.0.0.1:40000");
+    request
+        .headers_mut()
+        .insert(header::AUTHORIZATION, "Basic s3cr3t".parse().unwrap());
+
+    let response = admin_app(&conf).oneshot(request).await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_security_headers_on_every_response() {
+    let conf = cors_conf(vec!["http://localhost:3000".to_string()]);
+
+    let requests = [
+        Request::builder().uri("/nope").body(Body::empty()).unwrap(),
+        Request::builder()
+            .uri("/api/v1/plans")
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, "http://localhost:3000")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "GET")
+            .body(Body::empty())
+            .unwrap(),
+    ];
+
+    for request in requests {
+        let uri = request.uri().clone();
+        let response = app(conf.clone(), disconnected_nats_client())
+            .oneshot(request)
+            .await
+            .unwrap();
+
+        let headers = response.headers();
+        assert_eq!(headers["x-content-type-options"], "nosniff", "{uri}");
+        assert!(
+            headers.contains_key(header::CONTENT_SECURITY_POLICY),
+            "{uri}"
+        );
+        assert!(
+            headers.contains_key(header::STRICT_TRANSPORT_SECURITY),
+            "{uri}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_strict_media_type_errors() {
+    let mut conf = Conf::default();
+    conf.json_api.strict_media_type = true;
+
+    let requests = [
+        (
+            Request::builder()
+                .uri("/api/v1/portfolios")
+                .method(Method::POST)
+                .header(
+                    header::CONTENT_TYPE,
+                    "application/vnd.api+json; charset=utf-8",
+                )
+                .body(Body::from("{}"))
+                .unwrap(),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "Content-Type",
+        ),
+        (
+            Request::builder()
+                .uri("/api/v1/plans")
+                .header(header::ACCEPT, "application/json")
+                .body(Body::empty())
+                .unwrap(),
+            StatusCode::NOT_ACCEPTABLE,
+            "Accept",
+        ),
+    ];
+
+    for (request, status, source) in requests {
+        let response = app(conf.clone(), disconnected_nats_client())
+            .oneshot(request)
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), status);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "application/vnd.api+json"
+        );
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["errors"][0]["code"], status.as_str());
+        assert_eq!(body["errors"][0]["source"]["header"], source);
+    }
+
+    // Probes are outside the JSON:API routes.
+    let response = app(conf, disconnected_nats_client())
+        .oneshot(
+            Request::builder()
+                .uri("/readyz")
+                .header(header::ACCEPT, "text/plain")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_ne!(response.status(), StatusCode::NOT_ACCEPTABLE);
+}
+
+#[tokio::test]
+async fn test_request_schema_rejects_before_nats() {
+    let schema = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(schema.path(), r#"{"type": "object", "required": ["data"]}"#).unwrap();
+
+    let mut conf = Conf::default();
+    conf.routes.insert(
+        "/api/v1/portfolios".to_string(),
+        RouteConf {
+            request_schema: Some(schema.path().display().to_string()),
+            ..Default::default()
+        },
+    );
+
+    let response = app(conf, disconnected_nats_client())
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/portfolios")
+                .method(Method::POST)
+                .header(header::CONTENT_TYPE, "application/vnd.api+json")
+                .body(Body::from("{}"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["errors"][0]["source"]["pointer"], "/data");
+}
+
+#[tokio::test]
+async fn test_path_params_are_checked_before_nats() {
+    let cases = [
+        ("/api/v1/portfolios/abc", StatusCode::NOT_FOUND, "pid"),
+        (
+            "/api/v1/users/%2E%2E%2F%2E%2E%2Fx",
+            StatusCode::BAD_REQUEST,
+            "uid",
+        ),
+    ];
+
+    for (uri, status, parameter) in cases {
+        let response = app(Conf::default(), disconnected_nats_client())
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), status, "{uri}");
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["errors"][0]["source"]["parameter"], parameter, "{uri}");
+    }
+
+    // Valid values get as far as the NATS client.
+    let response = app(Conf::default(), disconnected_nats_client())
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/portfolios/007/securities/BRK.B/transactions")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+}
+
+#[tokio::test]
+async fn test_refresh_tokens_are_opaque() {
+    let jwt = format!(
+        "eyJhbGciOiJIUzI1NiIsInR5cCI6IkpXVCJ9.{}.SflKxwRJSMeKKF2QT4fwpMeJf36POk6yJV_adQssw5c",
+        "eyJzdWIiOiI0MiJ9".repeat(20)
+    );
+
+    for token in [jwt.as_str(), "dGVzdA%2B%2Fx%3D%3D", "dGVzdA+x=="] {
+        let response = app(Conf::default(), disconnected_nats_client())
+            .oneshot(
+                Request::builder()
+                    .uri(format!("/api/v1/refresh-tokens/{token}"))
+                    .method(Method::DELETE)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        // Past the parameter check the request waits for NATS.
+        assert_eq!(
+            response.status(),
+            StatusCode::SERVICE_UNAVAILABLE,
+            "{token}"
+        );
+    }
+
+    // The confirmation code route still takes slugs only.
+    let response = app(Conf::default(), disconnected_nats_client())
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/confirmation-codes/a.b")
+                .method(Method::POST)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_method_not_allowed_is_a_json_api_error() {
+    let response = app(Conf::default(), disconnected_nats_client())
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/portfolios/1")
+                .method(Method::PUT)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(
+        response.headers()[header::ALLOW],
+        "GET, HEAD, PATCH, DELETE, OPTIONS"
+    );
+    assert_eq!(
+        response.headers()[header::CONTENT_TYPE],
+        "application/vnd.api+json"
+    );
+
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["errors"][0]["code"], "405");
+}
+
+#[tokio::test]
+async fn test_options_and_head_without_cors() {
+    let response = app(Conf::default(), disconnected_nats_client())
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/portfolios")
+                .method(Method::OPTIONS)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(
+        response.headers()[header::ALLOW],
+        "GET, HEAD, POST, OPTIONS"
+    );
+
+    let response = app(Conf::default(), disconnected_nats_client())
+        .oneshot(
+            Request::builder()
+                .uri("/api/v1/statuses")
+                .method(Method::HEAD)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_ne!(response.headers()[header::CONTENT_LENGTH], "0");
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    assert!(body.is_empty());
+}